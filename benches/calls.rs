@@ -0,0 +1,48 @@
+//! The cost of recursive calls in programs of different sizes. A call used to copy every function
+//! of the program, so that it took longer the more functions there were; it should not any more.
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use toysrust::{interpreter::Interpreter, parser};
+
+/// A program computing `fib(20)` recursively, along with `padding` functions it never calls.
+fn program(padding: usize) -> String {
+    let mut source = String::from(
+        "
+        define fib(n) {
+            if (n < 2) n; else fib(n - 1) + fib(n - 2);
+        }
+
+        define main() = fib(20);
+        ",
+    );
+    for index in 0..padding {
+        source.push_str(&format!(
+            "define unused{}(a, b) {{ c = a * b; while (c > 0) c -= 1; a + b + c; }}\n",
+            index
+        ));
+    }
+
+    source
+}
+
+fn recursion(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fib(20)");
+    for padding in [0, 100, 1000] {
+        let source = program(padding);
+        group.bench_with_input(
+            BenchmarkId::new("unused functions", padding),
+            &source,
+            |b, source| {
+                b.iter_batched(
+                    || parser::parse(source).unwrap(),
+                    |program| Interpreter::new().call_main(program).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, recursion);
+criterion_main!(benches);
@@ -8,6 +8,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let (_, program) = parser::program(contents).unwrap();
     let mut interpreter = interpreter::Interpreter::default();
+    // 21! からは i64 に収まらない
+    interpreter.set_arithmetic_mode(interpreter::ArithmeticMode::BigInteger);
     interpreter.call_main(program)?;
 
     Ok(())
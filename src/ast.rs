@@ -1,9 +1,17 @@
+mod display;
+
+use std::rc::Rc;
+
+use crate::value::IntegerType;
+use num_bigint::BigInt;
+
 #[derive(Clone)]
 pub enum Operator {
     Add,
     Subtract,
     Multiply,
     Divide,
+    Remainder,
     LessThan,
     LessOrEqual,
     GreaterThan,
@@ -12,6 +20,54 @@ pub enum Operator {
     NotEqual,
 }
 
+/// A piece of a string literal with interpolations in it.
+#[derive(Clone)]
+pub enum FormatPart {
+    Literal(String),
+    /// `${expression:spec}`, or `${expression}` with the default `spec`.
+    Interpolation {
+        expression: Expression,
+        spec: FormatSpec,
+    },
+}
+
+/// How an interpolation formats its value, written `[[fill]align][0][width][radix]` after a `:`
+/// as in `${n:>8}`, `${n:08x}` or `${name:*^12}`.
+#[derive(Clone, Default)]
+pub struct FormatSpec {
+    /// What pads the value up to `width`, a space if `None`.
+    pub fill: Option<char>,
+    /// Where the value goes within `width`. By default, integers are aligned right and the other
+    /// values left.
+    pub align: Option<Alignment>,
+    /// Whether an integer is padded with zeros after its sign.
+    pub zero: bool,
+    pub width: usize,
+    /// The radix an integer is written in, 10 if `None`.
+    pub radix: Option<Radix>,
+}
+
+#[derive(Clone, Copy)]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Clone, Copy)]
+pub enum Radix {
+    Binary,
+    Octal,
+    LowerHex,
+    UpperHex,
+}
+
+/// A place which can be updated in place by a compound assignment.
+#[derive(Clone)]
+pub enum AssignmentTarget {
+    Variable { name: String },
+}
+
 #[derive(Clone)]
 pub enum Expression {
     Binary {
@@ -22,32 +78,154 @@ pub enum Expression {
     IntegerLiteral {
         value: i64,
     },
+    /// An integer literal beyond the range of `i64`, which only evaluates in the big integer
+    /// arithmetic mode.
+    BigIntegerLiteral {
+        value: BigInt,
+    },
+    /// An integer literal with the suffix of its type, as in `255u8`.
+    SizedIntegerLiteral {
+        value: i64,
+        ty: IntegerType,
+    },
+    StringLiteral {
+        value: String,
+    },
+    /// A string literal with `${expression}` or `${expression:spec}` in it, which evaluates to
+    /// the string with the values of the expressions put in.
+    Format {
+        parts: Vec<FormatPart>,
+    },
     Identifier {
         name: String,
     },
+    Tuple {
+        elements: Vec<Expression>,
+    },
+    TupleIndex {
+        tuple: Box<Expression>,
+        index: usize,
+    },
     Assignment {
         name: String,
         expression: Box<Expression>,
     },
+    /// `let name = expression;`, binding `name` immutably in the innermost scope.
+    Let {
+        name: String,
+        expression: Box<Expression>,
+    },
+    CompoundAssignment {
+        operator: Operator,
+        target: AssignmentTarget,
+        expression: Box<Expression>,
+    },
+    /// `(a, b) = expression;`. `pattern` only consists of bindings, wildcards and tuples.
+    Destructuring {
+        pattern: Pattern,
+        expression: Box<Expression>,
+    },
     Block {
         elements: Vec<Expression>,
     },
+    /// `define` inside a block. The function is visible only in that block, and its definition is
+    /// shared by the copies of the block and the scopes it is defined in.
+    FunctionDefinition {
+        function: Rc<Function>,
+    },
     While {
         condition: Box<Expression>,
         body: Box<Expression>,
     },
+    For {
+        variable: String,
+        start: Box<Expression>,
+        end: Box<Expression>,
+        inclusive: bool,
+        step: Option<Box<Expression>>,
+        body: Box<Expression>,
+    },
+    /// `for (variable in iterable) body` over the elements of a tuple or the values of a generator.
+    ForEach {
+        variable: String,
+        iterable: Box<Expression>,
+        body: Box<Expression>,
+    },
     If {
         condition: Box<Expression>,
         then_clause: Box<Expression>,
         else_clause: Option<Box<Expression>>,
     },
+    Match {
+        scrutinee: Box<Expression>,
+        arms: Vec<MatchArm>,
+    },
+    /// `name(args, named_args)`. Named arguments come after the positional ones.
     FunctionCall {
         name: String,
         args: Vec<Expression>,
+        named_args: Vec<(String, Expression)>,
     },
-    PrintLn {
+    /// `print(args);` or `println(args);`, which write the values of `args` separated by spaces.
+    Print {
+        args: Vec<Expression>,
+        newline: bool,
+    },
+    Return {
+        expression: Box<Expression>,
+    },
+    Throw {
         expression: Box<Expression>,
     },
+    /// `yield expression;`. A function whose body contains it is a generator.
+    Yield {
+        expression: Box<Expression>,
+    },
+    /// `try body catch (variable) { ... } finally { ... }`. At least one of the clauses is present.
+    Try {
+        body: Box<Expression>,
+        catch_clause: Option<CatchClause>,
+        finally_clause: Option<Box<Expression>>,
+    },
+    Break,
+    Continue,
+}
+
+#[derive(Clone)]
+pub enum Pattern {
+    Wildcard,
+    Literal {
+        value: i64,
+    },
+    Range {
+        start: i64,
+        end: i64,
+        inclusive: bool,
+    },
+    Binding {
+        name: String,
+    },
+    Variant {
+        name: String,
+        fields: Vec<Pattern>,
+    },
+    Tuple {
+        elements: Vec<Pattern>,
+    },
+}
+
+#[derive(Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard: Option<Expression>,
+    pub body: Expression,
+}
+
+/// `catch (variable) body`. `variable` is bound to the caught value only in `body`.
+#[derive(Clone)]
+pub struct CatchClause {
+    pub variable: String,
+    pub body: Box<Expression>,
 }
 
 pub fn add(lhs: Expression, rhs: Expression) -> Expression {
@@ -82,6 +260,14 @@ pub fn divide(lhs: Expression, rhs: Expression) -> Expression {
     }
 }
 
+pub fn remainder(lhs: Expression, rhs: Expression) -> Expression {
+    Expression::Binary {
+        operator: Operator::Remainder,
+        lhs: Box::new(lhs),
+        rhs: Box::new(rhs),
+    }
+}
+
 pub fn less_than(lhs: Expression, rhs: Expression) -> Expression {
     Expression::Binary {
         operator: Operator::LessThan,
@@ -134,10 +320,35 @@ pub fn integer(value: i64) -> Expression {
     Expression::IntegerLiteral { value }
 }
 
+pub fn big_integer(value: BigInt) -> Expression {
+    Expression::BigIntegerLiteral { value }
+}
+
+pub fn sized_integer(value: i64, ty: IntegerType) -> Expression {
+    Expression::SizedIntegerLiteral { value, ty }
+}
+
+pub fn string(value: &str) -> Expression {
+    Expression::StringLiteral {
+        value: value.to_string(),
+    }
+}
+
 pub fn identifier(name: &str) -> Expression {
     Expression::Identifier { name: name.into() }
 }
 
+pub fn tuple(elements: Vec<Expression>) -> Expression {
+    Expression::Tuple { elements }
+}
+
+pub fn tuple_index(tuple: Expression, index: usize) -> Expression {
+    Expression::TupleIndex {
+        tuple: Box::new(tuple),
+        index,
+    }
+}
+
 pub fn assignment(name: &str, expression: Expression) -> Expression {
     Expression::Assignment {
         name: name.into(),
@@ -145,6 +356,46 @@ pub fn assignment(name: &str, expression: Expression) -> Expression {
     }
 }
 
+pub fn ast_let(name: &str, expression: Expression) -> Expression {
+    Expression::Let {
+        name: name.into(),
+        expression: Box::new(expression),
+    }
+}
+
+pub fn destructuring(pattern: Pattern, expression: Expression) -> Expression {
+    Expression::Destructuring {
+        pattern,
+        expression: Box::new(expression),
+    }
+}
+
+pub fn variable_target(name: &str) -> AssignmentTarget {
+    AssignmentTarget::Variable { name: name.into() }
+}
+
+pub fn compound_assignment(
+    operator: Operator,
+    target: AssignmentTarget,
+    expression: Expression,
+) -> Expression {
+    Expression::CompoundAssignment {
+        operator,
+        target,
+        expression: Box::new(expression),
+    }
+}
+
+/// `target++` is sugar for `target += 1`.
+pub fn increment(target: AssignmentTarget) -> Expression {
+    compound_assignment(Operator::Add, target, integer(1))
+}
+
+/// `target--` is sugar for `target -= 1`.
+pub fn decrement(target: AssignmentTarget) -> Expression {
+    compound_assignment(Operator::Subtract, target, integer(1))
+}
+
 pub fn block(elements: Vec<Expression>) -> Expression {
     Expression::Block { elements }
 }
@@ -156,6 +407,32 @@ pub fn ast_while(condition: Expression, body: Expression) -> Expression {
     }
 }
 
+pub fn ast_for(
+    variable: &str,
+    start: Expression,
+    end: Expression,
+    inclusive: bool,
+    step: Option<Expression>,
+    body: Expression,
+) -> Expression {
+    Expression::For {
+        variable: variable.into(),
+        start: Box::new(start),
+        end: Box::new(end),
+        inclusive,
+        step: step.map(Box::new),
+        body: Box::new(body),
+    }
+}
+
+pub fn ast_for_each(variable: &str, iterable: Expression, body: Expression) -> Expression {
+    Expression::ForEach {
+        variable: variable.into(),
+        iterable: Box::new(iterable),
+        body: Box::new(body),
+    }
+}
+
 pub fn ast_if(
     condition: Expression,
     then_clause: Expression,
@@ -168,42 +445,552 @@ pub fn ast_if(
     }
 }
 
+pub fn ast_match(scrutinee: Expression, arms: Vec<MatchArm>) -> Expression {
+    Expression::Match {
+        scrutinee: Box::new(scrutinee),
+        arms,
+    }
+}
+
+pub fn match_arm(pattern: Pattern, guard: Option<Expression>, body: Expression) -> MatchArm {
+    MatchArm {
+        pattern,
+        guard,
+        body,
+    }
+}
+
+pub fn wildcard_pattern() -> Pattern {
+    Pattern::Wildcard
+}
+
+pub fn literal_pattern(value: i64) -> Pattern {
+    Pattern::Literal { value }
+}
+
+pub fn range_pattern(start: i64, end: i64, inclusive: bool) -> Pattern {
+    Pattern::Range {
+        start,
+        end,
+        inclusive,
+    }
+}
+
+pub fn binding_pattern(name: &str) -> Pattern {
+    Pattern::Binding { name: name.into() }
+}
+
+pub fn variant_pattern(name: &str, fields: Vec<Pattern>) -> Pattern {
+    Pattern::Variant {
+        name: name.into(),
+        fields,
+    }
+}
+
+pub fn tuple_pattern(elements: Vec<Pattern>) -> Pattern {
+    Pattern::Tuple { elements }
+}
+
+#[cfg(test)]
 pub fn call(name: &str, args: Vec<Expression>) -> Expression {
+    call_with_named(name, args, vec![])
+}
+
+pub fn call_with_named(
+    name: &str,
+    args: Vec<Expression>,
+    named_args: Vec<(&str, Expression)>,
+) -> Expression {
     Expression::FunctionCall {
         name: name.to_string(),
         args,
+        named_args: named_args
+            .into_iter()
+            .map(|(name, expression)| (name.to_string(), expression))
+            .collect(),
+    }
+}
+
+pub fn ast_print(args: Vec<Expression>) -> Expression {
+    Expression::Print {
+        args,
+        newline: false,
+    }
+}
+
+pub fn ast_println(args: Vec<Expression>) -> Expression {
+    Expression::Print {
+        args,
+        newline: true,
+    }
+}
+
+pub fn format(parts: Vec<FormatPart>) -> Expression {
+    Expression::Format { parts }
+}
+
+pub fn interpolation(expression: Expression, spec: FormatSpec) -> FormatPart {
+    FormatPart::Interpolation { expression, spec }
+}
+
+pub fn ast_return(expression: Expression) -> Expression {
+    Expression::Return {
+        expression: Box::new(expression),
+    }
+}
+
+pub fn ast_throw(expression: Expression) -> Expression {
+    Expression::Throw {
+        expression: Box::new(expression),
     }
 }
 
-pub fn ast_println(expression: Expression) -> Expression {
-    Expression::PrintLn {
+pub fn ast_yield(expression: Expression) -> Expression {
+    Expression::Yield {
         expression: Box::new(expression),
     }
 }
 
+pub fn ast_try(
+    body: Expression,
+    catch_clause: Option<(&str, Expression)>,
+    finally_clause: Option<Expression>,
+) -> Expression {
+    Expression::Try {
+        body: Box::new(body),
+        catch_clause: catch_clause.map(|(variable, body)| CatchClause {
+            variable: variable.to_string(),
+            body: Box::new(body),
+        }),
+        finally_clause: finally_clause.map(Box::new),
+    }
+}
+
+pub fn ast_break() -> Expression {
+    Expression::Break
+}
+
+pub fn ast_continue() -> Expression {
+    Expression::Continue
+}
+
 #[derive(Clone)]
 pub struct Function {
     pub name: String,
-    pub args: Vec<String>,
+    pub args: Vec<Parameter>,
+    pub contract: Contract,
     pub body: Expression,
+    /// Whether `body` contains `yield`, so that a call makes a generator instead of running it.
+    pub generator: bool,
+}
+
+impl Expression {
+    /// Whether `yield` appears in this expression, leaving out the bodies of nested functions.
+    pub fn contains_yield(&self) -> bool {
+        match self {
+            Expression::Yield { .. } => true,
+            Expression::IntegerLiteral { .. }
+            | Expression::BigIntegerLiteral { .. }
+            | Expression::SizedIntegerLiteral { .. }
+            | Expression::StringLiteral { .. }
+            | Expression::Identifier { .. }
+            | Expression::FunctionDefinition { .. }
+            | Expression::Break
+            | Expression::Continue => false,
+            Expression::Binary { lhs, rhs, .. } => lhs.contains_yield() || rhs.contains_yield(),
+            Expression::Tuple { elements } | Expression::Block { elements } => {
+                elements.iter().any(Expression::contains_yield)
+            }
+            Expression::TupleIndex {
+                tuple: expression, ..
+            }
+            | Expression::Assignment { expression, .. }
+            | Expression::Let { expression, .. }
+            | Expression::CompoundAssignment { expression, .. }
+            | Expression::Destructuring { expression, .. }
+            | Expression::Return { expression }
+            | Expression::Throw { expression } => expression.contains_yield(),
+            Expression::Print { args, .. } => args.iter().any(Expression::contains_yield),
+            Expression::Format { parts } => parts.iter().any(|part| match part {
+                FormatPart::Literal(_) => false,
+                FormatPart::Interpolation { expression, .. } => expression.contains_yield(),
+            }),
+            Expression::While { condition, body } => {
+                condition.contains_yield() || body.contains_yield()
+            }
+            Expression::For {
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => {
+                start.contains_yield()
+                    || end.contains_yield()
+                    || step.as_ref().is_some_and(|step| step.contains_yield())
+                    || body.contains_yield()
+            }
+            Expression::ForEach { iterable, body, .. } => {
+                iterable.contains_yield() || body.contains_yield()
+            }
+            Expression::If {
+                condition,
+                then_clause,
+                else_clause,
+            } => {
+                condition.contains_yield()
+                    || then_clause.contains_yield()
+                    || else_clause
+                        .as_ref()
+                        .is_some_and(|else_clause| else_clause.contains_yield())
+            }
+            Expression::Match { scrutinee, arms } => {
+                scrutinee.contains_yield()
+                    || arms.iter().any(|arm| {
+                        arm.guard.as_ref().is_some_and(Expression::contains_yield)
+                            || arm.body.contains_yield()
+                    })
+            }
+            Expression::FunctionCall {
+                args, named_args, ..
+            } => {
+                args.iter().any(Expression::contains_yield)
+                    || named_args.iter().any(|(_, arg)| arg.contains_yield())
+            }
+            Expression::Try {
+                body,
+                catch_clause,
+                finally_clause,
+            } => {
+                body.contains_yield()
+                    || catch_clause
+                        .as_ref()
+                        .is_some_and(|catch_clause| catch_clause.body.contains_yield())
+                    || finally_clause
+                        .as_ref()
+                        .is_some_and(|finally_clause| finally_clause.contains_yield())
+            }
+        }
+    }
+
+    /// The expressions directly inside this one, including those of a nested function.
+    pub fn children_mut(&mut self) -> Vec<&mut Expression> {
+        match self {
+            Expression::IntegerLiteral { .. }
+            | Expression::BigIntegerLiteral { .. }
+            | Expression::SizedIntegerLiteral { .. }
+            | Expression::StringLiteral { .. }
+            | Expression::Identifier { .. }
+            | Expression::Break
+            | Expression::Continue => vec![],
+            Expression::Binary { lhs, rhs, .. } => vec![lhs.as_mut(), rhs.as_mut()],
+            Expression::Tuple { elements } | Expression::Block { elements } => {
+                elements.iter_mut().collect()
+            }
+            Expression::TupleIndex {
+                tuple: expression, ..
+            }
+            | Expression::Assignment { expression, .. }
+            | Expression::Let { expression, .. }
+            | Expression::CompoundAssignment { expression, .. }
+            | Expression::Destructuring { expression, .. }
+            | Expression::Return { expression }
+            | Expression::Throw { expression }
+            | Expression::Yield { expression } => vec![expression.as_mut()],
+            Expression::Print { args, .. } => args.iter_mut().collect(),
+            Expression::Format { parts } => parts
+                .iter_mut()
+                .filter_map(|part| match part {
+                    FormatPart::Literal(_) => None,
+                    FormatPart::Interpolation { expression, .. } => Some(expression),
+                })
+                .collect(),
+            Expression::FunctionDefinition { function } => Rc::make_mut(function).children_mut(),
+            Expression::While { condition, body } => vec![condition.as_mut(), body.as_mut()],
+            Expression::For {
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => {
+                let mut children = vec![start.as_mut(), end.as_mut()];
+                children.extend(step.as_deref_mut());
+                children.push(body.as_mut());
+                children
+            }
+            Expression::ForEach { iterable, body, .. } => vec![iterable.as_mut(), body.as_mut()],
+            Expression::If {
+                condition,
+                then_clause,
+                else_clause,
+            } => {
+                let mut children = vec![condition.as_mut(), then_clause.as_mut()];
+                children.extend(else_clause.as_deref_mut());
+                children
+            }
+            Expression::Match { scrutinee, arms } => {
+                let mut children = vec![scrutinee.as_mut()];
+                for arm in arms {
+                    children.extend(arm.guard.as_mut());
+                    children.push(&mut arm.body);
+                }
+                children
+            }
+            Expression::FunctionCall {
+                args, named_args, ..
+            } => args
+                .iter_mut()
+                .chain(named_args.iter_mut().map(|(_, arg)| arg))
+                .collect(),
+            Expression::Try {
+                body,
+                catch_clause,
+                finally_clause,
+            } => {
+                let mut children = vec![body.as_mut()];
+                children.extend(
+                    catch_clause
+                        .as_mut()
+                        .map(|catch_clause| catch_clause.body.as_mut()),
+                );
+                children.extend(finally_clause.as_deref_mut());
+                children
+            }
+        }
+    }
+}
+
+impl Function {
+    /// The default arguments, the contract and the body.
+    pub fn children_mut(&mut self) -> Vec<&mut Expression> {
+        let mut children: Vec<_> = self
+            .args
+            .iter_mut()
+            .filter_map(|arg| match &mut arg.kind {
+                ParameterKind::Default(expression) => Some(expression),
+                ParameterKind::Required | ParameterKind::Variadic => None,
+            })
+            .collect();
+        children.extend(&mut self.contract.requires);
+        children.extend(&mut self.contract.ensures);
+        children.push(&mut self.body);
+
+        children
+    }
+
+    /// Checks that a call giving `positional` arguments by position and those named `named` by
+    /// name leaves no parameter without a value, and gives no more arguments by position than
+    /// there are parameters to take them.
+    pub fn check_arity(&self, positional: usize, named: &[&str]) -> Result<(), ArityMismatch> {
+        let given = positional + named.len();
+        let (fixed, variadic) = match self.args.split_last() {
+            Some((last, fixed)) if matches!(last.kind, ParameterKind::Variadic) => (fixed, true),
+            _ => (&self.args[..], false),
+        };
+        if !variadic && positional > fixed.len() {
+            return Err(ArityMismatch::TooMany {
+                expected: fixed.len(),
+                given,
+            });
+        }
+
+        let required = fixed
+            .iter()
+            .filter(|parameter| matches!(parameter.kind, ParameterKind::Required))
+            .count();
+        if given < required {
+            return Err(ArityMismatch::NotEnough {
+                expected: required,
+                given,
+            });
+        }
+        // 省略できる仮引数の後ろに必須の仮引数があると、必須の数だけ渡しても足りないことがある
+        let missing = fixed.iter().enumerate().find(|(index, parameter)| {
+            matches!(parameter.kind, ParameterKind::Required)
+                && *index >= positional
+                && !named.contains(&parameter.name.as_str())
+        });
+        if let Some((_, parameter)) = missing {
+            return Err(ArityMismatch::Missing {
+                parameter: parameter.name.clone(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// A call giving a function too few or too many arguments.
+#[derive(Debug, PartialEq)]
+pub enum ArityMismatch {
+    /// The function takes at least `expected` arguments.
+    NotEnough { expected: usize, given: usize },
+    /// The function takes at most `expected` arguments.
+    TooMany { expected: usize, given: usize },
+    /// Enough arguments are given, but none of them for the required parameter `parameter`.
+    Missing { parameter: String },
+}
+
+/// The `requires (condition)` and `ensures (condition)` clauses of a function, checked before and
+/// after each call. `result` refers to the return value in `ensures`.
+#[derive(Clone, Default)]
+pub struct Contract {
+    pub requires: Vec<Expression>,
+    pub ensures: Vec<Expression>,
+}
+
+pub fn contract(requires: Vec<Expression>, ensures: Vec<Expression>) -> Contract {
+    Contract { requires, ensures }
+}
+
+#[derive(Clone)]
+pub struct Parameter {
+    pub name: String,
+    pub kind: ParameterKind,
+}
+
+#[derive(Clone)]
+pub enum ParameterKind {
+    Required,
+    /// `name = expression`. `expression` is evaluated by each call which omits the argument.
+    Default(Expression),
+    /// `...name`, which collects the remaining positional arguments into a tuple.
+    /// Only the last parameter can be variadic.
+    Variadic,
+}
+
+pub fn parameter(name: &str) -> Parameter {
+    Parameter {
+        name: name.to_string(),
+        kind: ParameterKind::Required,
+    }
+}
+
+pub fn default_parameter(name: &str, expression: Expression) -> Parameter {
+    Parameter {
+        name: name.to_string(),
+        kind: ParameterKind::Default(expression),
+    }
 }
 
+pub fn variadic_parameter(name: &str) -> Parameter {
+    Parameter {
+        name: name.to_string(),
+        kind: ParameterKind::Variadic,
+    }
+}
+
+/// A constructor of an `enum`. `fields` only name the payload; their count is the arity.
+#[derive(Clone)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+pub struct Enum {
+    pub name: String,
+    pub variants: Vec<Variant>,
+}
+
+#[allow(clippy::enum_variant_names)]
 pub enum TopLevel {
     FunctionDefinition(Function),
+    EnumDefinition(Enum),
     GlobalVariableDefinition {
         name: String,
         expression: Expression,
     },
+    ConstantDefinition {
+        name: String,
+        expression: Expression,
+    },
+    MacroDefinition(Macro),
+    /// `import "path" as alias;`, which makes the definitions of the module at `path` available
+    /// as `alias::name`.
+    Import {
+        path: String,
+        alias: String,
+    },
+    /// A statement outside of any function. The statements of a program run in order when it is
+    /// started, before `main` if there is one.
+    Statement(Expression),
+}
+
+/// `macro name(params) body`. Calls to `name` are replaced by `body` with `params` substituted
+/// before the program is checked.
+pub struct Macro {
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Expression,
 }
 
+#[cfg(test)]
 pub fn define_function(name: &str, args: &[&str], body: Expression) -> TopLevel {
+    let args: Vec<_> = args.iter().map(|arg| parameter(arg)).collect();
+    define_function_with_parameters(name, &args, body)
+}
+
+#[cfg(test)]
+pub fn define_function_with_parameters(
+    name: &str,
+    args: &[Parameter],
+    body: Expression,
+) -> TopLevel {
+    define_function_with_contract(name, args, Contract::default(), body)
+}
+
+pub fn define_function_with_contract(
+    name: &str,
+    args: &[Parameter],
+    contract: Contract,
+    body: Expression,
+) -> TopLevel {
     TopLevel::FunctionDefinition(Function {
         name: name.to_string(),
-        args: args.iter().map(|arg| arg.to_string()).collect(),
+        args: args.to_vec(),
+        contract,
+        generator: body.contains_yield(),
         body,
     })
 }
 
+#[cfg(test)]
+pub fn nested_function(name: &str, args: &[&str], body: Expression) -> Expression {
+    let args: Vec<_> = args.iter().map(|arg| parameter(arg)).collect();
+    nested_function_with_contract(name, &args, Contract::default(), body)
+}
+
+pub fn nested_function_with_contract(
+    name: &str,
+    args: &[Parameter],
+    contract: Contract,
+    body: Expression,
+) -> Expression {
+    Expression::FunctionDefinition {
+        function: Rc::new(Function {
+            name: name.to_string(),
+            args: args.to_vec(),
+            contract,
+            generator: body.contains_yield(),
+            body,
+        }),
+    }
+}
+
+pub fn define_enum(name: &str, variants: &[(&str, &[&str])]) -> TopLevel {
+    TopLevel::EnumDefinition(Enum {
+        name: name.to_string(),
+        variants: variants
+            .iter()
+            .map(|(name, fields)| Variant {
+                name: name.to_string(),
+                fields: fields.iter().map(|field| field.to_string()).collect(),
+            })
+            .collect(),
+    })
+}
+
 pub fn difine_global_variable(name: &str, expression: Expression) -> TopLevel {
     TopLevel::GlobalVariableDefinition {
         name: name.to_string(),
@@ -211,6 +998,32 @@ pub fn difine_global_variable(name: &str, expression: Expression) -> TopLevel {
     }
 }
 
+pub fn define_constant(name: &str, expression: Expression) -> TopLevel {
+    TopLevel::ConstantDefinition {
+        name: name.to_string(),
+        expression,
+    }
+}
+
+pub fn define_macro(name: &str, params: &[&str], body: Expression) -> TopLevel {
+    TopLevel::MacroDefinition(Macro {
+        name: name.to_string(),
+        params: params.iter().map(|param| param.to_string()).collect(),
+        body,
+    })
+}
+
+pub fn import(path: &str, alias: &str) -> TopLevel {
+    TopLevel::Import {
+        path: path.to_string(),
+        alias: alias.to_string(),
+    }
+}
+
+pub fn top_level_statement(expression: Expression) -> TopLevel {
+    TopLevel::Statement(expression)
+}
+
 pub struct Program {
     pub definitions: Vec<TopLevel>,
 }
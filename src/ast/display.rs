@@ -0,0 +1,514 @@
+//! Prints programs back as Toys source, e.g. to show what macros expand to.
+
+use std::fmt;
+
+use super::{
+    Alignment, AssignmentTarget, Expression, FormatPart, FormatSpec, Function, Operator, Parameter,
+    ParameterKind, Pattern, Program, Radix, TopLevel,
+};
+
+const INDENT: &str = "    ";
+
+/// Binds tighter than every binary operator.
+const ATOM: u8 = 4;
+
+impl Operator {
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::LessThan
+            | Operator::LessOrEqual
+            | Operator::GreaterThan
+            | Operator::GreaterOrEqual
+            | Operator::EqualEqual
+            | Operator::NotEqual => 1,
+            Operator::Add | Operator::Subtract => 2,
+            Operator::Multiply | Operator::Divide | Operator::Remainder => 3,
+        }
+    }
+}
+
+/// Writes the spec in the form it is parsed from, which is empty for the default one.
+impl fmt::Display for FormatSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(fill) = self.fill {
+            write!(f, "{}", fill)?;
+        }
+        match self.align {
+            Some(Alignment::Left) => write!(f, "<")?,
+            Some(Alignment::Center) => write!(f, "^")?,
+            Some(Alignment::Right) => write!(f, ">")?,
+            None => {}
+        }
+        if self.zero {
+            write!(f, "0")?;
+        }
+        if self.width > 0 {
+            write!(f, "{}", self.width)?;
+        }
+        match self.radix {
+            Some(Radix::Binary) => write!(f, "b"),
+            Some(Radix::Octal) => write!(f, "o"),
+            Some(Radix::LowerHex) => write!(f, "x"),
+            Some(Radix::UpperHex) => write!(f, "X"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let symbol = match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+            Operator::Remainder => "%",
+            Operator::LessThan => "<",
+            Operator::LessOrEqual => "<=",
+            Operator::GreaterThan => ">",
+            Operator::GreaterOrEqual => ">=",
+            Operator::EqualEqual => "==",
+            Operator::NotEqual => "!=",
+        };
+
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Literal { value } => write!(f, "{}", value),
+            Pattern::Range {
+                start,
+                end,
+                inclusive,
+            } => write!(
+                f,
+                "{}{}{}",
+                start,
+                if *inclusive { "..=" } else { ".." },
+                end
+            ),
+            Pattern::Binding { name } => write!(f, "{}", name),
+            Pattern::Variant { name, fields } => {
+                write!(f, "{}(", name)?;
+                write_list(f, fields, |f, field| write!(f, "{}", field))?;
+                write!(f, ")")
+            }
+            Pattern::Tuple { elements } => {
+                write!(f, "(")?;
+                write_list(f, elements, |f, element| write!(f, "{}", element))?;
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_expression(f, self, 0)
+    }
+}
+
+impl fmt::Display for TopLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TopLevel::FunctionDefinition(function) => write_function(f, function, 0),
+            TopLevel::EnumDefinition(definition) => {
+                writeln!(f, "enum {} {{", definition.name)?;
+                for variant in &definition.variants {
+                    write!(f, "{}{}", INDENT, variant.name)?;
+                    if !variant.fields.is_empty() {
+                        write!(f, "({})", variant.fields.join(", "))?;
+                    }
+                    writeln!(f, ",")?;
+                }
+                write!(f, "}}")
+            }
+            TopLevel::GlobalVariableDefinition { name, expression } => {
+                write!(f, "global {} = ", name)?;
+                write_expression(f, expression, 0)?;
+                write!(f, ";")
+            }
+            TopLevel::ConstantDefinition { name, expression } => {
+                write!(f, "const {} = ", name)?;
+                write_expression(f, expression, 0)?;
+                write!(f, ";")
+            }
+            TopLevel::MacroDefinition(definition) => {
+                write!(
+                    f,
+                    "macro {}({}) ",
+                    definition.name,
+                    definition.params.join(", ")
+                )?;
+                write_statement(f, &definition.body, 0)
+            }
+            TopLevel::Import { path, alias } => {
+                write!(f, "import ")?;
+                write_string(f, path)?;
+                write!(f, " as {};", alias)
+            }
+            TopLevel::Statement(statement) => write_statement(f, statement, 0),
+        }
+    }
+}
+
+/// Top-level definitions are separated by blank lines.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (index, definition) in self.definitions.iter().enumerate() {
+            if index > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}", definition)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn write_indent(f: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+    for _ in 0..indent {
+        write!(f, "{}", INDENT)?;
+    }
+
+    Ok(())
+}
+
+fn write_list<T>(
+    f: &mut fmt::Formatter,
+    items: &[T],
+    mut write_item: impl FnMut(&mut fmt::Formatter, &T) -> fmt::Result,
+) -> fmt::Result {
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write_item(f, item)?;
+    }
+
+    Ok(())
+}
+
+fn write_string(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    write_string_contents(f, value)?;
+    write!(f, "\"")
+}
+
+fn write_string_contents(f: &mut fmt::Formatter, value: &str) -> fmt::Result {
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => write!(f, "\\\\")?,
+            '"' => write!(f, "\\\"")?,
+            '\n' => write!(f, "\\n")?,
+            '\t' => write!(f, "\\t")?,
+            // `${` は補間の始まりと読まれてしまう
+            '$' if chars.peek() == Some(&'{') => write!(f, "\\$")?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a string literal with interpolations, e.g. `"x = ${x:>4}"`.
+fn write_format(f: &mut fmt::Formatter, parts: &[FormatPart], indent: usize) -> fmt::Result {
+    write!(f, "\"")?;
+    for part in parts {
+        match part {
+            FormatPart::Literal(value) => write_string_contents(f, value)?,
+            FormatPart::Interpolation { expression, spec } => {
+                write!(f, "${{")?;
+                write_expression(f, expression, indent)?;
+                let spec = spec.to_string();
+                if !spec.is_empty() {
+                    write!(f, ":{}", spec)?;
+                }
+                write!(f, "}}")?;
+            }
+        }
+    }
+    write!(f, "\"")
+}
+
+fn write_function(f: &mut fmt::Formatter, function: &Function, indent: usize) -> fmt::Result {
+    write!(f, "define {}(", function.name)?;
+    write_list(f, &function.args, |f, arg| write_parameter(f, arg, indent))?;
+    write!(f, ") ")?;
+    for condition in &function.contract.requires {
+        write!(f, "requires (")?;
+        write_expression(f, condition, indent)?;
+        write!(f, ") ")?;
+    }
+    for condition in &function.contract.ensures {
+        write!(f, "ensures (")?;
+        write_expression(f, condition, indent)?;
+        write!(f, ") ")?;
+    }
+
+    match &function.body {
+        Expression::Block { .. } => write_statement(f, &function.body, indent),
+        body => {
+            write!(f, "= ")?;
+            write_expression(f, body, indent)?;
+            write!(f, ";")
+        }
+    }
+}
+
+fn write_parameter(f: &mut fmt::Formatter, parameter: &Parameter, indent: usize) -> fmt::Result {
+    match &parameter.kind {
+        ParameterKind::Required => write!(f, "{}", parameter.name),
+        ParameterKind::Default(expression) => {
+            write!(f, "{} = ", parameter.name)?;
+            write_expression(f, expression, indent)
+        }
+        ParameterKind::Variadic => write!(f, "...{}", parameter.name),
+    }
+}
+
+/// Writes `statement` as a line of a block, starting at the current column. The lines of nested
+/// blocks are indented one level deeper than `indent`.
+fn write_statement(f: &mut fmt::Formatter, statement: &Expression, indent: usize) -> fmt::Result {
+    match statement {
+        Expression::Assignment { name, expression } => {
+            write!(f, "{} = ", name)?;
+            write_expression(f, expression, indent)?;
+            write!(f, ";")
+        }
+        Expression::Let { name, expression } => {
+            write!(f, "let {} = ", name)?;
+            write_expression(f, expression, indent)?;
+            write!(f, ";")
+        }
+        Expression::CompoundAssignment {
+            operator,
+            target: AssignmentTarget::Variable { name },
+            expression,
+        } => {
+            write!(f, "{} {}= ", name, operator)?;
+            write_expression(f, expression, indent)?;
+            write!(f, ";")
+        }
+        Expression::Destructuring {
+            pattern,
+            expression,
+        } => {
+            write!(f, "{} = ", pattern)?;
+            write_expression(f, expression, indent)?;
+            write!(f, ";")
+        }
+        Expression::Block { elements } => {
+            if elements.is_empty() {
+                return write!(f, "{{}}");
+            }
+
+            writeln!(f, "{{")?;
+            for element in elements {
+                write_indent(f, indent + 1)?;
+                write_statement(f, element, indent + 1)?;
+                writeln!(f)?;
+            }
+            write_indent(f, indent)?;
+            write!(f, "}}")
+        }
+        Expression::FunctionDefinition { function } => write_function(f, function, indent),
+        Expression::While { condition, body } => {
+            write!(f, "while (")?;
+            write_expression(f, condition, indent)?;
+            write!(f, ") ")?;
+            write_statement(f, body, indent)
+        }
+        Expression::For {
+            variable,
+            start,
+            end,
+            inclusive,
+            step,
+            body,
+        } => {
+            write!(f, "for ({} in ", variable)?;
+            write_expression(f, start, indent)?;
+            write!(f, "{}", if *inclusive { "..=" } else { ".." })?;
+            write_expression(f, end, indent)?;
+            if let Some(step) = step {
+                write!(f, " step ")?;
+                write_expression(f, step, indent)?;
+            }
+            write!(f, ") ")?;
+            write_statement(f, body, indent)
+        }
+        Expression::ForEach {
+            variable,
+            iterable,
+            body,
+        } => {
+            write!(f, "for ({} in ", variable)?;
+            write_expression(f, iterable, indent)?;
+            write!(f, ") ")?;
+            write_statement(f, body, indent)
+        }
+        Expression::If {
+            condition,
+            then_clause,
+            else_clause,
+        } => {
+            write!(f, "if (")?;
+            write_expression(f, condition, indent)?;
+            write!(f, ") ")?;
+            write_statement(f, then_clause, indent)?;
+            if let Some(else_clause) = else_clause {
+                write!(f, " else ")?;
+                write_statement(f, else_clause, indent)?;
+            }
+
+            Ok(())
+        }
+        Expression::Match { scrutinee, arms } => {
+            write!(f, "match (")?;
+            write_expression(f, scrutinee, indent)?;
+            writeln!(f, ") {{")?;
+            for arm in arms {
+                write_indent(f, indent + 1)?;
+                write!(f, "{}", arm.pattern)?;
+                if let Some(guard) = &arm.guard {
+                    write!(f, " if ")?;
+                    write_expression(f, guard, indent + 1)?;
+                }
+                write!(f, " => ")?;
+                match &arm.body {
+                    Expression::Block { .. } => write_statement(f, &arm.body, indent + 1)?,
+                    body => write_expression(f, body, indent + 1)?,
+                }
+                writeln!(f, ",")?;
+            }
+            write_indent(f, indent)?;
+            write!(f, "}}")
+        }
+        Expression::Print { args, newline } => {
+            write!(f, "{}(", if *newline { "println" } else { "print" })?;
+            write_list(f, args, |f, arg| write_expression(f, arg, indent))?;
+            write!(f, ");")
+        }
+        Expression::Return { expression } => {
+            write!(f, "return ")?;
+            write_expression(f, expression, indent)?;
+            write!(f, ";")
+        }
+        Expression::Throw { expression } => {
+            write!(f, "throw ")?;
+            write_expression(f, expression, indent)?;
+            write!(f, ";")
+        }
+        Expression::Yield { expression } => {
+            write!(f, "yield ")?;
+            write_expression(f, expression, indent)?;
+            write!(f, ";")
+        }
+        Expression::Try {
+            body,
+            catch_clause,
+            finally_clause,
+        } => {
+            write!(f, "try ")?;
+            write_statement(f, body, indent)?;
+            if let Some(catch_clause) = catch_clause {
+                write!(f, " catch ({}) ", catch_clause.variable)?;
+                write_statement(f, &catch_clause.body, indent)?;
+            }
+            if let Some(finally_clause) = finally_clause {
+                write!(f, " finally ")?;
+                write_statement(f, finally_clause, indent)?;
+            }
+
+            Ok(())
+        }
+        Expression::Break => write!(f, "break;"),
+        Expression::Continue => write!(f, "continue;"),
+        expression => {
+            write_expression(f, expression, indent)?;
+            write!(f, ";")
+        }
+    }
+}
+
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::Binary { operator, .. } => operator.precedence(),
+        _ => ATOM,
+    }
+}
+
+/// Writes `expression`, parenthesized unless it binds at least as tight as `required`.
+fn write_operand(
+    f: &mut fmt::Formatter,
+    expression: &Expression,
+    required: u8,
+    indent: usize,
+) -> fmt::Result {
+    if precedence(expression) < required {
+        write!(f, "(")?;
+        write_expression(f, expression, indent)?;
+        write!(f, ")")
+    } else {
+        write_expression(f, expression, indent)
+    }
+}
+
+/// Writes `expression` in the position of an operand. Statements only end up there through macro
+/// expansion, and are written as they are.
+fn write_expression(f: &mut fmt::Formatter, expression: &Expression, indent: usize) -> fmt::Result {
+    match expression {
+        Expression::Binary { operator, lhs, rhs } => {
+            // 演算子はすべて左結合なので、右の被演算子は同じ優先順位でも括弧で囲む
+            write_operand(f, lhs, operator.precedence(), indent)?;
+            write!(f, " {} ", operator)?;
+            write_operand(f, rhs, operator.precedence() + 1, indent)
+        }
+        Expression::IntegerLiteral { value } => write!(f, "{}", value),
+        Expression::BigIntegerLiteral { value } => write!(f, "{}", value),
+        Expression::SizedIntegerLiteral { value, ty } => write!(f, "{}{}", value, ty),
+        Expression::StringLiteral { value } => write_string(f, value),
+        Expression::Format { parts } => write_format(f, parts, indent),
+        Expression::Identifier { name } => write!(f, "{}", name),
+        Expression::Tuple { elements } => {
+            write!(f, "(")?;
+            write_list(f, elements, |f, element| {
+                write_expression(f, element, indent)
+            })?;
+            if elements.len() == 1 {
+                write!(f, ",")?;
+            }
+            write!(f, ")")
+        }
+        Expression::TupleIndex { tuple, index } => {
+            write_operand(f, tuple, ATOM, indent)?;
+            write!(f, ".{}", index)
+        }
+        Expression::FunctionCall {
+            name,
+            args,
+            named_args,
+        } => {
+            write!(f, "{}(", name)?;
+            write_list(f, args, |f, arg| write_expression(f, arg, indent))?;
+            if !args.is_empty() && !named_args.is_empty() {
+                write!(f, ", ")?;
+            }
+            write_list(f, named_args, |f, (name, arg)| {
+                write!(f, "{} = ", name)?;
+                write_expression(f, arg, indent)
+            })?;
+            write!(f, ")")
+        }
+        statement => write_statement(f, statement, indent),
+    }
+}
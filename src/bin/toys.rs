@@ -0,0 +1,112 @@
+use std::error::Error;
+use std::path::Path;
+use std::{env, process};
+use toysrust::modules::{self, FileLoader, ModuleLoader};
+use toysrust::project::Project;
+use toysrust::{interpreter, macros};
+
+const USAGE: &str = "Usage: toys run [--no-contracts] [--max-call-depth <depth>] [<file or project directory> [<args>...]]
+       toys expand [<file or project directory>]";
+
+/// How `toys run` runs a program, as its options tell.
+struct RunOptions {
+    contracts: bool,
+    max_call_depth: Option<usize>,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["run", ref rest @ ..] => match run_options(rest) {
+            Some((options, [])) => run(".", &[], &options),
+            Some((options, [path, args @ ..])) => run(path, args, &options),
+            None => usage(),
+        },
+        ["expand"] => expand(".").map(|()| 0),
+        ["expand", path] => expand(path).map(|()| 0),
+        _ => usage(),
+    };
+
+    match result {
+        Ok(status) => process::exit(status.into()),
+        Err(error) => {
+            eprintln!("error: {}", error);
+            process::exit(1);
+        }
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Takes the options of `toys run` off the front of `args`, giving back the rest. `None` means
+/// an option is not understood.
+fn run_options<'a>(mut args: &'a [&'a str]) -> Option<(RunOptions, &'a [&'a str])> {
+    let mut options = RunOptions {
+        contracts: true,
+        max_call_depth: None,
+    };
+    loop {
+        match args {
+            ["--no-contracts", rest @ ..] => {
+                options.contracts = false;
+                args = rest;
+            }
+            ["--max-call-depth", depth, rest @ ..] => {
+                options.max_call_depth = Some(depth.parse().ok()?);
+                args = rest;
+            }
+            [option, ..] if option.starts_with("--") => return None,
+            _ => return Some((options, args)),
+        }
+    }
+}
+
+/// Runs the program in `path` with the command-line arguments `args` as `options` tell, and gives
+/// back its exit status.
+fn run(path: &str, args: &[&str], options: &RunOptions) -> Result<u8, Box<dyn Error>> {
+    let (entry, loader) = open(path)?;
+    let program = modules::load(&entry, loader.as_ref())?;
+
+    let mut interpreter = interpreter::Interpreter::default();
+    interpreter.set_contract_checking(options.contracts);
+    if let Some(depth) = options.max_call_depth {
+        interpreter.set_max_call_depth(depth);
+    }
+    interpreter.set_module_loader(loader);
+    interpreter.set_args(args.iter().map(|arg| arg.to_string()));
+
+    Ok(interpreter.run(program)?)
+}
+
+/// Prints the program in `path` as it is after the modules it imports are linked and its macros
+/// are expanded. The names made up by expansion are respelled so that the output parses back,
+/// whereas those of imported definitions keep the `module::name` form linking gives them.
+fn expand(path: &str) -> Result<(), Box<dyn Error>> {
+    let (entry, loader) = open(path)?;
+    let mut program = macros::expand(modules::load(&entry, loader.as_ref())?)?;
+    macros::respell(&mut program);
+
+    print!("{}", program);
+
+    Ok(())
+}
+
+/// The path of the module to start from, and where it and the modules it imports are loaded from.
+/// If `path` is a directory, it is a project and its `toys.toml` tells both; otherwise `path` is a
+/// file whose imports are relative to its directory.
+fn open(path: &str) -> Result<(String, Box<dyn ModuleLoader>), Box<dyn Error>> {
+    let path = Path::new(path);
+    if path.is_dir() {
+        let project = Project::open(path)?;
+        return Ok((project.entry().to_string(), Box::new(project)));
+    }
+
+    let file = path.file_name().ok_or("not a file")?.to_string_lossy();
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    Ok((file.into_owned(), Box::new(FileLoader::new(dir))))
+}
@@ -0,0 +1,1361 @@
+//! Static checks run over a whole program before it is executed.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use crate::ast;
+use thiserror::Error;
+
+mod exhaustiveness;
+
+#[derive(Error, Debug)]
+pub enum CheckError {
+    #[error("`break` is used outside of a loop")]
+    BreakOutsideLoop,
+    #[error("`continue` is used outside of a loop")]
+    ContinueOutsideLoop,
+    #[error("`return` is used outside of a function")]
+    ReturnOutsideFunction,
+    #[error("The match is not exhaustive: {0} is not covered by any arm")]
+    NonExhaustiveMatch(String),
+    #[error("The match arm #{0} can never be reached")]
+    UnreachableMatchArm(usize),
+    #[error("Constructor {0} is defined more than once")]
+    DuplicateConstructor(String),
+    #[error("Constructor {0} is not defined")]
+    UnknownConstructor(String),
+    #[error("Constructor {0} has {1} field(s) but its pattern has {2}")]
+    ConstructorPatternArity(String, usize, usize),
+    #[error("Cannot assign to `{0}`, which is declared immutable by {1}")]
+    AssignmentToImmutable(String, String),
+    #[error("`yield` is used outside of the statements of a generator body")]
+    MisplacedYield,
+    #[error("Generator {0} cannot have `ensures` clauses, since it returns no result")]
+    EnsuresOnGenerator(String),
+    #[error("Function {0} takes at least {1} argument(s) but {2} were given")]
+    NotEnoughArguments(String, usize, usize),
+    #[error("Function {0} takes at most {1} argument(s) but {2} were given")]
+    TooManyArguments(String, usize, usize),
+    #[error("The argument `{1}` of `{0}` is not given")]
+    MissingArgument(String, String),
+}
+
+/// Where an expression appears, as far as control flow and variables are concerned.
+#[derive(Clone, Copy)]
+struct Context<'s> {
+    /// The name of the enclosing function, if any.
+    function: Option<&'s str>,
+    in_loop: bool,
+    /// Whether the expression is a statement of a generator body, which can suspend at `yield`.
+    /// Only blocks, `if`, loops, `match` arms and the clauses of `try` keep their statements
+    /// suspendable.
+    can_yield: bool,
+    scope: &'s Scope<'s>,
+    functions: &'s Functions<'s>,
+}
+
+/// The functions defined by the top level or by one block, and those visible around it.
+struct Functions<'s> {
+    functions: HashMap<&'s str, &'s ast::Function>,
+    parent: Option<&'s Functions<'s>>,
+}
+
+impl Functions<'_> {
+    /// The function a call to `name` calls, unless it is a builtin or a constructor.
+    fn lookup(&self, name: &str) -> Option<&ast::Function> {
+        match self.functions.get(name) {
+            Some(function) => Some(function),
+            None => self.parent.and_then(|parent| parent.lookup(name)),
+        }
+    }
+}
+
+/// The variables declared in one runtime scope, and the scope enclosing it.
+struct Scope<'s> {
+    /// For each variable, `Some` description of its declaration if it cannot be assigned.
+    variables: HashMap<&'s str, Option<String>>,
+    /// The `let` bindings among `variables` which the code checked so far has already made.
+    declared: RefCell<HashSet<String>>,
+    /// Whether the code in this scope only runs once the enclosing scopes have made all of their
+    /// `let` bindings, as the body of a nested function or an `ensures` clause does.
+    runs_after_parent: bool,
+    parent: Option<&'s Scope<'s>>,
+}
+
+impl<'s> Scope<'s> {
+    fn new(variables: HashMap<&'s str, Option<String>>, parent: Option<&'s Scope<'s>>) -> Self {
+        Scope {
+            variables,
+            declared: RefCell::new(HashSet::new()),
+            runs_after_parent: false,
+            parent,
+        }
+    }
+
+    /// Describes the declaration of the variable `name` refers to, if it is immutable.
+    fn immutable_declaration(&self, name: &str) -> Option<&str> {
+        self.lookup(name, true)
+    }
+
+    /// Finds the declaration of the variable `name` refers to, as `immutable_declaration` does.
+    /// With `in_order`, a `let` binding which has not been made yet does not hide the variables
+    /// of the enclosing scopes, since an assignment before it sets those.
+    fn lookup(&self, name: &str, in_order: bool) -> Option<&str> {
+        match self.variables.get(name) {
+            Some(Some(_)) if in_order && !self.declared.borrow().contains(name) => {
+                self.lookup_parent(name, in_order)
+            }
+            Some(declaration) => declaration.as_deref(),
+            None => self.lookup_parent(name, in_order),
+        }
+    }
+
+    fn lookup_parent(&self, name: &str, in_order: bool) -> Option<&str> {
+        let in_order = in_order && !self.runs_after_parent;
+        self.parent.and_then(|parent| parent.lookup(name, in_order))
+    }
+
+    /// Records that the `let` bindings in `names` have been made, by the code checked so far or
+    /// by an earlier iteration of a loop.
+    fn declare<'n>(&self, names: impl IntoIterator<Item = &'n str>) {
+        let names = names.into_iter().map(|name| name.to_string());
+        self.declared.borrow_mut().extend(names);
+    }
+}
+
+pub fn check(program: &ast::Program) -> Result<(), CheckError> {
+    let checker = Checker::new(program)?;
+
+    let mut globals = Scope::new(HashMap::new(), None);
+    for top_level in &program.definitions {
+        match top_level {
+            ast::TopLevel::GlobalVariableDefinition { name, .. } => {
+                globals.variables.insert(name.as_str(), None);
+            }
+            ast::TopLevel::ConstantDefinition { name, .. } => {
+                let declaration = format!("`const {}` at top level", name);
+                globals.variables.insert(name.as_str(), Some(declaration));
+            }
+            // 最上位の文が束縛する変数は大域変数になる
+            ast::TopLevel::Statement(ast::Expression::Let { name, .. }) => {
+                let declaration = format!("`let {}` at top level", name);
+                globals.variables.insert(name.as_str(), Some(declaration));
+            }
+            _ => {}
+        }
+    }
+
+    // for ループなどの中で束縛される変数は、大域変数の内側のスコープに置かれる
+    let mut statements = Scope::new(HashMap::new(), Some(&globals));
+    for top_level in &program.definitions {
+        if let ast::TopLevel::Statement(statement) = top_level {
+            collect_lets(statement, None, &mut statements.variables);
+        }
+    }
+
+    let functions = Functions {
+        functions: program
+            .definitions
+            .iter()
+            .filter_map(|top_level| match top_level {
+                ast::TopLevel::FunctionDefinition(function) => {
+                    Some((function.name.as_str(), function))
+                }
+                _ => None,
+            })
+            .collect(),
+        parent: None,
+    };
+
+    for top_level in &program.definitions {
+        match top_level {
+            ast::TopLevel::FunctionDefinition(function) => {
+                checker.check_function(function, &globals, &functions)?;
+            }
+            ast::TopLevel::EnumDefinition(_)
+            | ast::TopLevel::MacroDefinition(_)
+            | ast::TopLevel::Import { .. } => {}
+            ast::TopLevel::GlobalVariableDefinition { expression, .. }
+            | ast::TopLevel::ConstantDefinition { expression, .. } => {
+                let context = Context {
+                    function: None,
+                    in_loop: false,
+                    can_yield: false,
+                    scope: &globals,
+                    functions: &functions,
+                };
+                checker.check_expression(expression, context)?;
+            }
+            ast::TopLevel::Statement(statement) => {
+                let context = Context {
+                    function: None,
+                    in_loop: false,
+                    can_yield: false,
+                    scope: &statements,
+                    functions: &functions,
+                };
+                checker.check_expression(statement, context)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The scope of the body of `function`, which is defined in `parent`.
+fn function_scope<'s>(function: &'s ast::Function, parent: &'s Scope<'s>) -> Scope<'s> {
+    let mut variables: HashMap<_, _> = function
+        .args
+        .iter()
+        .map(|arg| (arg.name.as_str(), None))
+        .collect();
+    collect_lets(&function.body, Some(&function.name), &mut variables);
+
+    Scope {
+        runs_after_parent: true,
+        ..Scope::new(variables, Some(parent))
+    }
+}
+
+/// Registers the `let` bindings which `expression` makes in the scope it is evaluated in, leaving
+/// out those made in the inner scopes of `for` loops and match arms.
+fn collect_lets<'s>(
+    expression: &'s ast::Expression,
+    function: Option<&str>,
+    variables: &mut HashMap<&'s str, Option<String>>,
+) {
+    let mut collect = |expression| collect_lets(expression, function, variables);
+
+    match expression {
+        ast::Expression::Let { name, expression } => {
+            let declaration = match function {
+                Some(function) => format!("`let {}` in function `{}`", name, function),
+                None => format!("`let {}` at top level", name),
+            };
+            collect_lets(expression, function, variables);
+            variables.insert(name.as_str(), Some(declaration));
+        }
+        ast::Expression::Binary { lhs, rhs, .. } => {
+            collect(lhs);
+            collect(rhs);
+        }
+        // the body of a nested function is evaluated in a scope of its own
+        ast::Expression::IntegerLiteral { .. }
+        | ast::Expression::BigIntegerLiteral { .. }
+        | ast::Expression::SizedIntegerLiteral { .. }
+        | ast::Expression::StringLiteral { .. }
+        | ast::Expression::Identifier { .. }
+        | ast::Expression::FunctionDefinition { .. }
+        | ast::Expression::Break
+        | ast::Expression::Continue => {}
+        ast::Expression::Tuple { elements }
+        | ast::Expression::Block { elements }
+        | ast::Expression::Print { args: elements, .. } => {
+            elements.iter().for_each(collect);
+        }
+        ast::Expression::Format { parts } => {
+            for part in parts {
+                if let ast::FormatPart::Interpolation { expression, .. } = part {
+                    collect(expression);
+                }
+            }
+        }
+        ast::Expression::FunctionCall {
+            args, named_args, ..
+        } => args
+            .iter()
+            .chain(named_args.iter().map(|(_, arg)| arg))
+            .for_each(collect),
+        ast::Expression::TupleIndex {
+            tuple: expression, ..
+        }
+        | ast::Expression::Assignment { expression, .. }
+        | ast::Expression::CompoundAssignment { expression, .. }
+        | ast::Expression::Destructuring { expression, .. }
+        | ast::Expression::Return { expression }
+        | ast::Expression::Throw { expression }
+        | ast::Expression::Yield { expression } => collect(expression),
+        // so is a `catch` clause
+        ast::Expression::Try {
+            body,
+            finally_clause,
+            ..
+        } => {
+            collect(body);
+            if let Some(finally_clause) = finally_clause {
+                collect(finally_clause);
+            }
+        }
+        ast::Expression::While { condition, body } => {
+            collect(condition);
+            collect(body);
+        }
+        ast::Expression::For {
+            start, end, step, ..
+        } => {
+            collect(start);
+            collect(end);
+            if let Some(step) = step {
+                collect(step);
+            }
+        }
+        ast::Expression::ForEach { iterable, .. } => collect(iterable),
+        ast::Expression::If {
+            condition,
+            then_clause,
+            else_clause,
+        } => {
+            collect(condition);
+            collect(then_clause);
+            if let Some(else_clause) = else_clause {
+                collect(else_clause);
+            }
+        }
+        ast::Expression::Match { scrutinee, .. } => collect(scrutinee),
+    }
+}
+
+/// Appends the names of the variables `pattern` binds.
+fn pattern_variables<'s>(pattern: &'s ast::Pattern, variables: &mut Vec<&'s str>) {
+    match pattern {
+        ast::Pattern::Binding { name } => variables.push(name),
+        ast::Pattern::Variant {
+            fields: patterns, ..
+        }
+        | ast::Pattern::Tuple { elements: patterns } => {
+            for pattern in patterns {
+                pattern_variables(pattern, variables);
+            }
+        }
+        ast::Pattern::Wildcard | ast::Pattern::Literal { .. } | ast::Pattern::Range { .. } => {}
+    }
+}
+
+/// What the checker knows about the whole program while it visits each definition.
+struct Checker<'a> {
+    /// Arity of each constructor, keyed by name.
+    constructors: HashMap<&'a str, usize>,
+    analysis: exhaustiveness::Analysis<'a>,
+}
+
+impl<'a> Checker<'a> {
+    fn new(program: &'a ast::Program) -> Result<Self, CheckError> {
+        let mut constructors = HashMap::new();
+        let mut siblings = HashMap::new();
+
+        for top_level in &program.definitions {
+            if let ast::TopLevel::EnumDefinition(definition) = top_level {
+                let variants: Vec<_> = definition
+                    .variants
+                    .iter()
+                    .map(|variant| exhaustiveness::Constructor::Variant {
+                        name: &variant.name,
+                        arity: variant.fields.len(),
+                    })
+                    .collect();
+
+                for variant in &definition.variants {
+                    if constructors
+                        .insert(variant.name.as_str(), variant.fields.len())
+                        .is_some()
+                    {
+                        return Err(CheckError::DuplicateConstructor(variant.name.clone()));
+                    }
+                    siblings.insert(variant.name.as_str(), variants.clone());
+                }
+            }
+        }
+
+        Ok(Checker {
+            constructors,
+            analysis: exhaustiveness::Analysis::new(siblings),
+        })
+    }
+
+    fn check_function(
+        &self,
+        function: &'a ast::Function,
+        parent: &Scope,
+        functions: &Functions,
+    ) -> Result<(), CheckError> {
+        let scope = function_scope(function, parent);
+
+        // default values are evaluated before the body, so `return` cannot appear in them
+        for arg in &function.args {
+            if let ast::ParameterKind::Default(default) = &arg.kind {
+                let context = Context {
+                    function: None,
+                    in_loop: false,
+                    can_yield: false,
+                    scope: &scope,
+                    functions,
+                };
+                self.check_expression(default, context)?;
+            }
+        }
+
+        if function.generator && !function.contract.ensures.is_empty() {
+            return Err(CheckError::EnsuresOnGenerator(function.name.clone()));
+        }
+        // 事前条件と事後条件も本体の外で評価される。事後条件では `result` が見える
+        let result_scope = Scope {
+            runs_after_parent: true,
+            ..Scope::new(HashMap::from([("result", None)]), Some(&scope))
+        };
+        let requires = function.contract.requires.iter();
+        let requires = requires.map(|condition| (condition, &scope));
+        let ensures = function.contract.ensures.iter();
+        let ensures = ensures.map(|condition| (condition, &result_scope));
+        for (condition, scope) in requires.chain(ensures) {
+            let context = Context {
+                function: None,
+                in_loop: false,
+                can_yield: false,
+                scope,
+                functions,
+            };
+            self.check_expression(condition, context)?;
+        }
+
+        let context = Context {
+            function: Some(&function.name),
+            in_loop: false,
+            can_yield: function.generator,
+            scope: &scope,
+            functions,
+        };
+
+        self.check_expression(&function.body, context)
+    }
+
+    /// Checks the body of a `for` loop, in which `variable` is bound in a scope of its own.
+    fn check_loop_body(
+        &self,
+        variable: &'a str,
+        body: &'a ast::Expression,
+        context: Context,
+    ) -> Result<(), CheckError> {
+        let mut variables = HashMap::from([(variable, None)]);
+        collect_lets(body, context.function, &mut variables);
+        let scope = Scope::new(variables, Some(context.scope));
+        // ループのスコープは繰り返しの間で共有されるので、本体の束縛は二回目以降に見える
+        scope.declare(scope.variables.keys().copied());
+
+        self.check_expression(
+            body,
+            Context {
+                in_loop: true,
+                scope: &scope,
+                ..context
+            },
+        )
+    }
+
+    fn check_expression(
+        &self,
+        expression: &'a ast::Expression,
+        context: Context,
+    ) -> Result<(), CheckError> {
+        let check_assignable = |name: &str| match context.scope.immutable_declaration(name) {
+            Some(declaration) => Err(CheckError::AssignmentToImmutable(
+                name.to_string(),
+                declaration.to_string(),
+            )),
+            None => Ok(()),
+        };
+        let statement = context;
+        let context = Context {
+            can_yield: false,
+            ..context
+        };
+
+        match expression {
+            ast::Expression::Binary { lhs, rhs, .. } => {
+                self.check_expression(lhs, context)?;
+                self.check_expression(rhs, context)?;
+            }
+            ast::Expression::IntegerLiteral { .. }
+            | ast::Expression::BigIntegerLiteral { .. }
+            | ast::Expression::SizedIntegerLiteral { .. }
+            | ast::Expression::StringLiteral { .. }
+            | ast::Expression::Identifier { .. } => {}
+            ast::Expression::FunctionDefinition { function } => {
+                self.check_function(function, context.scope, context.functions)?;
+            }
+            ast::Expression::Tuple { elements } | ast::Expression::Print { args: elements, .. } => {
+                for element in elements {
+                    self.check_expression(element, context)?;
+                }
+            }
+            ast::Expression::Format { parts } => {
+                for part in parts {
+                    if let ast::FormatPart::Interpolation { expression, .. } = part {
+                        self.check_expression(expression, context)?;
+                    }
+                }
+            }
+            ast::Expression::TupleIndex { tuple, .. } => {
+                self.check_expression(tuple, context)?;
+            }
+            ast::Expression::Assignment { name, expression } => {
+                check_assignable(name)?;
+                self.check_expression(expression, context)?;
+            }
+            ast::Expression::CompoundAssignment {
+                target, expression, ..
+            } => {
+                match target {
+                    ast::AssignmentTarget::Variable { name } => check_assignable(name)?,
+                }
+                self.check_expression(expression, context)?;
+            }
+            ast::Expression::Destructuring {
+                pattern,
+                expression,
+            } => {
+                let mut variables = Vec::new();
+                pattern_variables(pattern, &mut variables);
+                for name in variables {
+                    check_assignable(name)?;
+                }
+                self.check_expression(expression, context)?;
+            }
+            ast::Expression::Let { name, expression } => {
+                self.check_expression(expression, context)?;
+                context.scope.declare([name.as_str()]);
+            }
+            ast::Expression::Block { elements } => {
+                // ブロックで定義される関数は、ブロック全体から見える
+                let functions = Functions {
+                    functions: elements
+                        .iter()
+                        .filter_map(|element| match element {
+                            ast::Expression::FunctionDefinition { function } => {
+                                Some((function.name.as_str(), function.as_ref()))
+                            }
+                            _ => None,
+                        })
+                        .collect(),
+                    parent: Some(context.functions),
+                };
+                let statement = Context {
+                    functions: &functions,
+                    ..statement
+                };
+                for element in elements {
+                    self.check_expression(element, statement)?;
+                }
+            }
+            ast::Expression::While { condition, body } => {
+                let mut variables = HashMap::new();
+                collect_lets(expression, context.function, &mut variables);
+                context.scope.declare(variables.into_keys());
+
+                self.check_expression(condition, context)?;
+                self.check_expression(
+                    body,
+                    Context {
+                        in_loop: true,
+                        ..statement
+                    },
+                )?;
+            }
+            ast::Expression::For {
+                variable,
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => {
+                self.check_expression(start, context)?;
+                self.check_expression(end, context)?;
+                if let Some(step) = step {
+                    self.check_expression(step, context)?;
+                }
+
+                self.check_loop_body(variable, body, statement)?;
+            }
+            ast::Expression::ForEach {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.check_expression(iterable, context)?;
+                self.check_loop_body(variable, body, statement)?;
+            }
+            ast::Expression::If {
+                condition,
+                then_clause,
+                else_clause,
+            } => {
+                self.check_expression(condition, context)?;
+                self.check_expression(then_clause, statement)?;
+                if let Some(else_clause) = else_clause {
+                    self.check_expression(else_clause, statement)?;
+                }
+            }
+            ast::Expression::Match { scrutinee, arms } => {
+                self.check_expression(scrutinee, context)?;
+                self.check_match_arms(arms)?;
+                for arm in arms {
+                    let mut bindings = Vec::new();
+                    pattern_variables(&arm.pattern, &mut bindings);
+                    let mut variables: HashMap<_, _> =
+                        bindings.into_iter().map(|name| (name, None)).collect();
+                    if let Some(guard) = &arm.guard {
+                        collect_lets(guard, context.function, &mut variables);
+                    }
+                    collect_lets(&arm.body, context.function, &mut variables);
+                    let scope = Scope::new(variables, Some(context.scope));
+                    let context = Context {
+                        scope: &scope,
+                        ..context
+                    };
+
+                    if let Some(guard) = &arm.guard {
+                        self.check_expression(guard, context)?;
+                    }
+                    self.check_expression(
+                        &arm.body,
+                        Context {
+                            scope: &scope,
+                            ..statement
+                        },
+                    )?;
+                }
+            }
+            ast::Expression::FunctionCall {
+                name,
+                args,
+                named_args,
+            } => {
+                let named: Vec<_> = named_args.iter().map(|(name, _)| name.as_str()).collect();
+                // 名前付き引数の誤りは、実行時に呼び出しごとに報告される
+                let function = context.functions.lookup(name).filter(|function| {
+                    named.iter().enumerate().all(|(index, arg_name)| {
+                        !named[..index].contains(arg_name)
+                            && function
+                                .args
+                                .iter()
+                                .enumerate()
+                                .any(|(position, parameter)| {
+                                    parameter.name == *arg_name
+                                        && position >= args.len()
+                                        && !matches!(parameter.kind, ast::ParameterKind::Variadic)
+                                })
+                    })
+                });
+                if let Some(function) = function {
+                    match function.check_arity(args.len(), &named) {
+                        Ok(()) => {}
+                        Err(ast::ArityMismatch::NotEnough { expected, given }) => {
+                            return Err(CheckError::NotEnoughArguments(
+                                name.clone(),
+                                expected,
+                                given,
+                            ))
+                        }
+                        Err(ast::ArityMismatch::TooMany { expected, given }) => {
+                            return Err(CheckError::TooManyArguments(name.clone(), expected, given))
+                        }
+                        Err(ast::ArityMismatch::Missing { parameter }) => {
+                            return Err(CheckError::MissingArgument(name.clone(), parameter))
+                        }
+                    }
+                }
+                for arg in args.iter().chain(named_args.iter().map(|(_, arg)| arg)) {
+                    self.check_expression(arg, context)?;
+                }
+            }
+            ast::Expression::Return { expression } => {
+                if context.function.is_none() {
+                    return Err(CheckError::ReturnOutsideFunction);
+                }
+                self.check_expression(expression, context)?;
+            }
+            ast::Expression::Throw { expression } => {
+                self.check_expression(expression, context)?;
+            }
+            ast::Expression::Yield { expression } => {
+                if !statement.can_yield {
+                    return Err(CheckError::MisplacedYield);
+                }
+                self.check_expression(expression, context)?;
+            }
+            ast::Expression::Try {
+                body,
+                catch_clause,
+                finally_clause,
+            } => {
+                self.check_expression(body, statement)?;
+                if let Some(catch_clause) = catch_clause {
+                    let mut variables = HashMap::from([(catch_clause.variable.as_str(), None)]);
+                    collect_lets(&catch_clause.body, context.function, &mut variables);
+                    let scope = Scope::new(variables, Some(context.scope));
+                    self.check_expression(
+                        &catch_clause.body,
+                        Context {
+                            scope: &scope,
+                            ..statement
+                        },
+                    )?;
+                }
+                if let Some(finally_clause) = finally_clause {
+                    self.check_expression(finally_clause, statement)?;
+                }
+            }
+            ast::Expression::Break => {
+                if !context.in_loop {
+                    return Err(CheckError::BreakOutsideLoop);
+                }
+            }
+            ast::Expression::Continue => {
+                if !context.in_loop {
+                    return Err(CheckError::ContinueOutsideLoop);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that every arm of a match can be selected for some value and that every value is
+    /// selected by some arm. Guarded arms are assumed to possibly fail, so they never cover values.
+    fn check_match_arms(&self, arms: &'a [ast::MatchArm]) -> Result<(), CheckError> {
+        let mut covered = Vec::new();
+
+        for (index, arm) in arms.iter().enumerate() {
+            let pattern = self.lower_pattern(&arm.pattern)?;
+            if !self.analysis.is_useful(&covered, &pattern) {
+                return Err(CheckError::UnreachableMatchArm(index + 1));
+            }
+
+            if arm.guard.is_none() {
+                covered.push(pattern);
+            }
+        }
+
+        match self.analysis.missing(&covered) {
+            Some(witness) => Err(CheckError::NonExhaustiveMatch(witness.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    fn lower_pattern(
+        &self,
+        pattern: &'a ast::Pattern,
+    ) -> Result<exhaustiveness::Pattern<'a>, CheckError> {
+        let (name, fields) = match pattern {
+            ast::Pattern::Wildcard => return Ok(exhaustiveness::Pattern::Wildcard),
+            ast::Pattern::Binding { name } => {
+                if !self.constructors.contains_key(name.as_str()) {
+                    return Ok(exhaustiveness::Pattern::Wildcard);
+                }
+
+                // 引数のないコンストラクタの名前は変数ではなくそのコンストラクタにマッチする
+                (name, &[][..])
+            }
+            ast::Pattern::Literal { value } => {
+                return Ok(exhaustiveness::Pattern::Constructor(
+                    exhaustiveness::Constructor::Range(*value, *value),
+                    vec![],
+                ))
+            }
+            ast::Pattern::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                let range = match (inclusive, end.checked_sub(1)) {
+                    (true, _) => exhaustiveness::Constructor::Range(*start, *end),
+                    (false, Some(last)) => exhaustiveness::Constructor::Range(*start, last),
+                    // `start..i64::MIN` is empty whatever `start` is
+                    (false, None) => exhaustiveness::Constructor::Range(1, 0),
+                };
+
+                return Ok(exhaustiveness::Pattern::Constructor(range, vec![]));
+            }
+            ast::Pattern::Variant { name, fields } => (name, &fields[..]),
+            ast::Pattern::Tuple { elements } => {
+                let elements: Vec<_> = elements
+                    .iter()
+                    .map(|element| self.lower_pattern(element))
+                    .collect::<Result<_, _>>()?;
+
+                return Ok(exhaustiveness::Pattern::Constructor(
+                    exhaustiveness::Constructor::Tuple(elements.len()),
+                    elements,
+                ));
+            }
+        };
+
+        let arity = *self
+            .constructors
+            .get(name.as_str())
+            .ok_or_else(|| CheckError::UnknownConstructor(name.clone()))?;
+        if arity != fields.len() {
+            return Err(CheckError::ConstructorPatternArity(
+                name.clone(),
+                arity,
+                fields.len(),
+            ));
+        }
+
+        let fields = fields
+            .iter()
+            .map(|field| self.lower_pattern(field))
+            .collect::<Result<_, _>>()?;
+
+        Ok(exhaustiveness::Pattern::Constructor(
+            exhaustiveness::Constructor::Variant { name, arity },
+            fields,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn break_inside_loop() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::ast_while(
+                    ast::integer(1),
+                    ast::block(vec![ast::ast_if(
+                        ast::integer(1),
+                        ast::ast_break(),
+                        Some(ast::ast_continue()),
+                    )]),
+                ),
+            )],
+        };
+
+        assert!(check(&program).is_ok());
+    }
+
+    #[test]
+    fn continue_inside_for() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::ast_for(
+                    "i",
+                    ast::integer(0),
+                    ast::integer(10),
+                    false,
+                    None,
+                    ast::ast_continue(),
+                ),
+            )],
+        };
+
+        assert!(check(&program).is_ok());
+    }
+
+    #[test]
+    fn break_outside_loop() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::block(vec![ast::ast_break()]),
+            )],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::BreakOutsideLoop => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn continue_outside_loop() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::ast_if(ast::integer(1), ast::ast_continue(), None),
+            )],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::ContinueOutsideLoop => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn return_outside_function() {
+        let program = ast::Program {
+            definitions: vec![ast::difine_global_variable(
+                "answer",
+                ast::ast_return(ast::integer(42)),
+            )],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::ReturnOutsideFunction => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn exhaustive_match() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::ast_match(
+                    ast::integer(3),
+                    vec![
+                        ast::match_arm(ast::literal_pattern(0), None, ast::integer(0)),
+                        ast::match_arm(ast::range_pattern(1, 10, false), None, ast::integer(1)),
+                        ast::match_arm(
+                            ast::binding_pattern("n"),
+                            Some(ast::less_than(ast::identifier("n"), ast::integer(0))),
+                            ast::integer(2),
+                        ),
+                        ast::match_arm(ast::wildcard_pattern(), None, ast::integer(3)),
+                    ],
+                ),
+            )],
+        };
+
+        assert!(check(&program).is_ok());
+    }
+
+    #[test]
+    fn exhaustive_match_by_ranges() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::ast_match(
+                    ast::integer(3),
+                    vec![
+                        ast::match_arm(
+                            ast::range_pattern(0, i64::MAX, true),
+                            None,
+                            ast::integer(0),
+                        ),
+                        ast::match_arm(
+                            ast::range_pattern(i64::MIN, 0, false),
+                            None,
+                            ast::integer(1),
+                        ),
+                    ],
+                ),
+            )],
+        };
+
+        assert!(check(&program).is_ok());
+    }
+
+    #[test]
+    fn non_exhaustive_match() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::ast_match(
+                    ast::integer(3),
+                    vec![
+                        ast::match_arm(
+                            ast::range_pattern(i64::MIN, 0, false),
+                            None,
+                            ast::integer(0),
+                        ),
+                        ast::match_arm(ast::literal_pattern(0), None, ast::integer(1)),
+                        ast::match_arm(
+                            ast::wildcard_pattern(),
+                            Some(ast::integer(1)),
+                            ast::integer(2),
+                        ),
+                    ],
+                ),
+            )],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::NonExhaustiveMatch(witness) => assert_eq!(witness, "1"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn unreachable_match_arm() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::ast_match(
+                    ast::integer(3),
+                    vec![
+                        ast::match_arm(ast::range_pattern(1, 9, true), None, ast::integer(0)),
+                        ast::match_arm(ast::literal_pattern(5), None, ast::integer(1)),
+                        ast::match_arm(ast::wildcard_pattern(), None, ast::integer(2)),
+                    ],
+                ),
+            )],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::UnreachableMatchArm(arm) => assert_eq!(arm, 2),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn exhaustive_enum_match() {
+        let program = ast::Program {
+            definitions: vec![
+                ast::define_enum("Option", &[("None", &[]), ("Some", &["value"])]),
+                ast::define_function(
+                    "main",
+                    &[],
+                    ast::ast_match(
+                        ast::identifier("None"),
+                        vec![
+                            ast::match_arm(
+                                ast::variant_pattern("Some", vec![ast::literal_pattern(0)]),
+                                None,
+                                ast::integer(0),
+                            ),
+                            ast::match_arm(
+                                ast::variant_pattern("Some", vec![ast::binding_pattern("n")]),
+                                None,
+                                ast::identifier("n"),
+                            ),
+                            ast::match_arm(ast::binding_pattern("None"), None, ast::integer(1)),
+                        ],
+                    ),
+                ),
+            ],
+        };
+
+        assert!(check(&program).is_ok());
+    }
+
+    #[test]
+    fn unreachable_enum_match_arm() {
+        let program = ast::Program {
+            definitions: vec![
+                ast::define_enum("Option", &[("None", &[]), ("Some", &["value"])]),
+                ast::define_function(
+                    "main",
+                    &[],
+                    ast::ast_match(
+                        ast::identifier("None"),
+                        vec![
+                            ast::match_arm(
+                                ast::variant_pattern("Some", vec![ast::wildcard_pattern()]),
+                                None,
+                                ast::integer(0),
+                            ),
+                            ast::match_arm(
+                                ast::variant_pattern("Some", vec![ast::literal_pattern(3)]),
+                                None,
+                                ast::integer(3),
+                            ),
+                            ast::match_arm(ast::wildcard_pattern(), None, ast::integer(1)),
+                        ],
+                    ),
+                ),
+            ],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::UnreachableMatchArm(arm) => assert_eq!(arm, 2),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn non_exhaustive_enum_match() {
+        let program = ast::Program {
+            definitions: vec![
+                ast::define_enum("Option", &[("None", &[]), ("Some", &["value"])]),
+                ast::define_function(
+                    "main",
+                    &[],
+                    ast::ast_match(
+                        ast::identifier("None"),
+                        vec![ast::match_arm(
+                            ast::variant_pattern("Some", vec![ast::wildcard_pattern()]),
+                            None,
+                            ast::integer(0),
+                        )],
+                    ),
+                ),
+            ],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::NonExhaustiveMatch(witness) => assert_eq!(witness, "None"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn pattern_with_wrong_arity() {
+        let program = ast::Program {
+            definitions: vec![
+                ast::define_enum("Option", &[("None", &[]), ("Some", &["value"])]),
+                ast::define_function(
+                    "main",
+                    &[],
+                    ast::ast_match(
+                        ast::identifier("None"),
+                        vec![ast::match_arm(
+                            ast::variant_pattern("Some", vec![]),
+                            None,
+                            ast::integer(0),
+                        )],
+                    ),
+                ),
+            ],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::ConstructorPatternArity(name, expected, actual) => {
+                assert_eq!((name.as_str(), expected, actual), ("Some", 1, 0))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn duplicate_constructor() {
+        let program = ast::Program {
+            definitions: vec![
+                ast::define_enum("Option", &[("None", &[]), ("Some", &["value"])]),
+                ast::define_enum("Maybe", &[("None", &[])]),
+            ],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::DuplicateConstructor(name) => assert_eq!(name, "None"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn non_exhaustive_tuple_match() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::ast_match(
+                    ast::tuple(vec![ast::integer(1), ast::integer(2)]),
+                    vec![
+                        ast::match_arm(
+                            ast::tuple_pattern(vec![
+                                ast::range_pattern(i64::MIN, 0, false),
+                                ast::wildcard_pattern(),
+                            ]),
+                            None,
+                            ast::integer(0),
+                        ),
+                        ast::match_arm(
+                            ast::tuple_pattern(vec![
+                                ast::wildcard_pattern(),
+                                ast::literal_pattern(0),
+                            ]),
+                            None,
+                            ast::integer(1),
+                        ),
+                    ],
+                ),
+            )],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::NonExhaustiveMatch(witness) => {
+                assert_eq!(witness, "(0, -9223372036854775808)")
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn assignment_to_let_binding() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::block(vec![
+                    ast::ast_let("total", ast::integer(0)),
+                    ast::ast_for(
+                        "i",
+                        ast::integer(0),
+                        ast::integer(3),
+                        false,
+                        None,
+                        ast::compound_assignment(
+                            ast::Operator::Add,
+                            ast::variable_target("total"),
+                            ast::identifier("i"),
+                        ),
+                    ),
+                ]),
+            )],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::AssignmentToImmutable(name, declaration) => assert_eq!(
+                (name.as_str(), declaration.as_str()),
+                ("total", "`let total` in function `main`")
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn let_in_loop_does_not_freeze_outer_variable() {
+        let program = ast::Program {
+            definitions: vec![
+                ast::difine_global_variable("x", ast::integer(0)),
+                ast::define_function(
+                    "main",
+                    &[],
+                    ast::block(vec![
+                        ast::ast_for(
+                            "i",
+                            ast::integer(0),
+                            ast::integer(3),
+                            false,
+                            None,
+                            ast::ast_let("x", ast::identifier("i")),
+                        ),
+                        ast::destructuring(
+                            ast::tuple_pattern(vec![
+                                ast::binding_pattern("x"),
+                                ast::wildcard_pattern(),
+                            ]),
+                            ast::tuple(vec![ast::integer(1), ast::integer(2)]),
+                        ),
+                    ]),
+                ),
+            ],
+        };
+
+        assert!(check(&program).is_ok());
+    }
+
+    #[test]
+    fn nested_function_cannot_assign_enclosing_let() {
+        let program = ast::Program {
+            definitions: vec![ast::define_function(
+                "main",
+                &[],
+                ast::block(vec![
+                    ast::ast_let("count", ast::integer(0)),
+                    ast::nested_function(
+                        "bump",
+                        &[],
+                        ast::increment(ast::variable_target("count")),
+                    ),
+                ]),
+            )],
+        };
+
+        match check(&program).unwrap_err() {
+            CheckError::AssignmentToImmutable(name, declaration) => assert_eq!(
+                (name.as_str(), declaration.as_str()),
+                ("count", "`let count` in function `main`")
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn assignment_before_let_binding() {
+        // define main() { x = 1; x = 2; let x = 3; x; }
+        let program = |body| ast::Program {
+            definitions: vec![ast::define_function("main", &[], body)],
+        };
+        let body = ast::block(vec![
+            ast::assignment("x", ast::integer(1)),
+            ast::assignment("x", ast::integer(2)),
+            ast::ast_let("x", ast::integer(3)),
+            ast::identifier("x"),
+        ]);
+        assert!(check(&program(body)).is_ok());
+
+        // 二回目の繰り返しでは、前の繰り返しの `let` で束縛された変数に代入することになる
+        let body = ast::ast_while(
+            ast::integer(1),
+            ast::block(vec![
+                ast::assignment("x", ast::integer(1)),
+                ast::ast_let("x", ast::integer(2)),
+            ]),
+        );
+        assert!(matches!(
+            check(&program(body)).unwrap_err(),
+            CheckError::AssignmentToImmutable(..)
+        ));
+
+        // 関数は定義より後で呼ばれることがある
+        let body = ast::block(vec![
+            ast::nested_function("reset", &[], ast::assignment("x", ast::integer(0))),
+            ast::ast_let("x", ast::integer(1)),
+        ]);
+        assert!(matches!(
+            check(&program(body)).unwrap_err(),
+            CheckError::AssignmentToImmutable(..)
+        ));
+    }
+
+    #[test]
+    fn call_arity() {
+        // define pair(a, b = 0) = (a, b);
+        let pair = || {
+            ast::define_function_with_parameters(
+                "pair",
+                &[
+                    ast::parameter("a"),
+                    ast::default_parameter("b", ast::integer(0)),
+                ],
+                ast::tuple(vec![ast::identifier("a"), ast::identifier("b")]),
+            )
+        };
+        let program = |call| ast::Program {
+            definitions: vec![pair(), ast::define_function("main", &[], call)],
+        };
+
+        let call = ast::call_with_named("pair", vec![], vec![("a", ast::integer(1))]);
+        assert!(check(&program(call)).is_ok());
+
+        let call = ast::call("pair", vec![]);
+        match check(&program(call)).unwrap_err() {
+            CheckError::NotEnoughArguments(name, expected, given) => {
+                assert_eq!((name.as_str(), expected, given), ("pair", 1, 0))
+            }
+            _ => unreachable!(),
+        }
+
+        let call = ast::call("pair", vec![ast::integer(1); 3]);
+        match check(&program(call)).unwrap_err() {
+            CheckError::TooManyArguments(name, expected, given) => {
+                assert_eq!((name.as_str(), expected, given), ("pair", 2, 3))
+            }
+            _ => unreachable!(),
+        }
+
+        // ブロックで定義された同名の関数が優先される
+        let call = ast::block(vec![
+            ast::nested_function("pair", &[], ast::integer(0)),
+            ast::call("pair", vec![ast::integer(1)]),
+        ]);
+        assert!(matches!(
+            check(&program(call)).unwrap_err(),
+            CheckError::TooManyArguments(..)
+        ));
+    }
+}
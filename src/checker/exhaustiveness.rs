@@ -0,0 +1,267 @@
+//! Exhaustiveness and reachability of match arms.
+//! Ref. L. Maranget, "Warnings for pattern matching", Journal of Functional Programming, 2007.
+
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, PartialEq)]
+pub enum Constructor<'a> {
+    /// The integers from `.0` to `.1`, both inclusive. Empty when `.0 > .1`.
+    Range(i64, i64),
+    Variant {
+        name: &'a str,
+        arity: usize,
+    },
+    /// The tuples of the given size, which is their only constructor.
+    Tuple(usize),
+}
+
+impl Constructor<'_> {
+    fn arity(&self) -> usize {
+        match self {
+            Constructor::Range(..) => 0,
+            Constructor::Variant { arity, .. } | Constructor::Tuple(arity) => *arity,
+        }
+    }
+
+    /// Whether every value built by `other` is also built by `self`.
+    fn covers(&self, other: &Constructor) -> bool {
+        match (self, other) {
+            (Constructor::Range(start, end), Constructor::Range(other_start, other_end)) => {
+                start <= other_start && other_end <= end
+            }
+            (Constructor::Variant { name, .. }, Constructor::Variant { name: other, .. }) => {
+                name == other
+            }
+            (Constructor::Tuple(arity), Constructor::Tuple(other)) => arity == other,
+            _ => false,
+        }
+    }
+}
+
+/// A pattern reduced to what matters for the analysis: bindings become wildcards.
+#[derive(Clone)]
+pub enum Pattern<'a> {
+    Wildcard,
+    Constructor(Constructor<'a>, Vec<Pattern<'a>>),
+}
+
+impl fmt::Display for Pattern<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Pattern::Wildcard => write!(f, "_"),
+            Pattern::Constructor(Constructor::Range(start, _), _) => write!(f, "{}", start),
+            Pattern::Constructor(Constructor::Variant { name, .. }, fields) => {
+                write!(f, "{}", name)?;
+                if !fields.is_empty() {
+                    write!(f, "(")?;
+                    for (index, field) in fields.iter().enumerate() {
+                        if index > 0 {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "{}", field)?;
+                    }
+                    write!(f, ")")?;
+                }
+
+                Ok(())
+            }
+            Pattern::Constructor(Constructor::Tuple(_), elements) => {
+                write!(f, "(")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                if elements.len() == 1 {
+                    write!(f, ",")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+pub struct Analysis<'a> {
+    /// Every constructor of the enum each variant belongs to, keyed by variant name.
+    siblings: HashMap<&'a str, Vec<Constructor<'a>>>,
+}
+
+impl<'a> Analysis<'a> {
+    pub fn new(siblings: HashMap<&'a str, Vec<Constructor<'a>>>) -> Self {
+        Analysis { siblings }
+    }
+
+    /// Whether some value matched by `pattern` is matched by none of `previous`.
+    pub fn is_useful(&self, previous: &[Pattern<'a>], pattern: &Pattern<'a>) -> bool {
+        let rows: Vec<_> = previous.iter().map(|row| vec![row.clone()]).collect();
+
+        self.witness(&rows, std::slice::from_ref(pattern)).is_some()
+    }
+
+    /// A value matched by none of `patterns`, if any.
+    pub fn missing(&self, patterns: &[Pattern<'a>]) -> Option<Pattern<'a>> {
+        let rows: Vec<_> = patterns.iter().map(|row| vec![row.clone()]).collect();
+
+        self.witness(&rows, &[Pattern::Wildcard])
+            .map(|mut witness| witness.remove(0))
+    }
+
+    /// Finds an instance of `vector` which no row of the matrix `rows` matches.
+    fn witness(
+        &self,
+        rows: &[Vec<Pattern<'a>>],
+        vector: &[Pattern<'a>],
+    ) -> Option<Vec<Pattern<'a>>> {
+        let (head, tail) = match vector.split_first() {
+            Some(split) => split,
+            None => return if rows.is_empty() { Some(vec![]) } else { None },
+        };
+
+        let heads: Vec<&Constructor> = rows
+            .iter()
+            .filter_map(|row| match &row[0] {
+                Pattern::Constructor(constructor, _) => Some(constructor),
+                Pattern::Wildcard => None,
+            })
+            .collect();
+
+        match head {
+            Pattern::Constructor(constructor, fields) => {
+                for piece in split(constructor, &heads) {
+                    let vector: Vec<_> = fields.iter().chain(tail).cloned().collect();
+                    if let Some(witness) = self.witness(&specialize(rows, &piece), &vector) {
+                        return Some(rebuild(piece, witness));
+                    }
+                }
+
+                None
+            }
+            Pattern::Wildcard => {
+                if heads.is_empty() {
+                    return self.witness(&default_rows(rows), tail).map(|mut witness| {
+                        witness.insert(0, Pattern::Wildcard);
+                        witness
+                    });
+                }
+
+                let all = self.all_constructors(&heads);
+                if let Some(missing) = all
+                    .iter()
+                    .find(|constructor| !heads.iter().any(|head| head.covers(constructor)))
+                {
+                    let fields = vec![Pattern::Wildcard; missing.arity()];
+                    return self.witness(&default_rows(rows), tail).map(|mut witness| {
+                        witness.insert(0, Pattern::Constructor(missing.clone(), fields));
+                        witness
+                    });
+                }
+
+                for constructor in all {
+                    let vector: Vec<_> = vec![Pattern::Wildcard; constructor.arity()]
+                        .into_iter()
+                        .chain(tail.iter().cloned())
+                        .collect();
+                    if let Some(witness) = self.witness(&specialize(rows, &constructor), &vector) {
+                        return Some(rebuild(constructor, witness));
+                    }
+                }
+
+                None
+            }
+        }
+    }
+
+    /// Every constructor of the types the constructors in `heads` belong to, with integers split
+    /// into ranges that each head either covers entirely or not at all.
+    fn all_constructors(&self, heads: &[&Constructor<'a>]) -> Vec<Constructor<'a>> {
+        let mut all = Vec::new();
+
+        if heads
+            .iter()
+            .any(|head| matches!(head, Constructor::Range(..)))
+        {
+            all.extend(split(&Constructor::Range(i64::MIN, i64::MAX), heads));
+        }
+
+        for head in heads {
+            let constructors = match head {
+                Constructor::Range(..) => continue,
+                Constructor::Variant { name, .. } => self.siblings[name].clone(),
+                Constructor::Tuple(_) => vec![(*head).clone()],
+            };
+
+            for constructor in constructors {
+                if !all.contains(&constructor) {
+                    all.push(constructor);
+                }
+            }
+        }
+
+        all
+    }
+}
+
+/// Splits `constructor` into pieces such that each of `heads` covers a piece entirely or not at
+/// all. Only ranges are ever split.
+fn split<'a>(constructor: &Constructor<'a>, heads: &[&Constructor]) -> Vec<Constructor<'a>> {
+    let (start, end) = match *constructor {
+        Constructor::Range(start, end) => (start as i128, end as i128),
+        Constructor::Variant { .. } | Constructor::Tuple(_) => return vec![constructor.clone()],
+    };
+    if start > end {
+        return vec![];
+    }
+
+    // i128 so that `end + 1` cannot overflow at the ends of i64
+    let mut boundaries = vec![start, end + 1];
+    for head in heads {
+        if let Constructor::Range(head_start, head_end) = **head {
+            for boundary in [head_start as i128, head_end as i128 + 1] {
+                if start < boundary && boundary <= end {
+                    boundaries.push(boundary);
+                }
+            }
+        }
+    }
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    boundaries
+        .windows(2)
+        .map(|window| Constructor::Range(window[0] as i64, (window[1] - 1) as i64))
+        .collect()
+}
+
+/// The rows which match values built by `constructor`, with the head replaced by its fields.
+fn specialize<'a>(rows: &[Vec<Pattern<'a>>], constructor: &Constructor) -> Vec<Vec<Pattern<'a>>> {
+    rows.iter()
+        .filter_map(|row| {
+            let fields = match &row[0] {
+                Pattern::Wildcard => vec![Pattern::Wildcard; constructor.arity()],
+                Pattern::Constructor(head, fields) if head.covers(constructor) => fields.clone(),
+                Pattern::Constructor(..) => return None,
+            };
+
+            Some(fields.into_iter().chain(row[1..].iter().cloned()).collect())
+        })
+        .collect()
+}
+
+/// The rows which match any value through a wildcard head, with the head removed.
+fn default_rows<'a>(rows: &[Vec<Pattern<'a>>]) -> Vec<Vec<Pattern<'a>>> {
+    rows.iter()
+        .filter(|row| matches!(row[0], Pattern::Wildcard))
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+/// Folds the leading fields of `witness` back into a pattern for `constructor`.
+fn rebuild<'a>(constructor: Constructor<'a>, mut witness: Vec<Pattern<'a>>) -> Vec<Pattern<'a>> {
+    let rest = witness.split_off(constructor.arity());
+
+    std::iter::once(Pattern::Constructor(constructor, witness))
+        .chain(rest)
+        .collect()
+}
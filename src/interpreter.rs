@@ -1,8 +1,29 @@
 use std::collections::HashMap;
+use std::io::{self, Write};
+use std::mem;
+use std::rc::Rc;
 
 use crate::ast;
+use crate::checker::{self, CheckError};
+use crate::macros::{self, MacroError};
+use crate::modules::{self, FileLoader, ModuleError, ModuleLoader};
+use crate::value::Value;
+use code::Code;
+use num_bigint::BigInt;
 use thiserror::Error;
 
+mod arithmetic;
+mod builtins;
+mod code;
+mod contracts;
+mod format;
+mod frames;
+mod generator;
+mod machine;
+
+pub use arithmetic::ArithmeticMode;
+pub(crate) use generator::GeneratorState;
+
 #[derive(Clone)]
 struct Environment<T> {
     bindings: HashMap<String, T>,
@@ -10,13 +31,61 @@ struct Environment<T> {
 }
 
 impl<T> Environment<T> {
-    fn find_binding(&self, name: &str) -> Option<&HashMap<String, T>> {
-        self.bindings.get(name).map(|_| &self.bindings).or_else(|| {
+    fn new() -> Self {
+        Environment {
+            bindings: HashMap::new(),
+            next: None,
+        }
+    }
+
+    /// Opens a new innermost scope whose bindings shadow the current ones.
+    fn push_scope(&mut self) {
+        let outer = std::mem::replace(self, Environment::new());
+        self.next = Some(Box::new(outer));
+    }
+
+    /// Discards the innermost scope opened by `push_scope`.
+    fn pop_scope(&mut self) {
+        let outer = self
+            .next
+            .take()
+            .expect("pop_scope must be paired with push_scope");
+        *self = *outer;
+    }
+
+    /// Opens a new innermost scope holding `bindings`.
+    fn push_bindings(&mut self, bindings: HashMap<String, T>) {
+        self.push_scope();
+        self.bindings = bindings;
+    }
+
+    /// Discards the innermost scope, handing back its bindings.
+    fn pop_bindings(&mut self) -> HashMap<String, T> {
+        let bindings = std::mem::take(&mut self.bindings);
+        self.pop_scope();
+        bindings
+    }
+
+    fn find_binding_mut(&mut self, name: &str) -> Option<&mut HashMap<String, T>> {
+        if self.bindings.contains_key(name) {
+            Some(&mut self.bindings)
+        } else {
             self.next
-                .as_deref()
-                .map(|env| env.find_binding(name))
-                .flatten()
-        })
+                .as_deref_mut()
+                .and_then(|env| env.find_binding_mut(name))
+        }
+    }
+
+    /// The number of scopes enclosing the innermost one.
+    fn depth(&self) -> usize {
+        self.next.as_deref().map_or(0, |next| next.depth() + 1)
+    }
+
+    fn find_binding(&self, name: &str) -> Option<&HashMap<String, T>> {
+        self.bindings
+            .get(name)
+            .map(|_| &self.bindings)
+            .or_else(|| self.next.as_deref().and_then(|env| env.find_binding(name)))
     }
 }
 
@@ -26,20 +95,238 @@ pub enum InterpreterError {
     MainNotPresent,
     #[error("Zero is an invalid denominator")]
     ZeroDivision,
+    #[error("{1} {0} {2} overflows")]
+    IntegerOverflow(String, i64, i64),
+    #[error("{0} is beyond the range of 64-bit integers")]
+    IntegerOutOfRange(Value),
+    #[error("`{0}` cannot mix integers of types {1} and {2}")]
+    MixedIntegerTypes(String, String, String),
+    #[error("{0} does not fit in {1}")]
+    ConversionOutOfRange(Value, String),
     #[error("Variable {0} is not present in this environment")]
     VariableNotPresent(String),
     #[error("`else_clause` should not be None when the `if` condition is not met")]
     ElseClauseNoneUnderIfConditionNotMet,
-    #[error("An argument for `{0}` is not provided")]
-    NotEnoughArguments(String),
+    #[error("Function {0} takes at least {1} argument(s) but {2} were given")]
+    NotEnoughArguments(String, usize, usize),
+    #[error("Function {0} takes at most {1} argument(s) but {2} were given")]
+    TooManyArguments(String, usize, usize),
+    #[error("Calls are nested deeper than {0}, most recently in {}", .1.join(" <- "))]
+    StackOverflow(usize, Vec<String>),
     #[error("Function {0} is not found")]
     FunctionNotFound(String),
+    #[error("`{0}` has no parameter named `{1}`")]
+    UnknownNamedArgument(String, String),
+    #[error("The argument `{1}` of `{0}` is given more than once")]
+    DuplicateArgument(String, String),
+    #[error("The argument `{1}` of `{0}` is not given")]
+    MissingArgument(String, String),
+    #[error("No arm of the match accepts {0}")]
+    NoMatchingArm(Value),
+    #[error("{0} is not an integer")]
+    NotAnInteger(Value),
+    #[error("Constructor {0} takes {1} field(s) but {2} were given")]
+    ConstructorArity(String, usize, usize),
+    #[error("{0} is not a tuple")]
+    NotATuple(Value),
+    #[error("Index {1} is out of bounds for {0}")]
+    TupleIndexOutOfBounds(Value, usize),
+    #[error("{0} cannot be destructured into {1} element(s)")]
+    DestructuringMismatch(Value, usize),
+    #[error("The step of a `for` loop must not be zero")]
+    ZeroStep,
+    #[error("`{0}` is evaluated outside of a loop")]
+    ControlFlowOutsideLoop(String),
+    #[error("Builtin {0} takes {1} argument(s) but {2} were given")]
+    BuiltinArity(String, usize, usize),
+    #[error("{0} is not a string")]
+    NotAString(Value),
+    #[error("{0} is not an error")]
+    NotAnError(Value),
+    #[error("Uncaught exception: {0}")]
+    Uncaught(Value),
+    #[error("{0} cannot be iterated")]
+    NotIterable(Value),
+    #[error("{0} is not a generator")]
+    NotAGenerator(Value),
+    #[error("Generator {0} is resumed while it is running")]
+    GeneratorRunning(String),
+    #[error("Generator {0} has no more values")]
+    GeneratorExhausted(String),
+    #[error("`yield` is evaluated outside of a generator")]
+    YieldOutsideGenerator,
+    #[error("{0}({}) violates `{1}`", contracts::describe_arguments(.2))]
+    ContractViolation(String, String, Vec<(String, Value)>),
+    #[error(transparent)]
+    Check(#[from] CheckError),
+    #[error(transparent)]
+    Macro(#[from] MacroError),
+    #[error(transparent)]
+    Module(#[from] ModuleError),
+    #[error("Cannot write the output: {0}")]
+    Output(io::Error),
+    /// Raised by `exit(code)`. It passes through `catch` clauses, so that only `finally` clauses
+    /// run on the way out.
+    #[error("The program exits with status {0}")]
+    Exit(i64),
 }
 
-#[derive(Clone)]
+impl InterpreterError {
+    fn arity(function: &str, mismatch: ast::ArityMismatch) -> Self {
+        match mismatch {
+            ast::ArityMismatch::NotEnough { expected, given } => {
+                InterpreterError::NotEnoughArguments(function.to_string(), expected, given)
+            }
+            ast::ArityMismatch::TooMany { expected, given } => {
+                InterpreterError::TooManyArguments(function.to_string(), expected, given)
+            }
+            ast::ArityMismatch::Missing { parameter } => {
+                InterpreterError::MissingArgument(function.to_string(), parameter)
+            }
+        }
+    }
+
+    /// The name of the error, which `catch` exposes as its kind.
+    fn kind(&self) -> &'static str {
+        match self {
+            InterpreterError::MainNotPresent => "MainNotPresent",
+            InterpreterError::ZeroDivision => "ZeroDivision",
+            InterpreterError::IntegerOverflow(..) => "IntegerOverflow",
+            InterpreterError::IntegerOutOfRange(_) => "IntegerOutOfRange",
+            InterpreterError::MixedIntegerTypes(..) => "MixedIntegerTypes",
+            InterpreterError::ConversionOutOfRange(..) => "ConversionOutOfRange",
+            InterpreterError::VariableNotPresent(_) => "VariableNotPresent",
+            InterpreterError::ElseClauseNoneUnderIfConditionNotMet => {
+                "ElseClauseNoneUnderIfConditionNotMet"
+            }
+            InterpreterError::NotEnoughArguments(..) => "NotEnoughArguments",
+            InterpreterError::TooManyArguments(..) => "TooManyArguments",
+            InterpreterError::StackOverflow(..) => "StackOverflow",
+            InterpreterError::FunctionNotFound(_) => "FunctionNotFound",
+            InterpreterError::UnknownNamedArgument(..) => "UnknownNamedArgument",
+            InterpreterError::DuplicateArgument(..) => "DuplicateArgument",
+            InterpreterError::MissingArgument(..) => "MissingArgument",
+            InterpreterError::NoMatchingArm(_) => "NoMatchingArm",
+            InterpreterError::NotAnInteger(_) => "NotAnInteger",
+            InterpreterError::ConstructorArity(..) => "ConstructorArity",
+            InterpreterError::NotATuple(_) => "NotATuple",
+            InterpreterError::TupleIndexOutOfBounds(..) => "TupleIndexOutOfBounds",
+            InterpreterError::DestructuringMismatch(..) => "DestructuringMismatch",
+            InterpreterError::ZeroStep => "ZeroStep",
+            InterpreterError::ControlFlowOutsideLoop(_) => "ControlFlowOutsideLoop",
+            InterpreterError::BuiltinArity(..) => "BuiltinArity",
+            InterpreterError::NotAString(_) => "NotAString",
+            InterpreterError::NotAnError(_) => "NotAnError",
+            InterpreterError::Uncaught(_) => "Uncaught",
+            InterpreterError::NotIterable(_) => "NotIterable",
+            InterpreterError::NotAGenerator(_) => "NotAGenerator",
+            InterpreterError::GeneratorRunning(_) => "GeneratorRunning",
+            InterpreterError::GeneratorExhausted(_) => "GeneratorExhausted",
+            InterpreterError::YieldOutsideGenerator => "YieldOutsideGenerator",
+            InterpreterError::ContractViolation(..) => "ContractViolation",
+            InterpreterError::Check(_) => "Check",
+            InterpreterError::Macro(_) => "Macro",
+            InterpreterError::Module(_) => "Module",
+            InterpreterError::Output(_) => "Output",
+            InterpreterError::Exit(_) => "Exit",
+        }
+    }
+
+    /// The value a `catch` clause receives for this error: the thrown value itself, or a
+    /// description of an error raised by the interpreter.
+    fn into_exception(self) -> Value {
+        match self {
+            InterpreterError::Uncaught(value) => value,
+            error => Value::Error {
+                kind: error.kind().to_string(),
+                message: error.to_string(),
+            },
+        }
+    }
+}
+
+/// The ways an evaluation can leave an expression other than by producing its value.
+/// `Return`, `Break` and `Continue` are not errors: they unwind only up to the enclosing
+/// function call or loop, which consumes them.
+enum Unwind {
+    Return(Value),
+    Break,
+    Continue,
+    Error(InterpreterError),
+}
+
+impl From<InterpreterError> for Unwind {
+    fn from(error: InterpreterError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+impl Unwind {
+    /// Converts a signal which escaped every loop into an error.
+    /// `Return` is handled by the caller since its meaning depends on where it is caught.
+    fn into_error(self) -> InterpreterError {
+        match self {
+            Unwind::Return(_) => unreachable!("`return` must be consumed by the caller"),
+            Unwind::Break => InterpreterError::ControlFlowOutsideLoop("break".to_string()),
+            Unwind::Continue => InterpreterError::ControlFlowOutsideLoop("continue".to_string()),
+            Unwind::Error(error) => error,
+        }
+    }
+}
+
+impl Value {
+    fn as_integer(&self) -> Result<i64, InterpreterError> {
+        match self {
+            Value::Integer(value) | Value::SizedInteger(_, value) => Ok(*value),
+            Value::BigInteger(_) => Err(InterpreterError::IntegerOutOfRange(self.clone())),
+            _ => Err(InterpreterError::NotAnInteger(self.clone())),
+        }
+    }
+
+    fn as_big_integer(&self) -> Result<BigInt, InterpreterError> {
+        match self {
+            Value::Integer(value) | Value::SizedInteger(_, value) => Ok(BigInt::from(*value)),
+            Value::BigInteger(value) => Ok(value.clone()),
+            _ => Err(InterpreterError::NotAnInteger(self.clone())),
+        }
+    }
+}
+
+/// Whether `current` has not yet passed `end` when counting from a range's start by `step`.
+fn in_range(current: i64, end: i64, inclusive: bool, step: i64) -> bool {
+    match (step > 0, inclusive) {
+        (true, true) => current <= end,
+        (true, false) => current < end,
+        (false, true) => current >= end,
+        (false, false) => current > end,
+    }
+}
+
+/// The default limit of `set_max_call_depth` on how many calls can be open at once.
+const DEFAULT_MAX_CALL_DEPTH: usize = 100_000;
+
 pub struct Interpreter {
-    variable_environment: Environment<i64>,
-    function_environment: Environment<ast::Function>,
+    /// The variables of the running call, or of the top-level statements outside of any call.
+    variable_environment: Environment<Value>,
+    /// The global variables and constants, which every call sees.
+    globals: HashMap<String, Value>,
+    function_environment: Environment<frames::FunctionBinding>,
+    /// The calls which have not returned yet, outermost first.
+    call_stack: Vec<frames::CallFrame>,
+    constructors: HashMap<String, ast::Variant>,
+    /// Whether the contracts of functions are checked on each call.
+    check_contracts: bool,
+    arithmetic_mode: ArithmeticMode,
+    /// How deep calls can be nested before `StackOverflow` is raised.
+    max_call_depth: usize,
+    /// Whether the top-level statements of a program are running, whose variables are global.
+    running_statements: bool,
+    module_loader: Rc<dyn ModuleLoader>,
+    /// The command-line arguments `args()` gives to the program.
+    args: Vec<String>,
+    /// What `print` and `println` have written while the output is captured. `None` while they
+    /// write to the standard output.
+    output: Option<String>,
 }
 
 impl Default for Interpreter {
@@ -51,191 +338,312 @@ impl Default for Interpreter {
 impl Interpreter {
     pub fn new() -> Self {
         Interpreter {
-            variable_environment: Environment {
-                bindings: HashMap::new(),
-                next: None,
-            },
-            function_environment: Environment {
-                bindings: HashMap::new(),
-                next: None,
-            },
+            variable_environment: Environment::new(),
+            globals: HashMap::new(),
+            function_environment: Environment::new(),
+            call_stack: vec![],
+            constructors: HashMap::new(),
+            check_contracts: true,
+            arithmetic_mode: ArithmeticMode::default(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            running_statements: false,
+            module_loader: Rc::new(FileLoader::new(".")),
+            args: vec![],
+            output: None,
         }
     }
 
-    pub fn interpret(&mut self, expression: &ast::Expression) -> Result<i64, InterpreterError> {
-        let value = match expression {
-            ast::Expression::Binary { operator, lhs, rhs } => {
-                let lhs = self.interpret(lhs)?;
-                let rhs = self.interpret(rhs)?;
+    /// Makes `print` and `println` write into a buffer instead of the standard output.
+    pub fn capture_output(&mut self) {
+        self.output.get_or_insert_with(String::new);
+    }
 
-                match operator {
-                    ast::Operator::Add => lhs + rhs,
-                    ast::Operator::Subtract => lhs - rhs,
-                    ast::Operator::Multiply => lhs * rhs,
-                    ast::Operator::Divide => {
-                        if rhs == 0 {
-                            return Err(InterpreterError::ZeroDivision);
-                        }
+    /// Gives back what has been captured since the last call, leaving the buffer empty.
+    pub fn take_output(&mut self) -> String {
+        self.output.as_mut().map(mem::take).unwrap_or_default()
+    }
 
-                        lhs / rhs
-                    }
-                    ast::Operator::LessThan => {
-                        if lhs < rhs {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    ast::Operator::LessOrEqual => {
-                        if lhs <= rhs {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    ast::Operator::GreaterThan => {
-                        if lhs > rhs {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    ast::Operator::GreaterOrEqual => {
-                        if lhs >= rhs {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    ast::Operator::EqualEqual => {
-                        if lhs == rhs {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                    ast::Operator::NotEqual => {
-                        if lhs != rhs {
-                            1
-                        } else {
-                            0
-                        }
-                    }
-                }
-            }
-            ast::Expression::IntegerLiteral { value } => *value,
-            ast::Expression::Identifier { name } => *self
-                .variable_environment
-                .find_binding(name)
-                .map(|bindings| bindings.get(name))
-                .flatten()
-                .ok_or_else(|| InterpreterError::VariableNotPresent(name.clone()))?,
-            ast::Expression::Assignment { name, expression } => {
-                let value = self.interpret(expression)?;
-                self.variable_environment
-                    .bindings
-                    .insert(name.clone(), value);
-                value
-            }
-            ast::Expression::Block { elements } => {
-                let mut value = 0;
-                for element in elements {
-                    value = self.interpret(element)?;
-                }
-                value
+    /// Writes `text` where the output of the program goes.
+    fn write_output(&mut self, text: &str) -> Result<(), InterpreterError> {
+        match &mut self.output {
+            Some(buffer) => buffer.push_str(text),
+            None => {
+                let mut stdout = io::stdout().lock();
+                stdout
+                    .write_all(text.as_bytes())
+                    .and_then(|()| stdout.flush())
+                    .map_err(InterpreterError::Output)?;
             }
-            ast::Expression::While { condition, body } => {
-                loop {
-                    let condition = self.interpret(condition)?;
-                    if condition != 0 {
-                        self.interpret(body)?;
-                    } else {
-                        break;
-                    }
-                }
+        }
 
-                1
-            }
-            ast::Expression::If {
-                condition,
-                then_clause,
-                else_clause,
-            } => {
-                let condition = self.interpret(condition)?;
-                if condition != 0 {
-                    self.interpret(then_clause)?
-                } else {
-                    let expression = else_clause
-                        .as_ref()
-                        .ok_or(InterpreterError::ElseClauseNoneUnderIfConditionNotMet)?;
-
-                    self.interpret(expression)?
-                }
-            }
-            ast::Expression::FunctionCall { name, args } => {
-                let backup_environments = self.clone();
+        Ok(())
+    }
+
+    /// Sets the command-line arguments of the program, which `args()` and `argc()` give.
+    pub fn set_args(&mut self, args: impl IntoIterator<Item = String>) {
+        self.args = args.into_iter().collect();
+    }
 
-                let definition = backup_environments
-                    .function_environment
-                    .bindings
-                    .get(name)
-                    .ok_or_else(|| InterpreterError::FunctionNotFound(name.clone()))?;
+    /// Replaces where the modules imported by programs are loaded from, which is the current
+    /// directory by default.
+    pub fn set_module_loader(&mut self, loader: impl ModuleLoader + 'static) {
+        self.module_loader = Rc::new(loader);
+    }
 
-                let mut args_iter = args.iter();
+    /// Turns checking the `requires` and `ensures` clauses of functions on or off.
+    pub fn set_contract_checking(&mut self, enabled: bool) {
+        self.check_contracts = enabled;
+    }
 
-                // 関数呼び出し先では呼び出し元のローカル変数が見えないようにする
-                for formal_param_name in &definition.args {
-                    let actual_expression = args_iter.next().ok_or_else(|| {
-                        InterpreterError::NotEnoughArguments(formal_param_name.clone())
-                    })?;
+    /// Sets what arithmetic does on overflow, which is to raise `IntegerOverflow` by default.
+    pub fn set_arithmetic_mode(&mut self, mode: ArithmeticMode) {
+        self.arithmetic_mode = mode;
+    }
 
-                    let actual_value = self.interpret(actual_expression)?;
+    /// Sets how deep calls can be nested before `StackOverflow` is raised, which is 100000 by
+    /// default. The frames of calls are kept on the heap, so that the limit bounds the memory they
+    /// take rather than the native stack.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
 
-                    self.variable_environment
-                        .bindings
-                        .insert(formal_param_name.clone(), actual_value);
-                }
-                let value = self.interpret(&definition.body)?;
+    pub fn interpret(&mut self, expression: &ast::Expression) -> Result<Value, InterpreterError> {
+        self.restoring(|interpreter| match interpreter.evaluate(expression) {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(unwind) => Err(unwind.into_error()),
+        })
+    }
 
-                // 呼び出し先から返ったら変数環境も元に戻す
-                self.variable_environment.bindings =
-                    backup_environments.variable_environment.bindings;
+    /// Runs `f` as a whole: if it fails, every variable, function and enum it has defined,
+    /// changed or removed is as it was before. Generators it has resumed stay resumed, and what
+    /// it has printed stays printed.
+    pub fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, InterpreterError>,
+    ) -> Result<T, InterpreterError> {
+        let variable_environment = self.variable_environment.clone();
+        let globals = self.globals.clone();
+        let function_environment = self.function_environment.clone();
+        let constructors = self.constructors.clone();
+        let calls = self.call_stack.len();
 
-                value
+        let result = f(self);
+        if result.is_err() {
+            self.call_stack.truncate(calls);
+            self.variable_environment = variable_environment;
+            self.globals = globals;
+            self.function_environment = function_environment;
+            self.constructors = constructors;
+        }
+
+        result
+    }
+
+    /// Evaluates `expression` on a machine of its own.
+    fn evaluate(&mut self, expression: &ast::Expression) -> Result<Value, Unwind> {
+        self.execute(&Rc::new(Code::compile(expression)))
+    }
+
+    /// The values given for the parameters of `function` by the arguments of a call, `None` for
+    /// those which take their default values. The code of the function binds them in order.
+    fn given_arguments(
+        function: &ast::Function,
+        args: Vec<Value>,
+        named_args: Vec<(&str, Value)>,
+    ) -> Result<Vec<Option<Value>>, InterpreterError> {
+        let (fixed, variadic) = match function.args.split_last() {
+            Some((last, fixed)) if matches!(last.kind, ast::ParameterKind::Variadic) => {
+                (fixed, Some(last))
             }
-            ast::Expression::PrintLn { expression } => {
-                println!("{}", self.interpret(expression)?);
+            _ => (&function.args[..], None),
+        };
+
+        let named: Vec<_> = named_args.iter().map(|&(name, _)| name).collect();
+        let arity = function.check_arity(args.len(), &named);
 
-                1
+        let mut args = args.into_iter();
+        let mut values: Vec<Option<Value>> = fixed.iter().map(|_| args.next()).collect();
+        for (arg_name, value) in named_args {
+            let index = fixed
+                .iter()
+                .position(|parameter| parameter.name == arg_name)
+                .ok_or_else(|| {
+                    InterpreterError::UnknownNamedArgument(
+                        function.name.clone(),
+                        arg_name.to_string(),
+                    )
+                })?;
+            if values[index].is_some() {
+                return Err(InterpreterError::DuplicateArgument(
+                    function.name.clone(),
+                    arg_name.to_string(),
+                ));
             }
+            values[index] = Some(value);
+        }
+        // 名前付き引数の誤りの方を先に報告する
+        arity.map_err(|mismatch| InterpreterError::arity(&function.name, mismatch))?;
+
+        if variadic.is_some() {
+            values.push(Some(Value::Tuple(args.collect())));
+        }
+
+        Ok(values)
+    }
+
+    /// Defines `functions`, which a block defines, in a new function scope.
+    fn define_functions(&mut self, functions: &[code::BlockFunction]) {
+        // 相互再帰できるよう、ブロック内の関数は先にすべて定義しておく
+        self.function_environment.push_scope();
+        for (function, code) in functions {
+            self.function_environment.bindings.insert(
+                function.name.clone(),
+                self.bind_block_function(function, code),
+            );
+        }
+    }
+
+    /// Updates `name` where it is visible, or creates it in the innermost scope.
+    fn assign_variable(&mut self, name: &str, value: Value) {
+        match self.variable_mut(name) {
+            Some(variable) => *variable = value,
+            None => self.bind_variable(name, value),
+        }
+    }
+
+    /// Binds `name` in the innermost scope, which is the global one for the top-level statements
+    /// outside of any `for` loop, `match` arm, `catch` clause or call.
+    fn bind_variable(&mut self, name: &str, value: Value) {
+        let global = self.running_statements
+            && self.call_stack.is_empty()
+            && self.variable_environment.depth() == 0;
+        let bindings = if global {
+            &mut self.globals
+        } else {
+            &mut self.variable_environment.bindings
         };
+        bindings.insert(name.to_string(), value);
+    }
+
+    /// Tests `value` against `pattern`, collecting the variables the pattern binds.
+    /// A bare identifier naming a constructor without fields matches that constructor instead of
+    /// binding a variable.
+    fn pattern_matches(
+        &self,
+        pattern: &ast::Pattern,
+        value: &Value,
+        bindings: &mut Vec<(String, Value)>,
+    ) -> bool {
+        match (pattern, value) {
+            (ast::Pattern::Wildcard, _) => true,
+            (ast::Pattern::Binding { name }, _) if self.constructors.contains_key(name) => {
+                matches!(value, Value::Variant { name: value_name, fields } if value_name == name && fields.is_empty())
+            }
+            (ast::Pattern::Binding { name }, _) => {
+                bindings.push((name.clone(), value.clone()));
+                true
+            }
+            (
+                ast::Pattern::Literal { value: literal },
+                Value::Integer(value) | Value::SizedInteger(_, value),
+            ) => value == literal,
+            (
+                ast::Pattern::Range {
+                    start,
+                    end,
+                    inclusive,
+                },
+                Value::Integer(value) | Value::SizedInteger(_, value),
+            ) => start <= value && (value < end || (*inclusive && value == end)),
+            (
+                ast::Pattern::Variant {
+                    name: pattern_name,
+                    fields: patterns,
+                },
+                Value::Variant { name, fields },
+            ) => {
+                pattern_name == name
+                    && patterns.len() == fields.len()
+                    && patterns
+                        .iter()
+                        .zip(fields)
+                        .all(|(pattern, field)| self.pattern_matches(pattern, field, bindings))
+            }
+            (ast::Pattern::Tuple { elements: patterns }, Value::Tuple(elements)) => {
+                patterns.len() == elements.len()
+                    && patterns
+                        .iter()
+                        .zip(elements)
+                        .all(|(pattern, element)| self.pattern_matches(pattern, element, bindings))
+            }
+            _ => false,
+        }
+    }
 
-        Ok(value)
+    /// Runs `program`: first its top-level statements in order, then `main` if it defines one.
+    /// Gives back the value of `main`, or else of the last statement. The variables the statements
+    /// bind outside of `for` loops, `match` arms and `catch` clauses are global, so that every function sees them.
+    pub fn call_main(&mut self, program: ast::Program) -> Result<Value, InterpreterError> {
+        self.restoring(|interpreter| interpreter.define_and_call_main(program))
     }
 
-    pub fn call_main(&mut self, program: ast::Program) -> Result<i64, InterpreterError> {
+    fn define_and_call_main(&mut self, program: ast::Program) -> Result<Value, InterpreterError> {
+        let program = modules::link(program, self.module_loader.as_ref())?;
+        let program = macros::expand(program)?;
+        checker::check(&program)?;
+
+        let mut statements = vec![];
         for top_level in program.definitions {
             match top_level {
                 ast::TopLevel::FunctionDefinition(function) => {
-                    self.function_environment
-                        .bindings
-                        .insert(function.name.clone(), function);
+                    self.function_environment.bindings.insert(
+                        function.name.clone(),
+                        frames::FunctionBinding::top_level(function),
+                    );
+                }
+                ast::TopLevel::EnumDefinition(definition) => {
+                    for variant in definition.variants {
+                        self.constructors.insert(variant.name.clone(), variant);
+                    }
                 }
-                ast::TopLevel::GlobalVariableDefinition { name, expression } => {
+                ast::TopLevel::GlobalVariableDefinition { name, expression }
+                | ast::TopLevel::ConstantDefinition { name, expression } => {
                     let value = self.interpret(&expression)?;
-                    self.variable_environment.bindings.insert(name, value);
+                    self.globals.insert(name, value);
                 }
+                ast::TopLevel::MacroDefinition(_) | ast::TopLevel::Import { .. } => {}
+                ast::TopLevel::Statement(statement) => statements.push(statement),
             }
         }
 
-        let main = self
-            .function_environment
-            .bindings
-            .get("main")
-            .cloned()
-            .ok_or(InterpreterError::MainNotPresent)?;
+        let main = self.function_environment.bindings.get("main").cloned();
+        if main.is_none() && statements.is_empty() {
+            return Err(InterpreterError::MainNotPresent);
+        }
+
+        // 最上位の文の変数は大域変数になり、どの関数からも見える
+        self.running_statements = true;
+        let value = self
+            .execute(&Rc::new(Code::sequence(&statements)))
+            .map_err(Unwind::into_error);
+        self.running_statements = false;
+        let value = value?;
+
+        match main {
+            Some(main) => self.call_function(&main, vec![], vec![]),
+            None => Ok(value),
+        }
+    }
 
-        self.interpret(&main.body)
+    /// Runs `program` as `call_main` does, and gives back its exit status: the code it passed to
+    /// `exit`, or 0 if it finished without calling it. A code outside of 0..=255, which the OS
+    /// would cut down to its low 8 bits and might turn into 0, gives 1 so that it still fails.
+    pub fn run(&mut self, program: ast::Program) -> Result<u8, InterpreterError> {
+        match self.call_main(program) {
+            Ok(_) => Ok(0),
+            Err(InterpreterError::Exit(code)) => Ok(u8::try_from(code).unwrap_or(1)),
+            Err(error) => Err(error),
+        }
     }
 }
 
@@ -247,28 +655,40 @@ mod tests {
     fn test_10_plus_20_is_30() {
         let mut interpreter = Interpreter::new();
         let expression = ast::add(ast::integer(10), ast::integer(20));
-        assert_eq!(interpreter.interpret(&expression).unwrap(), 30);
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(30)
+        );
     }
 
     #[test]
     fn test_30_minus_20_is_10() {
         let mut interpreter = Interpreter::new();
         let expression = ast::subtract(ast::integer(30), ast::integer(20));
-        assert_eq!(interpreter.interpret(&expression).unwrap(), 10);
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(10)
+        );
     }
 
     #[test]
     fn test_10_multiplies_20_is_200() {
         let mut interpreter = Interpreter::new();
         let expression = ast::multiply(ast::integer(10), ast::integer(20));
-        assert_eq!(interpreter.interpret(&expression).unwrap(), 200);
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(200)
+        );
     }
 
     #[test]
     fn test_200_divided_by_20_is_10() {
         let mut interpreter = Interpreter::new();
         let expression = ast::divide(ast::integer(200), ast::integer(20));
-        assert_eq!(interpreter.interpret(&expression).unwrap(), 10);
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(10)
+        );
     }
 
     #[test]
@@ -286,7 +706,10 @@ mod tests {
     fn test_42_is_42() {
         let mut interpreter = Interpreter::new();
         let expression = ast::integer(42);
-        assert_eq!(interpreter.interpret(&expression).unwrap(), 42);
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(42)
+        );
     }
 
     #[test]
@@ -294,10 +717,62 @@ mod tests {
         let mut interpreter = Interpreter::new();
 
         let assignment = ast::assignment("foo", ast::integer(42));
-        assert_eq!(interpreter.interpret(&assignment).unwrap(), 42);
+        assert_eq!(
+            interpreter.interpret(&assignment).unwrap(),
+            Value::Integer(42)
+        );
 
         let identifier = ast::identifier("foo");
-        assert_eq!(interpreter.interpret(&identifier).unwrap(), 42);
+        assert_eq!(
+            interpreter.interpret(&identifier).unwrap(),
+            Value::Integer(42)
+        );
+    }
+
+    #[test]
+    fn compound_assignment_to_undefined_variable_is_error() {
+        let mut interpreter = Interpreter::new();
+
+        let expression = ast::compound_assignment(
+            ast::Operator::Add,
+            ast::variable_target("foo"),
+            ast::integer(1),
+        );
+
+        match interpreter.interpret(&expression).unwrap_err() {
+            InterpreterError::VariableNotPresent(name) => assert_eq!(name, "foo"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn compound_assignment_updates_outer_scope() {
+        let mut interpreter = Interpreter::new();
+
+        // sum = 0;
+        // for (i in 1..=4) sum += i;
+        // sum;
+        let expression = ast::block(vec![
+            ast::assignment("sum", ast::integer(0)),
+            ast::ast_for(
+                "i",
+                ast::integer(1),
+                ast::integer(4),
+                true,
+                None,
+                ast::compound_assignment(
+                    ast::Operator::Add,
+                    ast::variable_target("sum"),
+                    ast::identifier("i"),
+                ),
+            ),
+            ast::identifier("sum"),
+        ]);
+
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(10)
+        );
     }
 
     #[test]
@@ -307,7 +782,10 @@ mod tests {
         let condition = ast::less_than(ast::integer(2), ast::integer(4));
 
         let expression = ast::ast_if(condition, ast::integer(42), None);
-        assert_eq!(interpreter.interpret(&expression).unwrap(), 42);
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(42)
+        );
     }
 
     #[test]
@@ -317,7 +795,10 @@ mod tests {
         let condition = ast::greater_than(ast::integer(2), ast::integer(4));
 
         let expression = ast::ast_if(condition, ast::integer(42), Some(ast::integer(53)));
-        assert_eq!(interpreter.interpret(&expression).unwrap(), 53);
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(53)
+        );
     }
 
     #[test]
@@ -327,7 +808,10 @@ mod tests {
         let elements = vec![ast::integer(1), ast::integer(2), ast::integer(3)];
 
         let expression = ast::block(elements);
-        assert_eq!(interpreter.interpret(&expression).unwrap(), 3);
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(3)
+        );
     }
 
     #[test]
@@ -337,9 +821,12 @@ mod tests {
         let mut interpreter = Interpreter::new();
 
         let value = ast::integer(42);
-        let expression = ast::ast_println(value);
+        let expression = ast::ast_println(vec![value]);
 
-        assert_eq!(interpreter.interpret(&expression).unwrap(), 1);
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(1)
+        );
     }
 
     #[test]
@@ -378,6 +865,317 @@ mod tests {
         };
 
         let mut interpreter = Interpreter::new();
-        assert_eq!(interpreter.call_main(program).unwrap(), 120);
+        assert_eq!(interpreter.call_main(program).unwrap(), Value::Integer(120));
+    }
+
+    #[test]
+    fn return_from_function() {
+        let top_levels = vec![
+            // define main() {
+            //     first(42);
+            // }
+            ast::define_function("main", &[], ast::call("first", vec![ast::integer(42)])),
+            // define first(n) {
+            //     return n;
+            //     0;
+            // }
+            ast::define_function(
+                "first",
+                &["n"],
+                ast::block(vec![ast::ast_return(ast::identifier("n")), ast::integer(0)]),
+            ),
+        ];
+
+        let program = ast::Program {
+            definitions: top_levels,
+        };
+
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.call_main(program).unwrap(), Value::Integer(42));
+    }
+
+    #[test]
+    fn break_from_while() {
+        let mut interpreter = Interpreter::new();
+
+        // while (1) {
+        //     i = i + 1;
+        //     if (i == 3) break; else 0;
+        // }
+        let expression = ast::block(vec![
+            ast::assignment("i", ast::integer(0)),
+            ast::ast_while(
+                ast::integer(1),
+                ast::block(vec![
+                    ast::assignment("i", ast::add(ast::identifier("i"), ast::integer(1))),
+                    ast::ast_if(
+                        ast::equal_equal(ast::identifier("i"), ast::integer(3)),
+                        ast::ast_break(),
+                        Some(ast::integer(0)),
+                    ),
+                ]),
+            ),
+            ast::identifier("i"),
+        ]);
+
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(3)
+        );
+    }
+
+    #[test]
+    fn continue_in_while() {
+        let mut interpreter = Interpreter::new();
+
+        // while (i < 5) {
+        //     i = i + 1;
+        //     if (i == 3) continue; else 0;
+        //     sum = sum + i;
+        // }
+        let expression = ast::block(vec![
+            ast::assignment("i", ast::integer(0)),
+            ast::assignment("sum", ast::integer(0)),
+            ast::ast_while(
+                ast::less_than(ast::identifier("i"), ast::integer(5)),
+                ast::block(vec![
+                    ast::assignment("i", ast::add(ast::identifier("i"), ast::integer(1))),
+                    ast::ast_if(
+                        ast::equal_equal(ast::identifier("i"), ast::integer(3)),
+                        ast::ast_continue(),
+                        Some(ast::integer(0)),
+                    ),
+                    ast::assignment(
+                        "sum",
+                        ast::add(ast::identifier("sum"), ast::identifier("i")),
+                    ),
+                ]),
+            ),
+            ast::identifier("sum"),
+        ]);
+
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(12)
+        );
+    }
+
+    #[test]
+    fn for_with_break() {
+        let mut interpreter = Interpreter::new();
+
+        // for (i in 0..=100) {
+        //     if (i * i > 50) break; else 0;
+        //     last = i;
+        // }
+        let expression = ast::block(vec![
+            ast::assignment("last", ast::integer(0)),
+            ast::ast_for(
+                "i",
+                ast::integer(0),
+                ast::integer(100),
+                true,
+                None,
+                ast::block(vec![
+                    ast::ast_if(
+                        ast::greater_than(
+                            ast::multiply(ast::identifier("i"), ast::identifier("i")),
+                            ast::integer(50),
+                        ),
+                        ast::ast_break(),
+                        Some(ast::integer(0)),
+                    ),
+                    ast::assignment("last", ast::identifier("i")),
+                ]),
+            ),
+            ast::identifier("last"),
+        ]);
+
+        assert_eq!(
+            interpreter.interpret(&expression).unwrap(),
+            Value::Integer(7)
+        );
+    }
+
+    #[test]
+    fn for_with_zero_step_is_error() {
+        let mut interpreter = Interpreter::new();
+
+        let expression = ast::ast_for(
+            "i",
+            ast::integer(0),
+            ast::integer(10),
+            false,
+            Some(ast::integer(0)),
+            ast::integer(0),
+        );
+
+        match interpreter.interpret(&expression).unwrap_err() {
+            InterpreterError::ZeroStep => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn match_selects_first_matching_arm() {
+        let mut interpreter = Interpreter::new();
+
+        // match (n) {
+        //     0 => 100,
+        //     1..10 => 200,
+        //     n if n < 0 => 0 - n,
+        //     _ => 300,
+        // }
+        let classify = |n| {
+            ast::ast_match(
+                ast::integer(n),
+                vec![
+                    ast::match_arm(ast::literal_pattern(0), None, ast::integer(100)),
+                    ast::match_arm(ast::range_pattern(1, 10, false), None, ast::integer(200)),
+                    ast::match_arm(
+                        ast::binding_pattern("n"),
+                        Some(ast::less_than(ast::identifier("n"), ast::integer(0))),
+                        ast::subtract(ast::integer(0), ast::identifier("n")),
+                    ),
+                    ast::match_arm(ast::wildcard_pattern(), None, ast::integer(300)),
+                ],
+            )
+        };
+
+        assert_eq!(
+            interpreter.interpret(&classify(0)).unwrap(),
+            Value::Integer(100)
+        );
+        assert_eq!(
+            interpreter.interpret(&classify(9)).unwrap(),
+            Value::Integer(200)
+        );
+        assert_eq!(
+            interpreter.interpret(&classify(-7)).unwrap(),
+            Value::Integer(7)
+        );
+        assert_eq!(
+            interpreter.interpret(&classify(10)).unwrap(),
+            Value::Integer(300)
+        );
+    }
+
+    #[test]
+    fn match_without_matching_arm_is_error() {
+        let mut interpreter = Interpreter::new();
+
+        let expression = ast::ast_match(
+            ast::integer(42),
+            vec![ast::match_arm(
+                ast::range_pattern(0, 42, false),
+                None,
+                ast::integer(1),
+            )],
+        );
+
+        match interpreter.interpret(&expression).unwrap_err() {
+            InterpreterError::NoMatchingArm(value) => assert_eq!(value, Value::Integer(42)),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn constructor_builds_variant() {
+        let top_levels = vec![
+            // enum Shape { Circle(r), Rect(w, h) }
+            ast::define_enum("Shape", &[("Circle", &["r"]), ("Rect", &["w", "h"])]),
+            // define main() {
+            //     Rect(2, Circle(3));
+            // }
+            ast::define_function(
+                "main",
+                &[],
+                ast::call(
+                    "Rect",
+                    vec![ast::integer(2), ast::call("Circle", vec![ast::integer(3)])],
+                ),
+            ),
+        ];
+
+        let program = ast::Program {
+            definitions: top_levels,
+        };
+
+        let mut interpreter = Interpreter::new();
+        let value = interpreter.call_main(program).unwrap();
+        assert_eq!(
+            value,
+            Value::Variant {
+                name: "Rect".to_string(),
+                fields: vec![
+                    Value::Integer(2),
+                    Value::Variant {
+                        name: "Circle".to_string(),
+                        fields: vec![Value::Integer(3)],
+                    },
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn constructor_with_wrong_arity_is_error() {
+        let top_levels = vec![
+            ast::define_enum("Shape", &[("Circle", &["r"])]),
+            ast::define_function("main", &[], ast::call("Circle", vec![])),
+        ];
+
+        let program = ast::Program {
+            definitions: top_levels,
+        };
+
+        let mut interpreter = Interpreter::new();
+        match interpreter.call_main(program).unwrap_err() {
+            InterpreterError::ConstructorArity(name, expected, actual) => {
+                assert_eq!((name.as_str(), expected, actual), ("Circle", 1, 0))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn arithmetic_on_variant_is_error() {
+        let top_levels = vec![
+            ast::define_enum("Color", &[("Red", &[])]),
+            ast::define_function(
+                "main",
+                &[],
+                ast::add(ast::identifier("Red"), ast::integer(1)),
+            ),
+        ];
+
+        let program = ast::Program {
+            definitions: top_levels,
+        };
+
+        let mut interpreter = Interpreter::new();
+        match interpreter.call_main(program).unwrap_err() {
+            InterpreterError::NotAnInteger(value) => assert_eq!(value.to_string(), "Red"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn scopes_left_open_by_failure_are_closed() {
+        let mut interpreter = Interpreter::new();
+        interpreter.variable_environment.push_scope();
+
+        let result: Result<(), _> = interpreter.restoring(|interpreter| {
+            interpreter.variable_environment.push_scope();
+            interpreter.push_frame("f", vec![], None);
+            interpreter.variable_environment.push_scope();
+            interpreter.function_environment.push_scope();
+            Err(InterpreterError::ZeroDivision)
+        });
+
+        assert!(result.is_err());
+        assert!(interpreter.call_stack.is_empty());
+        assert_eq!(interpreter.variable_environment.depth(), 1);
+        assert_eq!(interpreter.function_environment.depth(), 0);
     }
 }
@@ -0,0 +1,243 @@
+//! The binary operators, and what the arithmetic ones do with results which do not fit in an
+//! integer. Every mode behaves the same whether the interpreter is built for debugging or not.
+
+use super::InterpreterError;
+use crate::ast;
+use crate::value::{IntegerType, Value};
+use num_bigint::BigInt;
+use num_traits::Zero;
+
+/// What `+`, `-`, `*` and `/` give when the result does not fit in an integer.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArithmeticMode {
+    /// Raise `IntegerOverflow`.
+    #[default]
+    Checked,
+    /// Wrap around in two's complement.
+    Wrapping,
+    /// Give the largest or the smallest integer, whichever is closer.
+    Saturating,
+    /// Give the exact result, as a big integer if it does not fit in `i64`. Integer literals
+    /// beyond the range of `i64` are only valid in this mode.
+    BigInteger,
+}
+
+/// Applies a binary operator to already evaluated operands.
+/// Only `==` and `!=` accept values other than integers, which are compared structurally.
+/// Integers of a fixed width can only be combined with integers of the same type.
+pub(super) fn apply_binary(
+    operator: &ast::Operator,
+    lhs: &Value,
+    rhs: &Value,
+    mode: ArithmeticMode,
+) -> Result<Value, InterpreterError> {
+    let value = match (operator, lhs, rhs) {
+        (_, Value::SizedInteger(ty, lhs), Value::SizedInteger(rhs_ty, rhs)) if ty == rhs_ty => {
+            return apply_sized_operator(operator, *ty, *lhs, *rhs, mode)
+        }
+        (_, Value::SizedInteger(..), _) | (_, _, Value::SizedInteger(..)) => {
+            if let (Some(lhs_ty), Some(rhs_ty)) = (integer_type(lhs), integer_type(rhs)) {
+                return Err(InterpreterError::MixedIntegerTypes(
+                    operator.to_string(),
+                    lhs_ty,
+                    rhs_ty,
+                ));
+            }
+
+            match operator {
+                ast::Operator::EqualEqual => (lhs == rhs) as i64,
+                ast::Operator::NotEqual => (lhs != rhs) as i64,
+                _ => return Err(InterpreterError::NotAnInteger(non_integer(lhs, rhs))),
+            }
+        }
+        (ast::Operator::EqualEqual, ..) => (lhs == rhs) as i64,
+        (ast::Operator::NotEqual, ..) => (lhs != rhs) as i64,
+        (_, Value::Integer(lhs), Value::Integer(rhs)) => {
+            match apply_operator(operator, *lhs, *rhs, mode) {
+                // 溢れたときだけ多倍長整数で計算し直す
+                Err(InterpreterError::IntegerOverflow(..))
+                    if mode == ArithmeticMode::BigInteger =>
+                {
+                    return apply_big_operator(operator, &BigInt::from(*lhs), &BigInt::from(*rhs))
+                }
+                result => result?,
+            }
+        }
+        _ => return apply_big_operator(operator, &lhs.as_big_integer()?, &rhs.as_big_integer()?),
+    };
+
+    Ok(Value::Integer(value))
+}
+
+/// The name of the type of `value`, if it is an integer.
+fn integer_type(value: &Value) -> Option<String> {
+    match value {
+        Value::Integer(_) | Value::BigInteger(_) => Some("i64".to_string()),
+        Value::SizedInteger(ty, _) => Some(ty.to_string()),
+        _ => None,
+    }
+}
+
+/// Whichever of `lhs` and `rhs` is not an integer.
+fn non_integer(lhs: &Value, rhs: &Value) -> Value {
+    match integer_type(lhs) {
+        Some(_) => rhs.clone(),
+        None => lhs.clone(),
+    }
+}
+
+/// An arithmetic operator as each mode computes it.
+type Operation = (
+    fn(i64, i64) -> Option<i64>,
+    fn(i64, i64) -> i64,
+    fn(i64, i64) -> i64,
+);
+
+fn apply_operator(
+    operator: &ast::Operator,
+    lhs: i64,
+    rhs: i64,
+    mode: ArithmeticMode,
+) -> Result<i64, InterpreterError> {
+    let (checked, wrapping, saturating): Operation = match operator {
+        ast::Operator::Add => (i64::checked_add, i64::wrapping_add, i64::saturating_add),
+        ast::Operator::Subtract => (i64::checked_sub, i64::wrapping_sub, i64::saturating_sub),
+        ast::Operator::Multiply => (i64::checked_mul, i64::wrapping_mul, i64::saturating_mul),
+        ast::Operator::Divide | ast::Operator::Remainder if rhs == 0 => {
+            return Err(InterpreterError::ZeroDivision)
+        }
+        // i64::MIN / -1 だけが溢れる
+        ast::Operator::Divide => (i64::checked_div, i64::wrapping_div, i64::saturating_div),
+        // 剰余は溢れない。i64::MIN % -1 も 0 になる
+        ast::Operator::Remainder => return Ok(lhs.wrapping_rem(rhs)),
+        ast::Operator::LessThan => return Ok((lhs < rhs) as i64),
+        ast::Operator::LessOrEqual => return Ok((lhs <= rhs) as i64),
+        ast::Operator::GreaterThan => return Ok((lhs > rhs) as i64),
+        ast::Operator::GreaterOrEqual => return Ok((lhs >= rhs) as i64),
+        ast::Operator::EqualEqual => return Ok((lhs == rhs) as i64),
+        ast::Operator::NotEqual => return Ok((lhs != rhs) as i64),
+    };
+
+    match mode {
+        ArithmeticMode::Checked | ArithmeticMode::BigInteger => checked(lhs, rhs)
+            .ok_or_else(|| InterpreterError::IntegerOverflow(operator.to_string(), lhs, rhs)),
+        ArithmeticMode::Wrapping => Ok(wrapping(lhs, rhs)),
+        ArithmeticMode::Saturating => Ok(saturating(lhs, rhs)),
+    }
+}
+
+/// Applies an operator other than `==` and `!=` to integers, at least one of which may be beyond
+/// the range of `i64`.
+fn apply_big_operator(
+    operator: &ast::Operator,
+    lhs: &BigInt,
+    rhs: &BigInt,
+) -> Result<Value, InterpreterError> {
+    let value = match operator {
+        ast::Operator::Add => lhs + rhs,
+        ast::Operator::Subtract => lhs - rhs,
+        ast::Operator::Multiply => lhs * rhs,
+        ast::Operator::Divide | ast::Operator::Remainder if rhs.is_zero() => {
+            return Err(InterpreterError::ZeroDivision)
+        }
+        // i64 と同じく 0 に向かって切り捨て、剰余は被除数と同じ符号にする
+        ast::Operator::Divide => lhs / rhs,
+        ast::Operator::Remainder => lhs % rhs,
+        ast::Operator::LessThan => return Ok(Value::Integer((lhs < rhs) as i64)),
+        ast::Operator::LessOrEqual => return Ok(Value::Integer((lhs <= rhs) as i64)),
+        ast::Operator::GreaterThan => return Ok(Value::Integer((lhs > rhs) as i64)),
+        ast::Operator::GreaterOrEqual => return Ok(Value::Integer((lhs >= rhs) as i64)),
+        ast::Operator::EqualEqual => return Ok(Value::Integer((lhs == rhs) as i64)),
+        ast::Operator::NotEqual => return Ok(Value::Integer((lhs != rhs) as i64)),
+    };
+
+    Ok(Value::from(value))
+}
+
+/// Applies an operator other than `==` and `!=` to two integers of the type `ty`, giving an
+/// integer of the same type. In the big integer mode, the result must fit as in the checked mode.
+fn apply_sized_operator(
+    operator: &ast::Operator,
+    ty: IntegerType,
+    lhs: i64,
+    rhs: i64,
+    mode: ArithmeticMode,
+) -> Result<Value, InterpreterError> {
+    // どの型の積も i128 には収まる
+    let (wide_lhs, wide_rhs) = (i128::from(lhs), i128::from(rhs));
+    let result = match operator {
+        ast::Operator::Add => wide_lhs + wide_rhs,
+        ast::Operator::Subtract => wide_lhs - wide_rhs,
+        ast::Operator::Multiply => wide_lhs * wide_rhs,
+        ast::Operator::Divide | ast::Operator::Remainder if rhs == 0 => {
+            return Err(InterpreterError::ZeroDivision)
+        }
+        ast::Operator::Divide => wide_lhs / wide_rhs,
+        ast::Operator::Remainder => wide_lhs % wide_rhs,
+        ast::Operator::LessThan => return Ok(Value::Integer((lhs < rhs) as i64)),
+        ast::Operator::LessOrEqual => return Ok(Value::Integer((lhs <= rhs) as i64)),
+        ast::Operator::GreaterThan => return Ok(Value::Integer((lhs > rhs) as i64)),
+        ast::Operator::GreaterOrEqual => return Ok(Value::Integer((lhs >= rhs) as i64)),
+        ast::Operator::EqualEqual => return Ok(Value::Integer((lhs == rhs) as i64)),
+        ast::Operator::NotEqual => return Ok(Value::Integer((lhs != rhs) as i64)),
+    };
+
+    let (min, max) = ty.bounds();
+    let value = match mode {
+        _ if (i128::from(min)..=i128::from(max)).contains(&result) => result as i64,
+        ArithmeticMode::Checked | ArithmeticMode::BigInteger => {
+            return Err(InterpreterError::IntegerOverflow(
+                operator.to_string(),
+                lhs,
+                rhs,
+            ))
+        }
+        ArithmeticMode::Wrapping => ty.truncate(result),
+        ArithmeticMode::Saturating => result.clamp(min.into(), max.into()) as i64,
+    };
+
+    Ok(Value::SizedInteger(ty, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflow_in_each_mode() {
+        let cases = [
+            (ast::Operator::Add, i64::MAX, 1, i64::MIN, i64::MAX),
+            (ast::Operator::Subtract, i64::MIN, 1, i64::MAX, i64::MIN),
+            (ast::Operator::Multiply, i64::MAX, -2, 2, i64::MIN),
+            (ast::Operator::Divide, i64::MIN, -1, i64::MIN, i64::MAX),
+        ];
+
+        for (operator, lhs, rhs, wrapped, saturated) in cases {
+            match apply_operator(&operator, lhs, rhs, ArithmeticMode::Checked) {
+                Err(InterpreterError::IntegerOverflow(symbol, l, r)) => {
+                    assert_eq!((symbol, l, r), (operator.to_string(), lhs, rhs))
+                }
+                _ => unreachable!(),
+            }
+            assert_eq!(
+                apply_operator(&operator, lhs, rhs, ArithmeticMode::Wrapping).unwrap(),
+                wrapped
+            );
+            assert_eq!(
+                apply_operator(&operator, lhs, rhs, ArithmeticMode::Saturating).unwrap(),
+                saturated
+            );
+        }
+
+        for mode in [
+            ArithmeticMode::Checked,
+            ArithmeticMode::Wrapping,
+            ArithmeticMode::Saturating,
+        ] {
+            let remainder = apply_operator(&ast::Operator::Remainder, i64::MIN, -1, mode);
+            assert_eq!(remainder.unwrap(), 0);
+            let quotient = apply_operator(&ast::Operator::Divide, 1, 0, mode);
+            assert!(matches!(quotient, Err(InterpreterError::ZeroDivision)));
+        }
+    }
+}
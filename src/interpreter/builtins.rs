@@ -0,0 +1,150 @@
+//! Functions provided by the interpreter itself. A function defined by the program with the same
+//! name shadows a builtin. `next`, which resumes a generator, is provided by the machine instead.
+
+use super::{Interpreter, InterpreterError};
+use crate::value::{IntegerType, Value};
+use num_bigint::Sign;
+use num_traits::ToPrimitive;
+
+pub(super) type Builtin = fn(&mut Interpreter, Vec<Value>) -> Result<Value, InterpreterError>;
+
+pub fn lookup(name: &str) -> Option<Builtin> {
+    let builtin: Builtin = match name {
+        "error" => error,
+        "error_kind" => error_kind,
+        "error_message" => error_message,
+        "args" => args,
+        "argc" => argc,
+        "exit" => exit,
+        "u8" => |_, args| convert("u8", args, Some(IntegerType::U8)),
+        "u16" => |_, args| convert("u16", args, Some(IntegerType::U16)),
+        "u32" => |_, args| convert("u32", args, Some(IntegerType::U32)),
+        "i32" => |_, args| convert("i32", args, Some(IntegerType::I32)),
+        "i64" => |_, args| convert("i64", args, None),
+        "truncate_u8" => |_, args| truncate("truncate_u8", args, Some(IntegerType::U8)),
+        "truncate_u16" => |_, args| truncate("truncate_u16", args, Some(IntegerType::U16)),
+        "truncate_u32" => |_, args| truncate("truncate_u32", args, Some(IntegerType::U32)),
+        "truncate_i32" => |_, args| truncate("truncate_i32", args, Some(IntegerType::I32)),
+        "truncate_i64" => |_, args| truncate("truncate_i64", args, None),
+        _ => return None,
+    };
+
+    Some(builtin)
+}
+
+/// Checks that exactly `N` arguments are given to the builtin `name`.
+fn expect_args<const N: usize>(
+    name: &str,
+    args: Vec<Value>,
+) -> Result<[Value; N], InterpreterError> {
+    let given = args.len();
+
+    args.try_into()
+        .map_err(|_| InterpreterError::BuiltinArity(name.to_string(), N, given))
+}
+
+fn expect_string(value: Value) -> Result<String, InterpreterError> {
+    match value {
+        Value::String(value) => Ok(value),
+        _ => Err(InterpreterError::NotAString(value)),
+    }
+}
+
+/// `error(kind, message)` builds an error in the same form as the ones raised by the interpreter.
+fn error(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, InterpreterError> {
+    let [kind, message] = expect_args("error", args)?;
+
+    Ok(Value::Error {
+        kind: expect_string(kind)?,
+        message: expect_string(message)?,
+    })
+}
+
+fn error_kind(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, InterpreterError> {
+    match expect_args("error_kind", args)? {
+        [Value::Error { kind, .. }] => Ok(Value::String(kind)),
+        [value] => Err(InterpreterError::NotAnError(value)),
+    }
+}
+
+fn error_message(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, InterpreterError> {
+    match expect_args("error_message", args)? {
+        [Value::Error { message, .. }] => Ok(Value::String(message)),
+        [value] => Err(InterpreterError::NotAnError(value)),
+    }
+}
+
+/// `args()` is a tuple of the command-line arguments of the program, as strings.
+fn args(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, InterpreterError> {
+    let [] = expect_args("args", args)?;
+
+    Ok(Value::Tuple(
+        interpreter
+            .args
+            .iter()
+            .cloned()
+            .map(Value::String)
+            .collect(),
+    ))
+}
+
+/// `argc()` is the number of command-line arguments of the program.
+fn argc(interpreter: &mut Interpreter, args: Vec<Value>) -> Result<Value, InterpreterError> {
+    let [] = expect_args("argc", args)?;
+
+    Ok(Value::Integer(interpreter.args.len() as i64))
+}
+
+/// `exit(code)` stops the program with the exit status `code`, running the `finally` clauses it
+/// is in on the way out.
+fn exit(_: &mut Interpreter, args: Vec<Value>) -> Result<Value, InterpreterError> {
+    let [code] = expect_args("exit", args)?;
+
+    Err(InterpreterError::Exit(code.as_integer()?))
+}
+
+/// `u8(n)`, `u16(n)`, `u32(n)`, `i32(n)` and `i64(n)` convert the integer `n` to the type they
+/// are named after, which must have `n` in its range. `ty` is `None` for `i64`.
+fn convert(
+    name: &str,
+    args: Vec<Value>,
+    ty: Option<IntegerType>,
+) -> Result<Value, InterpreterError> {
+    let [value] = expect_args(name, args)?;
+    let integer = value.as_big_integer()?;
+
+    match (integer.to_i64(), ty) {
+        (Some(integer), Some(ty)) if ty.contains(integer) => Ok(Value::SizedInteger(ty, integer)),
+        (Some(integer), None) => Ok(Value::Integer(integer)),
+        (_, ty) => Err(InterpreterError::ConversionOutOfRange(
+            value,
+            ty.map_or("i64".to_string(), |ty| ty.to_string()),
+        )),
+    }
+}
+
+/// `truncate_u8(n)` and the like convert the integer `n` by keeping as many of its low bits as
+/// the type has, in two's complement, as casts do in Rust.
+fn truncate(
+    name: &str,
+    args: Vec<Value>,
+    ty: Option<IntegerType>,
+) -> Result<Value, InterpreterError> {
+    let [value] = expect_args(name, args)?;
+    let integer = value.as_big_integer()?;
+
+    // 下位 64 ビットを取り出してから、さらに型の幅に切り詰める
+    let mut bytes = integer.to_signed_bytes_le();
+    let sign = if integer.sign() == Sign::Minus {
+        0xff
+    } else {
+        0
+    };
+    bytes.resize(8, sign);
+    let low = i64::from_le_bytes(bytes[..8].try_into().unwrap());
+
+    Ok(match ty {
+        Some(ty) => Value::SizedInteger(ty, ty.truncate(low.into())),
+        None => Value::Integer(low),
+    })
+}
@@ -0,0 +1,586 @@
+//! Expressions compiled into flat sequences of instructions for the machine to run.
+//!
+//! Every instruction takes its operands from the top of the stack of values and leaves its result
+//! there, so that running the code of an expression leaves its value on top. Jumps go to indices
+//! within the same code; a function body is a code of its own.
+
+use std::mem;
+use std::rc::Rc;
+
+use crate::ast;
+use crate::value::Value;
+
+pub(super) struct Code {
+    pub(super) instructions: Vec<Instruction>,
+}
+
+/// A call as written, shared by the instructions which look the callee up and call it.
+pub(super) struct CallSite {
+    pub(super) name: String,
+    /// The number of positional arguments.
+    pub(super) args: usize,
+    /// The names of the named arguments, whose values come after the positional ones.
+    pub(super) named_args: Vec<String>,
+}
+
+/// The code of a function, which a call runs from the start: it binds the parameters, evaluating
+/// the default values of those not given, checks the `requires` clauses, runs the body and then
+/// checks the `ensures` clauses. Everything runs on the machine of the call.
+pub(super) struct FunctionCode {
+    pub(super) call: Rc<Code>,
+    /// Where the `ensures` clauses are checked, if there are any, which `return` goes on at.
+    pub(super) ensures: Option<usize>,
+}
+
+/// A function defined in a block, with its code compiled.
+pub(super) type BlockFunction = (Rc<ast::Function>, Rc<FunctionCode>);
+
+pub(super) enum Instruction {
+    Push(Value),
+    /// Pushes a literal beyond the range of `i64`, which is an error out of the big integer mode.
+    PushBigInteger(Value),
+    /// Appends to the string on top.
+    Append(String),
+    /// Pops a value and appends it to the string under it as `spec` formats it.
+    AppendFormatted(ast::FormatSpec),
+    Load(String),
+    /// Pushes the current value of a variable which a compound assignment updates.
+    Fetch(String),
+    Update {
+        operator: ast::Operator,
+        name: String,
+    },
+    Assign(String),
+    Let(String),
+    Destructure(ast::Pattern),
+    /// Pops as many elements as given and pushes the tuple of them.
+    Tuple(usize),
+    TupleIndex(usize),
+    Binary(ast::Operator),
+    /// Replaces the value on top with the integer it is, or fails if it is none.
+    Integer,
+    Pop,
+    Jump(usize),
+    /// Pops an integer and jumps if it is zero.
+    JumpUnless(usize),
+    /// Fails as an `if` without `else` whose condition is not met.
+    MissingElse,
+    /// Opens a function scope binding the functions of a block.
+    DefineFunctions(Vec<BlockFunction>),
+    PopFunctionScope,
+    /// Begins a loop, which `break` leaves to `exit` and `continue` restarts at the instruction
+    /// after this one.
+    EnterWhile {
+        exit: usize,
+    },
+    /// Pops the step if there is one, the end and the start, and begins a loop over the range in
+    /// a new variable scope.
+    EnterFor {
+        inclusive: bool,
+        step: bool,
+        exit: usize,
+    },
+    /// Pops a tuple or a generator and begins a loop over it in a new variable scope.
+    EnterForEach {
+        exit: usize,
+    },
+    /// Pushes the next value of the innermost `for` loop, or leaves the loop if there are no
+    /// more.
+    Iterate,
+    /// Ends the innermost loop, closing its scope, and pushes its value.
+    ExitLoop,
+    /// Binds the variables of `pattern` in a new variable scope if the value on top matches it,
+    /// or jumps to the next arm if it does not.
+    MatchArm {
+        pattern: ast::Pattern,
+        next: usize,
+    },
+    /// Pops the value of a guard, and jumps to the next arm closing the scope of the arm if it
+    /// is zero.
+    Guard {
+        next: usize,
+    },
+    /// Closes the scope of an arm and replaces the value matched with the value of the arm.
+    EndArm {
+        end: usize,
+    },
+    /// Fails as no arm accepts the value on top.
+    NoMatch,
+    /// Looks up the callee before the arguments are evaluated.
+    Resolve(Rc<CallSite>),
+    /// Pops the values of the arguments and calls the callee looked up last.
+    Invoke(Rc<CallSite>),
+    Print {
+        args: usize,
+        newline: bool,
+    },
+    Return,
+    Throw,
+    /// Pushes the value given for the parameter at `parameter` of the running call and jumps to
+    /// `next`, unless none is given and the default value after this instruction is to be taken.
+    Bind {
+        parameter: usize,
+        next: usize,
+    },
+    /// Begins checking the contract of the running call, once its parameters are bound, or jumps
+    /// to `skip` if contracts are not checked.
+    Requires {
+        skip: usize,
+    },
+    /// Pops the value of the `requires` clause at the index, failing if it is zero.
+    Require(usize),
+    /// Binds the value on top to `result` for the `ensures` clauses, or jumps to `skip` if
+    /// contracts are not checked.
+    Ensures {
+        skip: usize,
+    },
+    /// Pops the value of the `ensures` clause at the index, failing if it is zero.
+    Ensure(usize),
+    /// Returns a generator from the running call, which is suspended at the next instruction.
+    Generate,
+    /// Suspends the generator whose body is running, giving the value on top to what resumed
+    /// it. The value stays on top as that of the `yield`.
+    Yield,
+    YieldOutsideGenerator,
+    Break,
+    Continue,
+    /// Begins a `try`. A caught error is pushed for the catch clause at `catch` to bind, and the
+    /// finally clause is at `finally`.
+    EnterTry {
+        catch: Option<usize>,
+        finally: Option<usize>,
+    },
+    /// Ends the body of a `try`, going on to the finally clause or else to `end`.
+    EndTry {
+        end: usize,
+    },
+    /// Ends the catch clause, closing its scope, as `EndTry` does the body.
+    EndCatch {
+        end: usize,
+    },
+    /// Pops the value of the finally clause, and goes on with what happened before it ran.
+    EndFinally,
+}
+
+impl Code {
+    pub(super) fn compile(expression: &ast::Expression) -> Code {
+        let mut compiler = Compiler::default();
+        compiler.expression(expression);
+        compiler.finish()
+    }
+
+    /// Compiles `elements` as a block whose value is that of the last element, leaving out the
+    /// functions they define.
+    pub(super) fn sequence(elements: &[ast::Expression]) -> Code {
+        let mut compiler = Compiler::default();
+        compiler.sequence(elements, false);
+        compiler.finish()
+    }
+}
+
+impl FunctionCode {
+    /// Compiles the code of `function`, once for all of its calls. The statements of the body
+    /// can suspend it at `yield` if it is a generator function.
+    pub(super) fn compile(function: &ast::Function) -> Self {
+        let mut compiler = Compiler::default();
+        // 既定値は仮引数の順に評価され、前の仮引数だけが見える
+        for (index, parameter) in function.args.iter().enumerate() {
+            let bind = compiler.emit(Instruction::Bind {
+                parameter: index,
+                next: 0,
+            });
+            if let ast::ParameterKind::Default(default) = &parameter.kind {
+                compiler.expression(default);
+            }
+            compiler.patch(bind);
+            compiler.emit(Instruction::Let(parameter.name.clone()));
+            compiler.emit(Instruction::Pop);
+        }
+
+        let contract = &function.contract;
+        if !contract.requires.is_empty() || !contract.ensures.is_empty() {
+            let requires = compiler.emit(Instruction::Requires { skip: 0 });
+            for (index, condition) in contract.requires.iter().enumerate() {
+                compiler.expression(condition);
+                compiler.emit(Instruction::Require(index));
+            }
+            compiler.patch(requires);
+        }
+
+        if function.generator {
+            compiler.emit(Instruction::Generate);
+        }
+        compiler.statement(&function.body, function.generator);
+
+        let ensures = (!contract.ensures.is_empty()).then(|| {
+            let ensures = compiler.emit(Instruction::Ensures { skip: 0 });
+            for (index, condition) in contract.ensures.iter().enumerate() {
+                compiler.expression(condition);
+                compiler.emit(Instruction::Ensure(index));
+            }
+            compiler.patch(ensures);
+            ensures
+        });
+
+        FunctionCode {
+            call: Rc::new(compiler.finish()),
+            ensures,
+        }
+    }
+}
+
+/// The functions which `elements` define, for their block to bind when it is entered.
+pub(super) fn block_functions(elements: &[ast::Expression]) -> Vec<BlockFunction> {
+    elements
+        .iter()
+        .filter_map(|element| match element {
+            ast::Expression::FunctionDefinition { function } => Some((
+                Rc::clone(function),
+                Rc::new(FunctionCode::compile(function)),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Default)]
+struct Compiler {
+    instructions: Vec<Instruction>,
+    /// Whether the expression compiled next is a statement of the body of a generator, where
+    /// `yield` suspends the body.
+    statement: bool,
+}
+
+impl Compiler {
+    fn finish(self) -> Code {
+        Code {
+            instructions: self.instructions,
+        }
+    }
+
+    /// Appends `instruction`, giving back where it is.
+    fn emit(&mut self, instruction: Instruction) -> usize {
+        self.instructions.push(instruction);
+        self.instructions.len() - 1
+    }
+
+    /// Points the jump at `at` to the next instruction to be emitted.
+    fn patch(&mut self, at: usize) {
+        let here = self.instructions.len();
+        match &mut self.instructions[at] {
+            Instruction::Jump(target)
+            | Instruction::JumpUnless(target)
+            | Instruction::EnterWhile { exit: target }
+            | Instruction::EnterFor { exit: target, .. }
+            | Instruction::EnterForEach { exit: target }
+            | Instruction::MatchArm { next: target, .. }
+            | Instruction::Guard { next: target }
+            | Instruction::EndArm { end: target }
+            | Instruction::EndTry { end: target }
+            | Instruction::EndCatch { end: target }
+            | Instruction::Bind { next: target, .. }
+            | Instruction::Requires { skip: target }
+            | Instruction::Ensures { skip: target } => *target = here,
+            _ => unreachable!("only jumps are patched"),
+        }
+    }
+
+    /// Compiles `expression`, which is a statement of the body of a generator if `statement` is
+    /// set.
+    fn statement(&mut self, expression: &ast::Expression, statement: bool) {
+        self.statement = statement;
+        self.expression(expression);
+    }
+
+    fn sequence(&mut self, elements: &[ast::Expression], statement: bool) {
+        let Some((last, elements)) = elements.split_last() else {
+            self.emit(Instruction::Push(Value::Integer(0)));
+            return;
+        };
+
+        for element in elements {
+            self.statement(element, statement);
+            self.emit(Instruction::Pop);
+        }
+        self.statement(last, statement);
+    }
+
+    /// Binds the next value of the innermost loop to `variable`, once the loop has one.
+    fn iterate(&mut self, variable: &str) {
+        self.emit(Instruction::Iterate);
+        self.emit(Instruction::Let(variable.to_string()));
+        self.emit(Instruction::Pop);
+    }
+
+    /// Compiles the body of a loop, which begins at `enter` and goes on while the instruction
+    /// after it does not leave.
+    fn loop_body(&mut self, enter: usize, body: &ast::Expression, statement: bool) {
+        self.statement(body, statement);
+        self.emit(Instruction::Pop);
+        self.emit(Instruction::Jump(enter + 1));
+        self.patch(enter);
+        self.emit(Instruction::ExitLoop);
+    }
+
+    fn expression(&mut self, expression: &ast::Expression) {
+        // `yield` は文の位置でだけジェネレータを中断でき、式の中では評価時に失敗する
+        let statement = mem::take(&mut self.statement);
+        match expression {
+            ast::Expression::Binary { operator, lhs, rhs } => {
+                self.expression(lhs);
+                self.expression(rhs);
+                self.emit(Instruction::Binary(operator.clone()));
+            }
+            ast::Expression::IntegerLiteral { value } => {
+                self.emit(Instruction::Push(Value::Integer(*value)));
+            }
+            ast::Expression::SizedIntegerLiteral { value, ty } => {
+                self.emit(Instruction::Push(Value::SizedInteger(*ty, *value)));
+            }
+            ast::Expression::BigIntegerLiteral { value } => {
+                self.emit(Instruction::PushBigInteger(Value::BigInteger(
+                    value.clone(),
+                )));
+            }
+            ast::Expression::StringLiteral { value } => {
+                self.emit(Instruction::Push(Value::String(value.clone())));
+            }
+            ast::Expression::Format { parts } => {
+                self.emit(Instruction::Push(Value::String(String::new())));
+                for part in parts {
+                    match part {
+                        ast::FormatPart::Literal(literal) => {
+                            self.emit(Instruction::Append(literal.clone()));
+                        }
+                        ast::FormatPart::Interpolation { expression, spec } => {
+                            self.expression(expression);
+                            self.emit(Instruction::AppendFormatted(spec.clone()));
+                        }
+                    }
+                }
+            }
+            ast::Expression::Identifier { name } => {
+                self.emit(Instruction::Load(name.clone()));
+            }
+            ast::Expression::Tuple { elements } => {
+                for element in elements {
+                    self.expression(element);
+                }
+                self.emit(Instruction::Tuple(elements.len()));
+            }
+            ast::Expression::TupleIndex { tuple, index } => {
+                self.expression(tuple);
+                self.emit(Instruction::TupleIndex(*index));
+            }
+            ast::Expression::Assignment { name, expression } => {
+                self.expression(expression);
+                self.emit(Instruction::Assign(name.clone()));
+            }
+            ast::Expression::Let { name, expression } => {
+                self.expression(expression);
+                self.emit(Instruction::Let(name.clone()));
+            }
+            ast::Expression::Destructuring {
+                pattern,
+                expression,
+            } => {
+                self.expression(expression);
+                self.emit(Instruction::Destructure(pattern.clone()));
+            }
+            ast::Expression::CompoundAssignment {
+                operator,
+                target: ast::AssignmentTarget::Variable { name },
+                expression,
+            } => {
+                self.emit(Instruction::Fetch(name.clone()));
+                self.expression(expression);
+                self.emit(Instruction::Update {
+                    operator: operator.clone(),
+                    name: name.clone(),
+                });
+            }
+            ast::Expression::Block { elements } => {
+                let functions = block_functions(elements);
+                if functions.is_empty() {
+                    self.sequence(elements, statement);
+                } else {
+                    self.emit(Instruction::DefineFunctions(functions));
+                    self.sequence(elements, statement);
+                    self.emit(Instruction::PopFunctionScope);
+                }
+            }
+            // ブロックに入ったときに定義済み
+            ast::Expression::FunctionDefinition { .. } => {
+                self.emit(Instruction::Push(Value::Integer(0)));
+            }
+            ast::Expression::While { condition, body } => {
+                let enter = self.emit(Instruction::EnterWhile { exit: 0 });
+                self.expression(condition);
+                let leave = self.emit(Instruction::JumpUnless(0));
+                self.statement(body, statement);
+                self.emit(Instruction::Pop);
+                self.emit(Instruction::Jump(enter + 1));
+                self.patch(enter);
+                self.patch(leave);
+                self.emit(Instruction::ExitLoop);
+            }
+            ast::Expression::For {
+                variable,
+                start,
+                end,
+                inclusive,
+                step,
+                body,
+            } => {
+                for bound in [Some(start), Some(end), step.as_ref()]
+                    .into_iter()
+                    .flatten()
+                {
+                    self.expression(bound);
+                    self.emit(Instruction::Integer);
+                }
+                let enter = self.emit(Instruction::EnterFor {
+                    inclusive: *inclusive,
+                    step: step.is_some(),
+                    exit: 0,
+                });
+                self.iterate(variable);
+                self.loop_body(enter, body, statement);
+            }
+            ast::Expression::ForEach {
+                variable,
+                iterable,
+                body,
+            } => {
+                self.expression(iterable);
+                let enter = self.emit(Instruction::EnterForEach { exit: 0 });
+                self.iterate(variable);
+                self.loop_body(enter, body, statement);
+            }
+            ast::Expression::If {
+                condition,
+                then_clause,
+                else_clause,
+            } => {
+                self.expression(condition);
+                let otherwise = self.emit(Instruction::JumpUnless(0));
+                self.statement(then_clause, statement);
+                let end = self.emit(Instruction::Jump(0));
+                self.patch(otherwise);
+                match else_clause {
+                    Some(else_clause) => self.statement(else_clause, statement),
+                    None => {
+                        self.emit(Instruction::MissingElse);
+                    }
+                }
+                self.patch(end);
+            }
+            ast::Expression::Match { scrutinee, arms } => {
+                self.expression(scrutinee);
+                let mut ends = vec![];
+                for arm in arms {
+                    let arm_start = self.emit(Instruction::MatchArm {
+                        pattern: arm.pattern.clone(),
+                        next: 0,
+                    });
+                    let guard = arm.guard.as_ref().map(|guard| {
+                        self.expression(guard);
+                        self.emit(Instruction::Guard { next: 0 })
+                    });
+                    self.statement(&arm.body, statement);
+                    ends.push(self.emit(Instruction::EndArm { end: 0 }));
+
+                    self.patch(arm_start);
+                    if let Some(guard) = guard {
+                        self.patch(guard);
+                    }
+                }
+                self.emit(Instruction::NoMatch);
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+            ast::Expression::FunctionCall {
+                name,
+                args,
+                named_args,
+            } => {
+                let call = Rc::new(CallSite {
+                    name: name.clone(),
+                    args: args.len(),
+                    named_args: named_args.iter().map(|(name, _)| name.clone()).collect(),
+                });
+
+                // 実引数はすべて呼び出し元で、呼び出し先を見つけてから評価する
+                self.emit(Instruction::Resolve(Rc::clone(&call)));
+                for arg in args.iter().chain(named_args.iter().map(|(_, arg)| arg)) {
+                    self.expression(arg);
+                }
+                self.emit(Instruction::Invoke(call));
+            }
+            ast::Expression::Print { args, newline } => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.emit(Instruction::Print {
+                    args: args.len(),
+                    newline: *newline,
+                });
+            }
+            ast::Expression::Return { expression } => {
+                self.expression(expression);
+                self.emit(Instruction::Return);
+            }
+            ast::Expression::Throw { expression } => {
+                self.expression(expression);
+                self.emit(Instruction::Throw);
+            }
+            ast::Expression::Yield { expression } if statement => {
+                self.expression(expression);
+                self.emit(Instruction::Yield);
+            }
+            ast::Expression::Yield { .. } => {
+                self.emit(Instruction::YieldOutsideGenerator);
+            }
+            ast::Expression::Try {
+                body,
+                catch_clause,
+                finally_clause,
+            } => {
+                let enter = self.emit(Instruction::EnterTry {
+                    catch: None,
+                    finally: None,
+                });
+                self.statement(body, statement);
+                let mut ends = vec![self.emit(Instruction::EndTry { end: 0 })];
+
+                let catch = catch_clause.as_ref().map(|catch_clause| {
+                    let start = self.instructions.len();
+                    // catch 節の変数は catch 節の中でだけ見える
+                    self.emit(Instruction::Let(catch_clause.variable.clone()));
+                    self.emit(Instruction::Pop);
+                    self.statement(&catch_clause.body, statement);
+                    ends.push(self.emit(Instruction::EndCatch { end: 0 }));
+                    start
+                });
+                let finally = finally_clause.as_ref().map(|finally_clause| {
+                    let start = self.instructions.len();
+                    self.statement(finally_clause, statement);
+                    self.emit(Instruction::EndFinally);
+                    start
+                });
+
+                self.instructions[enter] = Instruction::EnterTry { catch, finally };
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+            ast::Expression::Break => {
+                self.emit(Instruction::Break);
+            }
+            ast::Expression::Continue => {
+                self.emit(Instruction::Continue);
+            }
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Checking the `requires` and `ensures` clauses of functions, which the code of a call does around
+//! its body.
+
+use super::{Interpreter, InterpreterError};
+use crate::ast;
+use crate::value::Value;
+
+impl Interpreter {
+    /// The values of the parameters of `function`, which are bound in the innermost scope, for a
+    /// contract violation to report.
+    pub(super) fn contract_arguments(&self, function: &ast::Function) -> Vec<(String, Value)> {
+        // 本体で仮引数が書き換えられても、呼び出されたときの値を報告する
+        function
+            .args
+            .iter()
+            .filter_map(|arg| {
+                let value = self.variable_environment.bindings.get(&arg.name)?;
+                Some((arg.name.clone(), value.clone()))
+            })
+            .collect()
+    }
+}
+
+/// The error for a call to `function` with `arguments`, whose clause `keyword (condition)` is
+/// not met.
+pub(super) fn violation(
+    function: &ast::Function,
+    keyword: &str,
+    condition: &ast::Expression,
+    arguments: Vec<(String, Value)>,
+) -> InterpreterError {
+    InterpreterError::ContractViolation(
+        function.name.clone(),
+        format!("{} ({})", keyword, condition),
+        arguments,
+    )
+}
+
+/// Describes the arguments of a call as `name = value, ...`.
+pub(super) fn describe_arguments(arguments: &[(String, Value)]) -> String {
+    arguments
+        .iter()
+        .map(|(name, value)| format!("{} = {}", name, value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,64 @@
+//! Formatting the values interpolated into strings as their specs say.
+
+use super::InterpreterError;
+use crate::ast::{Alignment, FormatSpec, Radix};
+use crate::value::Value;
+use num_bigint::Sign;
+
+/// `value` as `${value:spec}` puts it into a string. A radix is only valid for integers.
+pub(super) fn format_value(value: &Value, spec: &FormatSpec) -> Result<String, InterpreterError> {
+    let (sign, digits) = match (value, spec.radix) {
+        (Value::Integer(_) | Value::BigInteger(_) | Value::SizedInteger(..), radix) => {
+            let integer = value.as_big_integer()?;
+            let magnitude = integer.magnitude();
+            let digits = match radix {
+                None => magnitude.to_string(),
+                Some(Radix::Binary) => format!("{:b}", magnitude),
+                Some(Radix::Octal) => format!("{:o}", magnitude),
+                Some(Radix::LowerHex) => format!("{:x}", magnitude),
+                Some(Radix::UpperHex) => format!("{:X}", magnitude),
+            };
+            (
+                if integer.sign() == Sign::Minus {
+                    "-"
+                } else {
+                    ""
+                },
+                digits,
+            )
+        }
+        (value, Some(_)) => return Err(InterpreterError::NotAnInteger(value.clone())),
+        (value, None) => ("", value.to_string()),
+    };
+
+    let length = sign.chars().count() + digits.chars().count();
+    let padding = spec.width.saturating_sub(length);
+    // 0 埋めは符号の後ろに入れる
+    let integer = matches!(
+        value,
+        Value::Integer(_) | Value::BigInteger(_) | Value::SizedInteger(..)
+    );
+    if spec.zero && integer {
+        return Ok(format!("{}{}{}", sign, "0".repeat(padding), digits));
+    }
+
+    let align = spec.align.unwrap_or(if integer {
+        Alignment::Right
+    } else {
+        Alignment::Left
+    });
+    let (before, after) = match align {
+        Alignment::Left => (0, padding),
+        Alignment::Center => (padding / 2, padding - padding / 2),
+        Alignment::Right => (padding, 0),
+    };
+    let fill = spec.fill.unwrap_or(' ').to_string();
+
+    Ok(format!(
+        "{}{}{}{}",
+        fill.repeat(before),
+        sign,
+        digits,
+        fill.repeat(after)
+    ))
+}
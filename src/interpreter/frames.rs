@@ -0,0 +1,341 @@
+//! The stack of function calls. A call runs in a frame of its own: it starts with no variables
+//! but its parameters, and sees only the functions visible where the function is defined. What the
+//! caller sees is put aside in the frame until the call returns, so nothing is copied on a call.
+//!
+//! Besides its own variables, a call sees the global ones and, for a function defined in a block,
+//! those of the code running that block for as long as that code runs.
+
+use std::collections::HashMap;
+use std::mem;
+use std::rc::{Rc, Weak};
+
+use super::code::FunctionCode;
+use super::{Environment, Interpreter, InterpreterError, Unwind};
+use crate::ast;
+use crate::value::Value;
+
+/// How many of the innermost calls `StackOverflow` names.
+const STACK_OVERFLOW_FRAMES: usize = 8;
+
+/// A function as the function environment binds it.
+#[derive(Clone)]
+pub(super) struct FunctionBinding {
+    pub(super) function: Rc<ast::Function>,
+    pub(super) code: Rc<FunctionCode>,
+    /// For a function defined in a block, the frame which keeps the variables of the code running
+    /// the block while the function is called.
+    pub(super) enclosing: Option<Enclosing>,
+}
+
+/// The frame which keeps the variables of the code running a block while a function defined in the
+/// block is called.
+#[derive(Clone)]
+pub(super) struct Enclosing {
+    frame: usize,
+    /// The call running the block, or `None` for the top-level statements. Once it has returned,
+    /// as it may have when a generator defined in the block is resumed, its variables are gone.
+    call: Option<Weak<()>>,
+}
+
+impl Enclosing {
+    /// The index of the frame, if the call running the block has not returned.
+    pub(super) fn frame(&self) -> Option<usize> {
+        match &self.call {
+            Some(call) if call.strong_count() == 0 => None,
+            _ => Some(self.frame),
+        }
+    }
+}
+
+impl FunctionBinding {
+    pub(super) fn top_level(function: ast::Function) -> Self {
+        FunctionBinding {
+            code: Rc::new(FunctionCode::compile(&function)),
+            function: Rc::new(function),
+            enclosing: None,
+        }
+    }
+}
+
+/// A call which has not returned yet.
+pub(super) struct CallFrame {
+    /// The name of the function called.
+    function: String,
+    /// The variables of the caller.
+    variables: Environment<Value>,
+    /// The function scopes of the caller which the callee does not see, innermost first.
+    functions: Vec<HashMap<String, FunctionBinding>>,
+    /// The `enclosing` frame of the function called.
+    enclosing: Option<usize>,
+    /// Held while the call has not returned, for the functions defined in it to tell.
+    running: Rc<()>,
+}
+
+/// How many calls and scopes are open at some point, to go back to when an error leaves more of
+/// them open.
+#[derive(Clone, Copy)]
+pub(super) struct Checkpoint {
+    calls: usize,
+    variable_scopes: usize,
+    function_scopes: usize,
+}
+
+/// Scopes taken out of the environments, outermost first.
+#[derive(Default)]
+pub(super) struct Scopes {
+    pub(super) variables: Vec<HashMap<String, Value>>,
+    pub(super) functions: Vec<HashMap<String, FunctionBinding>>,
+}
+
+impl Checkpoint {
+    /// How far this checkpoint is above `base`, to be put on another base with `on`.
+    pub(super) fn above(self, base: Checkpoint) -> Checkpoint {
+        Checkpoint {
+            calls: self.calls - base.calls,
+            variable_scopes: self.variable_scopes - base.variable_scopes,
+            function_scopes: self.function_scopes - base.function_scopes,
+        }
+    }
+
+    pub(super) fn on(self, base: Checkpoint) -> Checkpoint {
+        Checkpoint {
+            calls: self.calls + base.calls,
+            variable_scopes: self.variable_scopes + base.variable_scopes,
+            function_scopes: self.function_scopes + base.function_scopes,
+        }
+    }
+}
+
+impl Interpreter {
+    pub(super) fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            calls: self.call_stack.len(),
+            variable_scopes: self.variable_environment.depth(),
+            function_scopes: self.function_environment.depth(),
+        }
+    }
+
+    /// Closes the calls and then the scopes opened since `checkpoint` which are still open.
+    pub(super) fn unwind_to(&mut self, checkpoint: Checkpoint) {
+        while self.call_stack.len() > checkpoint.calls {
+            self.leave_call();
+        }
+        while self.variable_environment.depth() > checkpoint.variable_scopes {
+            self.variable_environment.pop_scope();
+        }
+        while self.function_environment.depth() > checkpoint.function_scopes {
+            self.function_environment.pop_scope();
+        }
+    }
+
+    /// Takes the scopes opened since `checkpoint` out, for them to be opened again later.
+    pub(super) fn take_scopes(&mut self, checkpoint: Checkpoint) -> Scopes {
+        let mut scopes = Scopes::default();
+        while self.variable_environment.depth() > checkpoint.variable_scopes {
+            scopes
+                .variables
+                .push(self.variable_environment.pop_bindings());
+        }
+        while self.function_environment.depth() > checkpoint.function_scopes {
+            scopes
+                .functions
+                .push(self.function_environment.pop_bindings());
+        }
+        scopes.variables.reverse();
+        scopes.functions.reverse();
+
+        scopes
+    }
+
+    /// Opens the scopes which `take_scopes` has taken out again, on top of those open now.
+    pub(super) fn open_scopes(&mut self, scopes: Scopes) {
+        for mut scope in scopes.functions {
+            // 関数は、スコープが開き直されたフレームの変数を見る
+            for binding in scope.values_mut() {
+                *binding = self.bind_block_function(&binding.function, &binding.code);
+            }
+            self.function_environment.push_bindings(scope);
+        }
+        for scope in scopes.variables {
+            self.variable_environment.push_bindings(scope);
+        }
+    }
+
+    /// Runs `f`, closing whatever calls and scopes it leaves open if it fails.
+    pub(super) fn restoring<T, E>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, E>,
+    ) -> Result<T, E> {
+        let checkpoint = self.checkpoint();
+        let result = f(self);
+        if result.is_err() {
+            self.unwind_to(checkpoint);
+        }
+
+        result
+    }
+
+    /// Pushes the frame of a call to the function `binding` binds, whose code binds the
+    /// parameters and checks the `requires` clauses in it.
+    pub(super) fn enter_function(
+        &mut self,
+        binding: &FunctionBinding,
+    ) -> Result<(), InterpreterError> {
+        self.check_call_depth()?;
+        let enclosing = binding.enclosing.as_ref().and_then(Enclosing::frame);
+        self.enter_call(&binding.function.name, enclosing);
+
+        Ok(())
+    }
+
+    /// Pops the frame of a call whose code has finished with `result`.
+    pub(super) fn leave_function(
+        &mut self,
+        result: Result<Value, Unwind>,
+    ) -> Result<Value, InterpreterError> {
+        // `return` stops at the function boundary, while loop signals must not leak into the
+        // caller's loops
+        let result = match result {
+            Ok(value) | Err(Unwind::Return(value)) => Ok(value),
+            Err(unwind) => Err(unwind.into_error()),
+        };
+        self.leave_call();
+
+        result
+    }
+
+    /// Pushes the frame of a call to the function `name`, which must be visible.
+    fn enter_call(&mut self, name: &str, enclosing: Option<usize>) {
+        // 呼び出し先からは、定義された場所から見える関数だけが見える
+        let mut functions = vec![];
+        while !self.function_environment.bindings.contains_key(name) {
+            functions.push(self.function_environment.pop_bindings());
+        }
+
+        self.push_frame(name, functions, enclosing);
+    }
+
+    /// Pushes the frame of a call to the function `name`, hiding the function scopes `functions`
+    /// from it.
+    pub(super) fn push_frame(
+        &mut self,
+        name: &str,
+        functions: Vec<HashMap<String, FunctionBinding>>,
+        enclosing: Option<usize>,
+    ) {
+        self.call_stack.push(CallFrame {
+            function: name.to_string(),
+            variables: mem::replace(&mut self.variable_environment, Environment::new()),
+            functions,
+            enclosing,
+            running: Rc::new(()),
+        });
+    }
+
+    /// Pops the frame of the innermost call, giving the caller back what it saw.
+    pub(super) fn leave_call(&mut self) {
+        let frame = self
+            .call_stack
+            .pop()
+            .expect("leave_call must be paired with enter_call");
+
+        self.variable_environment = frame.variables;
+        for scope in frame.functions.into_iter().rev() {
+            self.function_environment.push_bindings(scope);
+        }
+    }
+
+    /// Fails if another frame would nest calls deeper than `max_call_depth`.
+    pub(super) fn check_call_depth(&self) -> Result<(), InterpreterError> {
+        if self.call_stack.len() >= self.max_call_depth {
+            return Err(InterpreterError::StackOverflow(
+                self.call_stack.len(),
+                self.innermost_calls(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The names of the functions of the innermost calls, for errors to report.
+    pub(super) fn innermost_calls(&self) -> Vec<String> {
+        self.call_stack
+            .iter()
+            .rev()
+            .take(STACK_OVERFLOW_FRAMES)
+            .map(|frame| frame.function.clone())
+            .collect()
+    }
+
+    /// Binds the functions defined by a block which the running code is evaluating.
+    pub(super) fn bind_block_function(
+        &self,
+        function: &Rc<ast::Function>,
+        code: &Rc<FunctionCode>,
+    ) -> FunctionBinding {
+        // 呼び出されたとき、この関数を定義したコードの変数は次のフレームに退避されている
+        FunctionBinding {
+            function: Rc::clone(function),
+            code: Rc::clone(code),
+            enclosing: Some(Enclosing {
+                frame: self.call_stack.len(),
+                call: self
+                    .call_stack
+                    .last()
+                    .map(|frame| Rc::downgrade(&frame.running)),
+            }),
+        }
+    }
+
+    /// The scopes of variables a call sees besides its own, innermost first: those of the code
+    /// running the blocks which define the function called, and those of the functions which
+    /// define that code in turn.
+    fn enclosing_frames(&self) -> impl Iterator<Item = usize> + '_ {
+        let mut enclosing = self.call_stack.last().and_then(|frame| frame.enclosing);
+        std::iter::from_fn(move || {
+            let index = enclosing?;
+            enclosing = index
+                .checked_sub(1)
+                .and_then(|caller| self.call_stack[caller].enclosing);
+            Some(index)
+        })
+    }
+
+    /// The value of the variable `name`, looked up in the scopes of the running call, then in
+    /// the enclosing ones, and then among the global variables.
+    pub(super) fn variable(&self, name: &str) -> Option<&Value> {
+        if let Some(bindings) = self.variable_environment.find_binding(name) {
+            return bindings.get(name);
+        }
+        for index in self.enclosing_frames() {
+            if let Some(bindings) = self.call_stack[index].variables.find_binding(name) {
+                return bindings.get(name);
+            }
+        }
+
+        self.globals.get(name)
+    }
+
+    pub(super) fn variable_mut(&mut self, name: &str) -> Option<&mut Value> {
+        if self.variable_environment.find_binding(name).is_some() {
+            return self
+                .variable_environment
+                .find_binding_mut(name)
+                .and_then(|bindings| bindings.get_mut(name));
+        }
+        let enclosing = self.enclosing_frames().find(|&index| {
+            self.call_stack[index]
+                .variables
+                .find_binding(name)
+                .is_some()
+        });
+        if let Some(index) = enclosing {
+            return self.call_stack[index]
+                .variables
+                .find_binding_mut(name)
+                .and_then(|bindings| bindings.get_mut(name));
+        }
+
+        self.globals.get_mut(name)
+    }
+}
@@ -0,0 +1,119 @@
+//! Generators, whose bodies run on the machine of whatever resumes them until they yield.
+//!
+//! A suspended body keeps where its code was, the loops and `try`s it is inside of and the scopes
+//! it has opened, to be put back on the machine which resumes it next. A body which is never
+//! resumed again does not run the finally clauses it is inside of. Resuming a generator takes a frame
+//! as a call does, so that generators iterating over one another nest as deep as calls can.
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use super::frames::{Enclosing, FunctionBinding, Scopes};
+use super::machine::Continuation;
+use super::{Environment, Interpreter, InterpreterError};
+use crate::value::{Generator, Value};
+
+pub struct GeneratorState {
+    /// For a generator function defined in a block, the frame whose variables the body sees.
+    enclosing: Option<Enclosing>,
+    status: Status,
+}
+
+enum Status {
+    /// Suspended at the start of its body or at a `yield`.
+    Suspended(Suspended),
+    /// Running, so that it cannot be resumed until it yields.
+    Running,
+    Finished,
+}
+
+/// The body of a generator while it is suspended.
+pub(super) struct Suspended {
+    /// The functions visible where the generator function is defined.
+    pub(super) function_environment: Environment<FunctionBinding>,
+    /// The scopes opened by the body. The first variable scope holds the parameters.
+    pub(super) scopes: Scopes,
+    /// Where the body goes on.
+    pub(super) continuation: Continuation,
+}
+
+/// What a `for` loop without a range takes its values from.
+pub(super) enum Items {
+    Tuple(std::vec::IntoIter<Value>),
+    Generator(Generator),
+}
+
+impl Items {
+    pub(super) fn new(iterable: Value) -> Result<Self, InterpreterError> {
+        match iterable {
+            Value::Tuple(elements) => Ok(Items::Tuple(elements.into_iter())),
+            Value::Generator(generator) => Ok(Items::Generator(generator)),
+            _ => Err(InterpreterError::NotIterable(iterable)),
+        }
+    }
+}
+
+impl Generator {
+    /// Takes the suspended body out to run it. `None` means the body has finished.
+    pub(super) fn take(&self) -> Result<Option<Suspended>, InterpreterError> {
+        let mut state = self.state.borrow_mut();
+        match mem::replace(&mut state.status, Status::Running) {
+            Status::Suspended(suspended) => Ok(Some(suspended)),
+            Status::Running => Err(InterpreterError::GeneratorRunning(self.name.clone())),
+            Status::Finished => {
+                state.status = Status::Finished;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Puts the body taken out back, suspended at a `yield`.
+    pub(super) fn suspend(&self, suspended: Suspended) {
+        self.state.borrow_mut().status = Status::Suspended(suspended);
+    }
+
+    pub(super) fn finish(&self) {
+        self.state.borrow_mut().status = Status::Finished;
+    }
+
+    /// The index of the frame whose variables the body sees besides its own, if there is one
+    /// still.
+    pub(super) fn enclosing_frame(&self) -> Option<usize> {
+        self.state
+            .borrow()
+            .enclosing
+            .as_ref()
+            .and_then(Enclosing::frame)
+    }
+}
+
+impl Interpreter {
+    /// Leaves a call to the generator function `binding` binds, whose parameters are bound, and
+    /// gives back a generator whose body goes on at `continuation`.
+    pub(super) fn generate(
+        &mut self,
+        binding: &FunctionBinding,
+        continuation: Continuation,
+    ) -> Value {
+        // 仮引数は呼び出しのフレームの一番外側のスコープに束縛されている
+        let parameters = mem::take(&mut self.variable_environment.bindings);
+        let suspended = Suspended {
+            function_environment: self.function_environment.clone(),
+            scopes: Scopes {
+                variables: vec![parameters],
+                functions: vec![],
+            },
+            continuation,
+        };
+        self.leave_call();
+
+        Value::Generator(Generator {
+            name: binding.function.name.clone(),
+            state: Rc::new(RefCell::new(GeneratorState {
+                enclosing: binding.enclosing.clone(),
+                status: Status::Suspended(suspended),
+            })),
+        })
+    }
+}
@@ -0,0 +1,1024 @@
+//! The machine which runs compiled code. Instead of recursing on the native stack, it keeps the
+//! values being computed, the loops and `try`s it is inside of and the calls and generators which
+//! have not returned or yielded yet in vectors of its own, so that how deep programs can recurse is
+//! limited only by `max_call_depth` and memory.
+//!
+//! Default arguments, contracts and generators resumed by `next` run on the machine of the call
+//! too, so that a machine is only started by the interpreter itself, for a program or an
+//! expression it is given, and never by another machine.
+
+use std::mem;
+use std::rc::Rc;
+
+use super::builtins::{self, Builtin};
+use super::code::{CallSite, Code, Instruction};
+use super::contracts;
+use super::frames::{Checkpoint, FunctionBinding};
+use super::generator::{Items, Suspended};
+use super::{
+    arithmetic, format, in_range, ArithmeticMode, Environment, Interpreter, InterpreterError,
+    Unwind,
+};
+use crate::ast;
+use crate::value::{Generator, Value};
+
+/// What a call site calls, as looked up before its arguments are evaluated.
+enum Callee {
+    Constructor,
+    Builtin(Builtin),
+    /// The builtin `next`, which resumes a generator on the machine.
+    Next,
+    Function(FunctionBinding),
+}
+
+/// How far to go back when an unwind reaches a control.
+#[derive(Clone, Copy)]
+struct Mark {
+    checkpoint: Checkpoint,
+    values: usize,
+    callees: usize,
+}
+
+impl Mark {
+    /// How far this mark is above `base`, to be put on another base with `on`.
+    fn above(self, base: Mark) -> Mark {
+        Mark {
+            checkpoint: self.checkpoint.above(base.checkpoint),
+            values: self.values - base.values,
+            callees: self.callees - base.callees,
+        }
+    }
+
+    fn on(self, base: Mark) -> Mark {
+        Mark {
+            checkpoint: self.checkpoint.on(base.checkpoint),
+            values: self.values + base.values,
+            callees: self.callees + base.callees,
+        }
+    }
+}
+
+enum Loop {
+    While,
+    For {
+        next: Option<i64>,
+        end: i64,
+        inclusive: bool,
+        step: i64,
+    },
+    ForEach(Items),
+}
+
+/// Something which is waiting for the code it has started to finish or to unwind.
+enum Control {
+    /// A call whose code is running. `code` and `pc` are where the caller goes on.
+    Call {
+        function: FunctionBinding,
+        /// The values given for the parameters which are not bound yet.
+        given: Vec<Option<Value>>,
+        /// The values of the parameters once they are bound, if the contract is checked.
+        arguments: Vec<(String, Value)>,
+        code: Rc<Code>,
+        pc: usize,
+        mark: Mark,
+    },
+    /// A loop, which `continue` restarts at `start`.
+    Loop {
+        state: Loop,
+        start: usize,
+        exit: usize,
+        mark: Mark,
+    },
+    /// A `try` whose body is running, or whose catch clause is once `catch` is `None`.
+    Try {
+        catch: Option<usize>,
+        finally: Option<usize>,
+        mark: Mark,
+    },
+    /// A finally clause, and what it goes on with when it finishes.
+    Finally { pending: Result<Value, Unwind> },
+    /// A generator whose body is running in its own frame. `code` and `pc` are where what resumed
+    /// it goes on with the value it yields, and `done` where that goes on once the body finishes,
+    /// or `None` if `GeneratorExhausted` is raised there instead. `functions` are the functions
+    /// which what resumed it sees.
+    Generator {
+        generator: Generator,
+        functions: Environment<FunctionBinding>,
+        code: Rc<Code>,
+        pc: usize,
+        done: Option<usize>,
+        mark: Mark,
+    },
+}
+
+impl Control {
+    fn mark_mut(&mut self) -> Option<&mut Mark> {
+        match self {
+            Control::Call { mark, .. }
+            | Control::Loop { mark, .. }
+            | Control::Try { mark, .. }
+            | Control::Generator { mark, .. } => Some(mark),
+            Control::Finally { .. } => None,
+        }
+    }
+}
+
+/// Where some code goes on, taken off a machine. Its marks are relative to where it was taken.
+pub(super) struct Continuation {
+    code: Rc<Code>,
+    pc: usize,
+    values: Vec<Value>,
+    callees: Vec<Callee>,
+    controls: Vec<Control>,
+}
+
+impl Continuation {
+    /// The instruction at `pc` of `code`, with nothing open.
+    pub(super) fn new(code: Rc<Code>, pc: usize) -> Self {
+        Continuation {
+            code,
+            pc,
+            values: vec![],
+            callees: vec![],
+            controls: vec![],
+        }
+    }
+}
+
+/// The function of the running call, the values given for its parameters not bound yet and those
+/// of its parameters for the contract.
+type RunningCall<'m> = (
+    &'m FunctionBinding,
+    &'m mut Vec<Option<Value>>,
+    &'m mut Vec<(String, Value)>,
+);
+
+struct Machine {
+    code: Rc<Code>,
+    pc: usize,
+    values: Vec<Value>,
+    callees: Vec<Callee>,
+    controls: Vec<Control>,
+}
+
+impl Machine {
+    fn new(code: Rc<Code>, pc: usize) -> Self {
+        Machine {
+            code,
+            pc,
+            values: vec![],
+            callees: vec![],
+            controls: vec![],
+        }
+    }
+}
+
+impl Interpreter {
+    /// Runs `code`, giving back its value.
+    pub(super) fn execute(&mut self, code: &Rc<Code>) -> Result<Value, Unwind> {
+        self.run_nested(Machine::new(Rc::clone(code), 0))
+    }
+
+    /// Calls the function `binding` binds with the values of the arguments, which are evaluated
+    /// by the caller.
+    pub(super) fn call_function(
+        &mut self,
+        binding: &FunctionBinding,
+        args: Vec<Value>,
+        named_args: Vec<(&str, Value)>,
+    ) -> Result<Value, InterpreterError> {
+        // 呼び出しが終わると空のコードの末尾に戻り、その値で終わる
+        let code = Rc::new(Code {
+            instructions: vec![],
+        });
+        let mut machine = Machine::new(code, 0);
+        machine.call(self, binding.clone(), args, named_args)?;
+
+        self.run_nested(machine).map_err(Unwind::into_error)
+    }
+
+    /// Runs `machine`, closing whatever calls and scopes it leaves open if it fails.
+    fn run_nested(&mut self, mut machine: Machine) -> Result<Value, Unwind> {
+        let checkpoint = self.checkpoint();
+        let result = machine.run(self);
+        if result.is_err() {
+            self.unwind_to(checkpoint);
+        }
+
+        result
+    }
+
+    /// Finds what `call` calls, failing before its arguments are evaluated if it cannot be
+    /// called with them.
+    fn resolve(&self, call: &CallSite) -> Result<Callee, InterpreterError> {
+        let name = &call.name;
+        if self.function_environment.find_binding(name).is_none() {
+            let callee = match self.constructors.get(name) {
+                Some(variant) => Some((Callee::Constructor, Some(variant.fields.len()))),
+                None if name == "next" => Some((Callee::Next, None)),
+                None => builtins::lookup(name).map(|builtin| (Callee::Builtin(builtin), None)),
+            };
+
+            if let Some((callee, arity)) = callee {
+                if let Some(arg_name) = call.named_args.first() {
+                    return Err(InterpreterError::UnknownNamedArgument(
+                        name.clone(),
+                        arg_name.clone(),
+                    ));
+                }
+                if let Some(arity) = arity.filter(|&arity| arity != call.args) {
+                    return Err(InterpreterError::ConstructorArity(
+                        name.clone(),
+                        arity,
+                        call.args,
+                    ));
+                }
+
+                return Ok(callee);
+            }
+        }
+
+        self.function_environment
+            .find_binding(name)
+            .and_then(|bindings| bindings.get(name))
+            .cloned()
+            .map(Callee::Function)
+            .ok_or_else(|| InterpreterError::FunctionNotFound(name.clone()))
+    }
+}
+
+impl Machine {
+    fn run(&mut self, interpreter: &mut Interpreter) -> Result<Value, Unwind> {
+        loop {
+            let code = Rc::clone(&self.code);
+            let Some(instruction) = code.instructions.get(self.pc) else {
+                let value = self.pop();
+                match self.controls.pop() {
+                    None => return Ok(value),
+                    Some(Control::Call { code, pc, .. }) => {
+                        interpreter.leave_call();
+                        self.code = code;
+                        self.pc = pc;
+                        self.values.push(value);
+                    }
+                    Some(Control::Generator {
+                        generator,
+                        functions,
+                        code,
+                        pc,
+                        done,
+                        ..
+                    }) => {
+                        if let Err(error) =
+                            self.finish(interpreter, &generator, functions, code, pc, done)
+                        {
+                            self.unwind(interpreter, error.into())?;
+                        }
+                    }
+                    Some(_) => unreachable!("code must close the controls it opens"),
+                }
+                continue;
+            };
+
+            self.pc += 1;
+            if let Err(unwind) = self.step(interpreter, instruction) {
+                self.unwind(interpreter, unwind)?;
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.values
+            .pop()
+            .expect("an instruction pops a value it has not pushed")
+    }
+
+    fn top(&mut self) -> &mut Value {
+        self.values
+            .last_mut()
+            .expect("an instruction takes a value it has not pushed")
+    }
+
+    /// Pops the values of the last `count` operands, in the order they were pushed.
+    fn pop_many(&mut self, count: usize) -> Vec<Value> {
+        self.values.split_off(self.values.len() - count)
+    }
+
+    fn mark(&self, interpreter: &Interpreter) -> Mark {
+        Mark {
+            checkpoint: interpreter.checkpoint(),
+            values: self.values.len(),
+            callees: self.callees.len(),
+        }
+    }
+
+    fn restore(&mut self, interpreter: &mut Interpreter, mark: Mark) {
+        interpreter.unwind_to(mark.checkpoint);
+        self.values.truncate(mark.values);
+        self.callees.truncate(mark.callees);
+    }
+
+    /// Leaves the code running until a control takes `unwind` over. Gives it back if none does.
+    fn unwind(&mut self, interpreter: &mut Interpreter, mut unwind: Unwind) -> Result<(), Unwind> {
+        loop {
+            let Some(control) = self.controls.pop() else {
+                return Err(unwind);
+            };
+
+            unwind = match (control, unwind) {
+                (
+                    Control::Loop {
+                        state,
+                        start,
+                        exit,
+                        mark,
+                    },
+                    unwind @ (Unwind::Break | Unwind::Continue),
+                ) => {
+                    self.restore(interpreter, mark);
+                    self.pc = match unwind {
+                        Unwind::Break => exit,
+                        _ => start,
+                    };
+                    self.controls.push(Control::Loop {
+                        state,
+                        start,
+                        exit,
+                        mark,
+                    });
+                    return Ok(());
+                }
+                (
+                    Control::Try {
+                        catch: Some(catch),
+                        finally,
+                        mark,
+                    },
+                    Unwind::Error(error),
+                ) if !matches!(error, InterpreterError::Exit(_)) => {
+                    // 捕まえた例外が開いたままにした呼び出しやスコープは閉じておく
+                    self.restore(interpreter, mark);
+                    interpreter.variable_environment.push_scope();
+                    self.values.push(error.into_exception());
+                    self.controls.push(Control::Try {
+                        catch: None,
+                        finally,
+                        mark,
+                    });
+                    self.pc = catch;
+                    return Ok(());
+                }
+                (
+                    Control::Try {
+                        finally: Some(finally),
+                        mark,
+                        ..
+                    },
+                    unwind,
+                ) => {
+                    self.restore(interpreter, mark);
+                    self.controls.push(Control::Finally {
+                        pending: Err(unwind),
+                    });
+                    self.pc = finally;
+                    return Ok(());
+                }
+                (
+                    Control::Call {
+                        function,
+                        given,
+                        arguments,
+                        code,
+                        pc,
+                        mark,
+                    },
+                    unwind,
+                ) => {
+                    self.restore(interpreter, mark);
+                    match (unwind, function.code.ensures) {
+                        // 戻り値は ensures 節を確かめてから返す
+                        (Unwind::Return(value), Some(ensures)) => {
+                            self.values.push(value);
+                            self.code = Rc::clone(&function.code.call);
+                            self.pc = ensures;
+                            self.controls.push(Control::Call {
+                                function,
+                                given,
+                                arguments,
+                                code,
+                                pc,
+                                mark,
+                            });
+                            return Ok(());
+                        }
+                        (unwind, _) => {
+                            self.code = code;
+                            self.pc = pc;
+                            match interpreter.leave_function(Err(unwind)) {
+                                Ok(value) => {
+                                    self.values.push(value);
+                                    return Ok(());
+                                }
+                                Err(error) => error.into(),
+                            }
+                        }
+                    }
+                }
+                (
+                    Control::Generator {
+                        generator,
+                        functions,
+                        code,
+                        pc,
+                        done,
+                        mark,
+                    },
+                    unwind,
+                ) => {
+                    self.restore(interpreter, mark);
+                    let finished = self.finish(interpreter, &generator, functions, code, pc, done);
+                    match (unwind, finished) {
+                        // `return` finishes the generator. Its value is not yielded.
+                        (Unwind::Return(_), Ok(())) => return Ok(()),
+                        (Unwind::Return(_), Err(error)) => error.into(),
+                        (unwind, _) => unwind.into_error().into(),
+                    }
+                }
+                (_, unwind) => unwind,
+            };
+        }
+    }
+
+    fn step(
+        &mut self,
+        interpreter: &mut Interpreter,
+        instruction: &Instruction,
+    ) -> Result<(), Unwind> {
+        match instruction {
+            Instruction::Push(value) => self.values.push(value.clone()),
+            Instruction::PushBigInteger(value) => {
+                if interpreter.arithmetic_mode != ArithmeticMode::BigInteger {
+                    return Err(InterpreterError::IntegerOutOfRange(value.clone()).into());
+                }
+
+                self.values.push(value.clone());
+            }
+            Instruction::Append(literal) => self.append(literal),
+            Instruction::AppendFormatted(spec) => {
+                let value = self.pop();
+                self.append(&format::format_value(&value, spec)?);
+            }
+            Instruction::Load(name) => {
+                let value = match interpreter.variable(name) {
+                    Some(value) => value.clone(),
+                    // 引数のないコンストラクタは括弧なしでも値になる
+                    None => match interpreter.constructors.get(name) {
+                        Some(variant) if variant.fields.is_empty() => Value::Variant {
+                            name: name.clone(),
+                            fields: vec![],
+                        },
+                        _ => return Err(InterpreterError::VariableNotPresent(name.clone()).into()),
+                    },
+                };
+                self.values.push(value);
+            }
+            Instruction::Fetch(name) => {
+                let value = interpreter
+                    .variable(name)
+                    .cloned()
+                    .ok_or_else(|| InterpreterError::VariableNotPresent(name.clone()))?;
+                self.values.push(value);
+            }
+            Instruction::Update { operator, name } => {
+                let rhs = self.pop();
+                let current = self.pop();
+                let value = arithmetic::apply_binary(
+                    operator,
+                    &current,
+                    &rhs,
+                    interpreter.arithmetic_mode,
+                )?;
+
+                *interpreter
+                    .variable_mut(name)
+                    .ok_or_else(|| InterpreterError::VariableNotPresent(name.clone()))? =
+                    value.clone();
+                self.values.push(value);
+            }
+            Instruction::Assign(name) => {
+                let value = self.top().clone();
+                interpreter.assign_variable(name, value);
+            }
+            Instruction::Let(name) => {
+                let value = self.top().clone();
+                // 外側の同名の変数は書き換えず、最も内側のスコープに束縛する
+                interpreter.bind_variable(name, value);
+            }
+            Instruction::Destructure(pattern) => {
+                let value = self.top();
+
+                // 一部の変数だけが代入されることのないように、先に全体の形を確かめる
+                let mut bindings = Vec::new();
+                if !interpreter.pattern_matches(pattern, value, &mut bindings) {
+                    let expected = match pattern {
+                        ast::Pattern::Tuple { elements } => elements.len(),
+                        _ => 1,
+                    };
+                    return Err(
+                        InterpreterError::DestructuringMismatch(value.clone(), expected).into(),
+                    );
+                }
+
+                for (name, element) in bindings {
+                    interpreter.assign_variable(&name, element);
+                }
+            }
+            Instruction::Tuple(count) => {
+                let elements = self.pop_many(*count);
+                self.values.push(Value::Tuple(elements));
+            }
+            Instruction::TupleIndex(index) => {
+                let element = match self.pop() {
+                    Value::Tuple(mut elements) if *index < elements.len() => {
+                        elements.swap_remove(*index)
+                    }
+                    value @ Value::Tuple(_) => {
+                        return Err(InterpreterError::TupleIndexOutOfBounds(value, *index).into())
+                    }
+                    value => return Err(InterpreterError::NotATuple(value).into()),
+                };
+                self.values.push(element);
+            }
+            Instruction::Binary(operator) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                let value =
+                    arithmetic::apply_binary(operator, &lhs, &rhs, interpreter.arithmetic_mode)?;
+                self.values.push(value);
+            }
+            Instruction::Integer => {
+                let value = self.pop().as_integer()?;
+                self.values.push(Value::Integer(value));
+            }
+            Instruction::Pop => {
+                self.pop();
+            }
+            Instruction::Jump(target) => self.pc = *target,
+            Instruction::JumpUnless(target) => {
+                if self.pop().as_integer()? == 0 {
+                    self.pc = *target;
+                }
+            }
+            Instruction::MissingElse => {
+                return Err(InterpreterError::ElseClauseNoneUnderIfConditionNotMet.into())
+            }
+            Instruction::DefineFunctions(functions) => interpreter.define_functions(functions),
+            Instruction::PopFunctionScope => interpreter.function_environment.pop_scope(),
+            Instruction::EnterWhile { exit } => self.enter_loop(interpreter, Loop::While, *exit),
+            Instruction::EnterFor {
+                inclusive,
+                step,
+                exit,
+            } => {
+                let step = match step {
+                    true => self.pop().as_integer()?,
+                    false => 1,
+                };
+                let end = self.pop().as_integer()?;
+                let start = self.pop().as_integer()?;
+                if step == 0 {
+                    return Err(InterpreterError::ZeroStep.into());
+                }
+
+                // ループ変数はループの中でだけ見える
+                interpreter.variable_environment.push_scope();
+                let state = Loop::For {
+                    next: Some(start),
+                    end,
+                    inclusive: *inclusive,
+                    step,
+                };
+                self.enter_loop(interpreter, state, *exit);
+            }
+            Instruction::EnterForEach { exit } => {
+                let items = Items::new(self.pop())?;
+
+                interpreter.variable_environment.push_scope();
+                self.enter_loop(interpreter, Loop::ForEach(items), *exit);
+            }
+            Instruction::Iterate => {
+                let Some(Control::Loop { state, exit, .. }) = self.controls.last_mut() else {
+                    unreachable!("Iterate must be inside a loop");
+                };
+                let exit = *exit;
+                let item = match state {
+                    Loop::For {
+                        next,
+                        end,
+                        inclusive,
+                        step,
+                    } => match *next {
+                        Some(current) if in_range(current, *end, *inclusive, *step) => {
+                            *next = current.checked_add(*step);
+                            Some(Value::Integer(current))
+                        }
+                        _ => None,
+                    },
+                    Loop::ForEach(Items::Tuple(elements)) => elements.next(),
+                    Loop::ForEach(Items::Generator(generator)) => {
+                        // 値はジェネレータが yield したときに積まれる
+                        let generator = generator.clone();
+                        return Ok(self.resume(interpreter, &generator, Some(exit))?);
+                    }
+                    Loop::While => unreachable!("a `while` loop has nothing to iterate"),
+                };
+
+                match item {
+                    Some(item) => self.values.push(item),
+                    None => self.pc = exit,
+                }
+            }
+            Instruction::ExitLoop => {
+                let Some(Control::Loop { state, .. }) = self.controls.pop() else {
+                    unreachable!("ExitLoop must end a loop");
+                };
+                if !matches!(state, Loop::While) {
+                    interpreter.variable_environment.pop_scope();
+                }
+
+                self.values.push(Value::Integer(1));
+            }
+            Instruction::MatchArm { pattern, next } => {
+                let mut bindings = Vec::new();
+                if interpreter.pattern_matches(pattern, self.top(), &mut bindings) {
+                    // パターンで束縛される変数はそのアームの中でだけ見える
+                    interpreter.variable_environment.push_scope();
+                    interpreter.variable_environment.bindings.extend(bindings);
+                } else {
+                    self.pc = *next;
+                }
+            }
+            Instruction::Guard { next } => {
+                if self.pop().as_integer()? == 0 {
+                    interpreter.variable_environment.pop_scope();
+                    self.pc = *next;
+                }
+            }
+            Instruction::EndArm { end } => {
+                let value = self.pop();
+                interpreter.variable_environment.pop_scope();
+                *self.top() = value;
+                self.pc = *end;
+            }
+            Instruction::NoMatch => {
+                return Err(InterpreterError::NoMatchingArm(self.pop()).into());
+            }
+            Instruction::Resolve(call) => {
+                let callee = interpreter.resolve(call)?;
+                self.callees.push(callee);
+            }
+            Instruction::Invoke(call) => self.invoke(interpreter, call)?,
+            Instruction::Print { args, newline } => {
+                let args = self.pop_many(*args);
+                let mut text = args
+                    .iter()
+                    .map(Value::to_string)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if *newline {
+                    text.push('\n');
+                }
+                interpreter.write_output(&text)?;
+
+                self.values.push(Value::Integer(1));
+            }
+            Instruction::Return => return Err(Unwind::Return(self.pop())),
+            Instruction::Throw => return Err(InterpreterError::Uncaught(self.pop()).into()),
+            Instruction::Bind { parameter, next } => {
+                let (_, given, _) = self.running_call();
+                if let Some(value) = given[*parameter].take() {
+                    self.values.push(value);
+                    self.pc = *next;
+                }
+            }
+            Instruction::Requires { skip } => {
+                if !interpreter.check_contracts {
+                    self.pc = *skip;
+                    return Ok(());
+                }
+                let (function, _, arguments) = self.running_call();
+                *arguments = interpreter.contract_arguments(&function.function);
+            }
+            Instruction::Ensures { skip } => {
+                if !interpreter.check_contracts {
+                    self.pc = *skip;
+                    return Ok(());
+                }
+                let result = self.top().clone();
+                interpreter
+                    .variable_environment
+                    .bindings
+                    .insert("result".to_string(), result);
+            }
+            Instruction::Require(index) | Instruction::Ensure(index) => {
+                if self.pop().as_integer()? != 0 {
+                    return Ok(());
+                }
+                let (function, _, arguments) = self.running_call();
+                let function = &function.function;
+                let (keyword, conditions) = match instruction {
+                    Instruction::Require(_) => ("requires", &function.contract.requires),
+                    _ => ("ensures", &function.contract.ensures),
+                };
+                let arguments = mem::take(arguments);
+                return Err(contracts::violation(
+                    function,
+                    keyword,
+                    &conditions[*index],
+                    arguments,
+                )
+                .into());
+            }
+            Instruction::Generate => {
+                let Some(Control::Call {
+                    function, code, pc, ..
+                }) = self.controls.pop()
+                else {
+                    unreachable!("Generate must be in the code of a call");
+                };
+                let continuation = Continuation::new(Rc::clone(&self.code), self.pc);
+                let generator = interpreter.generate(&function, continuation);
+                self.code = code;
+                self.pc = pc;
+                self.values.push(generator);
+            }
+            Instruction::Yield => {
+                let value = self.top().clone();
+                self.suspend(interpreter, value);
+            }
+            Instruction::YieldOutsideGenerator => {
+                return Err(InterpreterError::YieldOutsideGenerator.into())
+            }
+            Instruction::Break => return Err(Unwind::Break),
+            Instruction::Continue => return Err(Unwind::Continue),
+            Instruction::EnterTry { catch, finally } => {
+                let mark = self.mark(interpreter);
+                self.controls.push(Control::Try {
+                    catch: *catch,
+                    finally: *finally,
+                    mark,
+                });
+            }
+            Instruction::EndTry { end } => self.end_try(*end),
+            Instruction::EndCatch { end } => {
+                interpreter.variable_environment.pop_scope();
+                self.end_try(*end);
+            }
+            Instruction::EndFinally => {
+                self.pop();
+                let Some(Control::Finally { pending }) = self.controls.pop() else {
+                    unreachable!("EndFinally must end a finally clause");
+                };
+                // finally 節は `return` などで抜けるときにも実行されている
+                self.values.push(pending?);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The call whose code is running, whose parameters and contract are what the instructions
+    /// around its body work on. Nothing its code opens is open at those instructions.
+    fn running_call(&mut self) -> RunningCall<'_> {
+        match self.controls.last_mut() {
+            Some(Control::Call {
+                function,
+                given,
+                arguments,
+                ..
+            }) => (function, given, arguments),
+            _ => unreachable!("parameters and contracts are only in the code of a call"),
+        }
+    }
+
+    /// Appends `text` to the string on top.
+    fn append(&mut self, text: &str) {
+        match self.top() {
+            Value::String(string) => string.push_str(text),
+            _ => unreachable!("Append must follow a string"),
+        }
+    }
+
+    fn enter_loop(&mut self, interpreter: &Interpreter, state: Loop, exit: usize) {
+        let mark = self.mark(interpreter);
+        self.controls.push(Control::Loop {
+            state,
+            start: self.pc,
+            exit,
+            mark,
+        });
+    }
+
+    /// Finishes the body or the catch clause of the innermost `try`, whose value is on top.
+    fn end_try(&mut self, end: usize) {
+        let Some(Control::Try { finally, .. }) = self.controls.pop() else {
+            unreachable!("EndTry must end a `try`");
+        };
+
+        match finally {
+            Some(finally) => {
+                let value = self.pop();
+                self.controls.push(Control::Finally { pending: Ok(value) });
+                self.pc = finally;
+            }
+            None => self.pc = end,
+        }
+    }
+
+    /// Calls the callee looked up last with the values of the arguments.
+    fn invoke(
+        &mut self,
+        interpreter: &mut Interpreter,
+        call: &CallSite,
+    ) -> Result<(), InterpreterError> {
+        let named_values = self.pop_many(call.named_args.len());
+        let args = self.pop_many(call.args);
+        let binding = match self.callees.pop().expect("Invoke must follow Resolve") {
+            Callee::Constructor => {
+                self.values.push(Value::Variant {
+                    name: call.name.clone(),
+                    fields: args,
+                });
+                return Ok(());
+            }
+            Callee::Builtin(builtin) => {
+                let value = builtin(interpreter, args)?;
+                self.values.push(value);
+                return Ok(());
+            }
+            Callee::Next => {
+                let given = args.len();
+                let [value] = <[Value; 1]>::try_from(args)
+                    .map_err(|_| InterpreterError::BuiltinArity("next".to_string(), 1, given))?;
+                return match value {
+                    Value::Generator(generator) => self.resume(interpreter, &generator, None),
+                    value => Err(InterpreterError::NotAGenerator(value)),
+                };
+            }
+            Callee::Function(binding) => binding,
+        };
+
+        let named_args = call
+            .named_args
+            .iter()
+            .map(String::as_str)
+            .zip(named_values)
+            .collect();
+
+        self.call(interpreter, binding, args, named_args)
+    }
+
+    /// Calls the function `binding` binds by switching to its code, to come back when it
+    /// finishes. A call to a generator function comes back as soon as its parameters are bound.
+    fn call(
+        &mut self,
+        interpreter: &mut Interpreter,
+        binding: FunctionBinding,
+        args: Vec<Value>,
+        named_args: Vec<(&str, Value)>,
+    ) -> Result<(), InterpreterError> {
+        let given = Interpreter::given_arguments(&binding.function, args, named_args)?;
+        interpreter.enter_function(&binding)?;
+        let mark = self.mark(interpreter);
+        let code = Rc::clone(&binding.code.call);
+        self.controls.push(Control::Call {
+            function: binding,
+            given,
+            arguments: vec![],
+            code: mem::replace(&mut self.code, code),
+            pc: mem::replace(&mut self.pc, 0),
+            mark,
+        });
+
+        Ok(())
+    }
+
+    /// Resumes `generator` by switching to its body, which goes on until it yields a value to
+    /// push here or until it finishes, after which this code goes on at `done`, or fails with
+    /// `GeneratorExhausted` if there is none.
+    fn resume(
+        &mut self,
+        interpreter: &mut Interpreter,
+        generator: &Generator,
+        done: Option<usize>,
+    ) -> Result<(), InterpreterError> {
+        // 再開した本体は、呼び出しと同じく自分のフレームで実行する
+        interpreter.check_call_depth()?;
+        let Some(Suspended {
+            function_environment,
+            scopes,
+            continuation,
+        }) = generator.take()?
+        else {
+            return match done {
+                Some(done) => {
+                    self.pc = done;
+                    Ok(())
+                }
+                None => Err(InterpreterError::GeneratorExhausted(generator.name.clone())),
+            };
+        };
+
+        interpreter.push_frame(&generator.name, vec![], generator.enclosing_frame());
+        let functions = mem::replace(&mut interpreter.function_environment, function_environment);
+        let mark = self.mark(interpreter);
+        interpreter.open_scopes(scopes);
+        self.controls.push(Control::Generator {
+            generator: generator.clone(),
+            functions,
+            code: mem::replace(&mut self.code, continuation.code),
+            pc: mem::replace(&mut self.pc, continuation.pc),
+            done,
+            mark,
+        });
+        self.values.extend(continuation.values);
+        self.callees.extend(continuation.callees);
+        for mut control in continuation.controls {
+            if let Some(control_mark) = control.mark_mut() {
+                *control_mark = control_mark.on(mark);
+            }
+            self.controls.push(control);
+        }
+
+        Ok(())
+    }
+
+    /// Suspends the generator whose body is running, taking the body and what it has opened off
+    /// the machine, and goes back to what resumed it with `value`.
+    fn suspend(&mut self, interpreter: &mut Interpreter, value: Value) {
+        let at = self
+            .controls
+            .iter()
+            .rposition(|control| matches!(control, Control::Generator { .. }))
+            .expect("Yield must be in the body of a generator");
+        // 本体が開いたままのループや try は、本体とともに中断される
+        let mut controls = self.controls.split_off(at + 1);
+        let Some(Control::Generator {
+            generator,
+            functions,
+            code,
+            pc,
+            mark,
+            ..
+        }) = self.controls.pop()
+        else {
+            unreachable!("the control found is a generator");
+        };
+        for control in &mut controls {
+            if let Some(control_mark) = control.mark_mut() {
+                *control_mark = control_mark.above(mark);
+            }
+        }
+
+        let continuation = Continuation {
+            code: mem::replace(&mut self.code, code),
+            pc: mem::replace(&mut self.pc, pc),
+            values: self.values.split_off(mark.values),
+            callees: self.callees.split_off(mark.callees),
+            controls,
+        };
+        let scopes = interpreter.take_scopes(mark.checkpoint);
+        let function_environment = mem::replace(&mut interpreter.function_environment, functions);
+        interpreter.leave_call();
+        generator.suspend(Suspended {
+            function_environment,
+            scopes,
+            continuation,
+        });
+
+        self.values.push(value);
+    }
+
+    /// Leaves the frame of `generator`, whose body has finished, going on at `done` of `code`.
+    /// Without `done`, `GeneratorExhausted` is raised at `pc` instead.
+    fn finish(
+        &mut self,
+        interpreter: &mut Interpreter,
+        generator: &Generator,
+        functions: Environment<FunctionBinding>,
+        code: Rc<Code>,
+        pc: usize,
+        done: Option<usize>,
+    ) -> Result<(), InterpreterError> {
+        interpreter.function_environment = functions;
+        interpreter.leave_call();
+        generator.finish();
+        self.code = code;
+        self.pc = done.unwrap_or(pc);
+
+        match done {
+            Some(_) => Ok(()),
+            None => Err(InterpreterError::GeneratorExhausted(generator.name.clone())),
+        }
+    }
+}
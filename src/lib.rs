@@ -1,3 +1,8 @@
 mod ast;
+pub mod checker;
 pub mod interpreter;
+pub mod macros;
+pub mod modules;
 pub mod parser;
+pub mod project;
+pub mod value;
@@ -0,0 +1,453 @@
+//! Expansion of `macro` definitions, run over a whole program before it is checked.
+//!
+//! A call to a macro is replaced by the body of the macro, with each parameter replaced by the
+//! corresponding argument. Expansion is hygienic: every other name the body binds is renamed to
+//! `name#n`, which no program can spell, so that it can neither capture nor be captured by the names
+//! around the call. Only the names of top-level definitions keep referring to them. `respell` gives
+//! those names a spelling when an expanded program is printed.
+
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::ast;
+use thiserror::Error;
+
+/// How many times the expansion of a macro call can contain another macro call.
+const RECURSION_LIMIT: usize = 64;
+
+#[derive(Error, Debug)]
+pub enum MacroError {
+    #[error("Macro {0} is defined more than once")]
+    DuplicateMacro(String),
+    #[error("{0} is defined both as a macro and as a function")]
+    NameConflict(String),
+    #[error("Macro {0} takes {1} argument(s) but {2} were given")]
+    MacroArity(String, usize, usize),
+    #[error("Macro {0} cannot take named arguments")]
+    NamedMacroArgument(String),
+    #[error("The argument for `{1}` of macro {0} must be a name, since `{1}` is bound or called")]
+    NotAName(String, String),
+    #[error("Macro {0} is still expanding after {1} levels of macro calls")]
+    RecursionLimit(String, usize),
+    #[error("`{1}` cannot be given to macro {0}, since it is a statement rather than a function")]
+    StatementArgument(String, String),
+}
+
+/// Replaces every macro call in `program` by its expansion, and drops the macro definitions.
+pub fn expand(program: ast::Program) -> Result<ast::Program, MacroError> {
+    let mut macros = HashMap::new();
+    let mut definitions = Vec::new();
+    for top_level in program.definitions {
+        match top_level {
+            ast::TopLevel::MacroDefinition(definition) => {
+                if macros.contains_key(&definition.name) {
+                    return Err(MacroError::DuplicateMacro(definition.name));
+                }
+                macros.insert(definition.name.clone(), definition);
+            }
+            top_level => definitions.push(top_level),
+        }
+    }
+
+    let mut top_level_names: HashSet<String> = macros.keys().cloned().collect();
+    for top_level in &definitions {
+        match top_level {
+            ast::TopLevel::FunctionDefinition(function) => {
+                if macros.contains_key(&function.name) {
+                    return Err(MacroError::NameConflict(function.name.clone()));
+                }
+                top_level_names.insert(function.name.clone());
+            }
+            ast::TopLevel::EnumDefinition(definition) => {
+                top_level_names.extend(
+                    definition
+                        .variants
+                        .iter()
+                        .map(|variant| variant.name.clone()),
+                );
+            }
+            ast::TopLevel::GlobalVariableDefinition { name, .. }
+            | ast::TopLevel::ConstantDefinition { name, .. } => {
+                top_level_names.insert(name.clone());
+            }
+            ast::TopLevel::MacroDefinition(_)
+            | ast::TopLevel::Import { .. }
+            | ast::TopLevel::Statement(_) => {}
+        }
+    }
+
+    let mut expander = Expander {
+        macros,
+        top_level_names,
+        expansions: 0,
+    };
+    for top_level in &mut definitions {
+        match top_level {
+            ast::TopLevel::FunctionDefinition(function) => expander.expand_function(function, 0)?,
+            ast::TopLevel::GlobalVariableDefinition { expression, .. }
+            | ast::TopLevel::ConstantDefinition { expression, .. }
+            | ast::TopLevel::Statement(expression) => expander.expand_expression(expression, 0)?,
+            ast::TopLevel::EnumDefinition(_)
+            | ast::TopLevel::MacroDefinition(_)
+            | ast::TopLevel::Import { .. } => {}
+        }
+    }
+
+    Ok(ast::Program { definitions })
+}
+
+struct Expander {
+    macros: HashMap<String, ast::Macro>,
+    /// The names which refer to the same definitions wherever they appear.
+    top_level_names: HashSet<String>,
+    /// How many macro calls have been expanded so far, which tells apart the names each expansion
+    /// introduces.
+    expansions: usize,
+}
+
+impl Expander {
+    fn expand_function(
+        &mut self,
+        function: &mut ast::Function,
+        depth: usize,
+    ) -> Result<(), MacroError> {
+        for expression in function.children_mut() {
+            self.expand_expression(expression, depth)?;
+        }
+        // マクロが `yield` に展開されたかもしれない
+        function.generator = function.body.contains_yield();
+
+        Ok(())
+    }
+
+    /// Expands the macro calls in `expression`. `depth` is how many expansions it came from.
+    fn expand_expression(
+        &mut self,
+        expression: &mut ast::Expression,
+        depth: usize,
+    ) -> Result<(), MacroError> {
+        if let ast::Expression::FunctionDefinition { function } = expression {
+            return self.expand_function(Rc::make_mut(function), depth);
+        }
+
+        // 引数を先に展開してから、呼び出しを置き換える
+        for child in expression.children_mut() {
+            self.expand_expression(child, depth)?;
+        }
+
+        let mut expansion = match expression {
+            ast::Expression::FunctionCall {
+                name,
+                args,
+                named_args,
+            } if self.macros.contains_key(name) => {
+                if depth == RECURSION_LIMIT {
+                    return Err(MacroError::RecursionLimit(name.clone(), RECURSION_LIMIT));
+                }
+                self.instantiate(name, args, named_args)?
+            }
+            _ => return Ok(()),
+        };
+        self.expand_expression(&mut expansion, depth + 1)?;
+        *expression = expansion;
+
+        Ok(())
+    }
+
+    /// The body of the macro `name` with `args` substituted for its parameters.
+    fn instantiate(
+        &mut self,
+        name: &str,
+        args: &[ast::Expression],
+        named_args: &[(String, ast::Expression)],
+    ) -> Result<ast::Expression, MacroError> {
+        self.expansions += 1;
+        let definition = &self.macros[name];
+        if !named_args.is_empty() {
+            return Err(MacroError::NamedMacroArgument(name.to_string()));
+        }
+        if args.len() != definition.params.len() {
+            return Err(MacroError::MacroArity(
+                name.to_string(),
+                definition.params.len(),
+                args.len(),
+            ));
+        }
+        // 引数は式として読まれるので、`println(..)` は存在しない関数の呼び出しになっている。
+        // 展開先で文として書き出されると、実行されないはずの出力文に見えてしまう
+        for arg in args {
+            if let ast::Expression::FunctionCall { name: callee, .. } = arg {
+                if callee == "print" || callee == "println" {
+                    return Err(MacroError::StatementArgument(
+                        name.to_string(),
+                        callee.clone(),
+                    ));
+                }
+            }
+        }
+
+        let mut body = definition.body.clone();
+        let mut nested_functions = HashSet::new();
+        collect_functions(&mut body, &mut nested_functions);
+
+        let substitution = Substitution {
+            name,
+            arguments: definition
+                .params
+                .iter()
+                .map(String::as_str)
+                .zip(args)
+                .collect(),
+            top_level_names: &self.top_level_names,
+            nested_functions,
+            expansion: self.expansions,
+        };
+        substitution.apply(&mut body)?;
+
+        // 文が一つだけなら、ブロックではなくその文に展開する
+        Ok(match body {
+            ast::Expression::Block { mut elements } if elements.len() == 1 => {
+                elements.pop().unwrap()
+            }
+            body => body,
+        })
+    }
+}
+
+/// What one expansion replaces the names in the body of a macro with.
+struct Substitution<'e> {
+    /// The name of the macro.
+    name: &'e str,
+    arguments: HashMap<&'e str, &'e ast::Expression>,
+    top_level_names: &'e HashSet<String>,
+    /// The functions defined inside the body, which are renamed along with the variables.
+    nested_functions: HashSet<String>,
+    expansion: usize,
+}
+
+impl Substitution<'_> {
+    fn fresh(&self, name: &str) -> String {
+        format!("{}#{}", name, self.expansion)
+    }
+
+    /// The argument for the parameter `name` where it is bound or called, which must be a name.
+    fn argument_name(&self, name: &str) -> Option<Result<String, MacroError>> {
+        self.arguments.get(name).map(|argument| match argument {
+            ast::Expression::Identifier { name } => Ok(name.clone()),
+            _ => Err(MacroError::NotAName(
+                self.name.to_string(),
+                name.to_string(),
+            )),
+        })
+    }
+
+    /// What a variable named `name` in the body is named in the expansion.
+    fn variable(&self, name: &str) -> Result<String, MacroError> {
+        match self.argument_name(name) {
+            Some(argument) => argument,
+            None if self.top_level_names.contains(name) => Ok(name.to_string()),
+            None => Ok(self.fresh(name)),
+        }
+    }
+
+    /// What a function named `name` in the body is named in the expansion.
+    fn function(&self, name: &str) -> Result<String, MacroError> {
+        match self.argument_name(name) {
+            Some(argument) => argument,
+            None if self.nested_functions.contains(name) => Ok(self.fresh(name)),
+            None => Ok(name.to_string()),
+        }
+    }
+
+    fn apply(&self, expression: &mut ast::Expression) -> Result<(), MacroError> {
+        match expression {
+            ast::Expression::Identifier { name } => {
+                // 引数はそのまま埋め込み、その中の名前には手を付けない
+                match self.arguments.get(name.as_str()) {
+                    Some(argument) => *expression = (*argument).clone(),
+                    None => *name = self.variable(name)?,
+                }
+                return Ok(());
+            }
+            ast::Expression::Assignment { name, .. }
+            | ast::Expression::Let { name, .. }
+            | ast::Expression::CompoundAssignment {
+                target: ast::AssignmentTarget::Variable { name },
+                ..
+            }
+            | ast::Expression::For { variable: name, .. }
+            | ast::Expression::ForEach { variable: name, .. } => *name = self.variable(name)?,
+            ast::Expression::Destructuring { pattern, .. } => self.apply_pattern(pattern)?,
+            ast::Expression::Match { arms, .. } => {
+                for arm in arms {
+                    self.apply_pattern(&mut arm.pattern)?;
+                }
+            }
+            ast::Expression::Try {
+                catch_clause: Some(catch_clause),
+                ..
+            } => catch_clause.variable = self.variable(&catch_clause.variable)?,
+            ast::Expression::FunctionDefinition { function } => {
+                let function = Rc::make_mut(function);
+                function.name = self.function(&function.name)?;
+                for arg in &mut function.args {
+                    arg.name = self.variable(&arg.name)?;
+                }
+            }
+            ast::Expression::FunctionCall { name, .. } => *name = self.function(name)?,
+            _ => {}
+        }
+
+        for child in expression.children_mut() {
+            self.apply(child)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_pattern(&self, pattern: &mut ast::Pattern) -> Result<(), MacroError> {
+        match pattern {
+            ast::Pattern::Binding { name } => *name = self.variable(name)?,
+            ast::Pattern::Variant { fields, .. } => {
+                for field in fields {
+                    self.apply_pattern(field)?;
+                }
+            }
+            ast::Pattern::Tuple { elements } => {
+                for element in elements {
+                    self.apply_pattern(element)?;
+                }
+            }
+            ast::Pattern::Wildcard | ast::Pattern::Literal { .. } | ast::Pattern::Range { .. } => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// Renames the names which expansion has made up, such as `tmp#1`, to ones a program can spell,
+/// so that the expanded program can be printed as source which parses back. Each gets a name
+/// nothing else in the program uses, e.g. `tmp_1`, which keeps the expansion hygienic.
+pub fn respell(program: &mut ast::Program) {
+    let mut used = HashSet::new();
+    for_each_name(program, &mut |name| {
+        used.insert(name.clone());
+    });
+
+    let mut spellings = HashMap::new();
+    for_each_name(program, &mut |name| {
+        if !name.contains('#') {
+            return;
+        }
+        let spelling = spellings.entry(name.clone()).or_insert_with(|| {
+            let mut spelling = name.replace('#', "_");
+            while used.contains(&spelling) {
+                spelling.push('_');
+            }
+            used.insert(spelling.clone());
+            spelling
+        });
+        name.clone_from(spelling);
+    });
+}
+
+/// Calls `f` with every name `program` defines or refers to.
+fn for_each_name(program: &mut ast::Program, f: &mut impl FnMut(&mut String)) {
+    for top_level in &mut program.definitions {
+        match top_level {
+            ast::TopLevel::FunctionDefinition(function) => function_names(function, f),
+            ast::TopLevel::EnumDefinition(definition) => {
+                for variant in &mut definition.variants {
+                    f(&mut variant.name);
+                }
+            }
+            ast::TopLevel::GlobalVariableDefinition { name, expression }
+            | ast::TopLevel::ConstantDefinition { name, expression } => {
+                f(name);
+                expression_names(expression, f);
+            }
+            ast::TopLevel::MacroDefinition(definition) => f(&mut definition.name),
+            ast::TopLevel::Import { alias, .. } => f(alias),
+            ast::TopLevel::Statement(expression) => expression_names(expression, f),
+        }
+    }
+}
+
+fn function_names(function: &mut ast::Function, f: &mut impl FnMut(&mut String)) {
+    f(&mut function.name);
+    for arg in &mut function.args {
+        f(&mut arg.name);
+    }
+    for child in function.children_mut() {
+        expression_names(child, f);
+    }
+}
+
+fn expression_names(expression: &mut ast::Expression, f: &mut impl FnMut(&mut String)) {
+    match expression {
+        ast::Expression::FunctionDefinition { function } => {
+            return function_names(Rc::make_mut(function), f);
+        }
+        ast::Expression::Identifier { name }
+        | ast::Expression::Assignment { name, .. }
+        | ast::Expression::Let { name, .. }
+        | ast::Expression::CompoundAssignment {
+            target: ast::AssignmentTarget::Variable { name },
+            ..
+        }
+        | ast::Expression::For { variable: name, .. }
+        | ast::Expression::ForEach { variable: name, .. } => f(name),
+        ast::Expression::FunctionCall {
+            name, named_args, ..
+        } => {
+            f(name);
+            for (name, _) in named_args {
+                f(name);
+            }
+        }
+        ast::Expression::Destructuring { pattern, .. } => pattern_names(pattern, f),
+        ast::Expression::Match { arms, .. } => {
+            for arm in arms {
+                pattern_names(&mut arm.pattern, f);
+            }
+        }
+        ast::Expression::Try {
+            catch_clause: Some(catch_clause),
+            ..
+        } => f(&mut catch_clause.variable),
+        _ => {}
+    }
+
+    for child in expression.children_mut() {
+        expression_names(child, f);
+    }
+}
+
+fn pattern_names(pattern: &mut ast::Pattern, f: &mut impl FnMut(&mut String)) {
+    match pattern {
+        ast::Pattern::Binding { name } => f(name),
+        ast::Pattern::Variant { name, fields } => {
+            f(name);
+            for field in fields {
+                pattern_names(field, f);
+            }
+        }
+        ast::Pattern::Tuple { elements } => {
+            for element in elements {
+                pattern_names(element, f);
+            }
+        }
+        ast::Pattern::Wildcard | ast::Pattern::Literal { .. } | ast::Pattern::Range { .. } => {}
+    }
+}
+
+/// Adds the names of the functions defined anywhere in `expression` to `names`.
+fn collect_functions(expression: &mut ast::Expression, names: &mut HashSet<String>) {
+    if let ast::Expression::FunctionDefinition { function } = expression {
+        names.insert(function.name.clone());
+    }
+
+    for child in expression.children_mut() {
+        collect_functions(child, names);
+    }
+}
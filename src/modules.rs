@@ -0,0 +1,538 @@
+//! Loading the modules a program imports, and linking them with the program into one.
+//!
+//! Each module has a namespace of its own: what a module defines is renamed to `module::name`,
+//! where `module` is the path of the module without its extension, e.g. `lib/math::gcd`. In the
+//! importing module, `alias::name` is renamed the same way.
+//!
+//! An import path is relative to the directory of the importing module, except that one starting
+//! with `@package/` refers to a module of the package `package`, which a `ModuleLoader` can check
+//! that the importing package depends on.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use crate::ast;
+use crate::parser::{self, ParseError};
+use thiserror::Error;
+
+/// The path the program which imports the others is known by.
+const MAIN: &str = "<main>";
+
+/// Where the sources of modules come from.
+pub trait ModuleLoader {
+    /// The source of the module at `path`, which is relative to the root of all modules, e.g.
+    /// `lib/math.toys`.
+    fn load(&self, path: &str) -> io::Result<String>;
+
+    /// Checks that the module at `importer` may import the one at `path`, before the latter is
+    /// loaded. Both paths are relative to the root of all modules. Any import is allowed unless a
+    /// loader tells otherwise.
+    fn check_import(&self, _importer: &str, _path: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Loads modules from the files under a directory.
+pub struct FileLoader {
+    root: PathBuf,
+}
+
+impl FileLoader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileLoader { root: root.into() }
+    }
+}
+
+impl ModuleLoader for FileLoader {
+    fn load(&self, path: &str) -> io::Result<String> {
+        fs::read_to_string(self.root.join(path))
+    }
+}
+
+impl<L: ModuleLoader + ?Sized> ModuleLoader for Box<L> {
+    fn load(&self, path: &str) -> io::Result<String> {
+        (**self).load(path)
+    }
+
+    fn check_import(&self, importer: &str, path: &str) -> io::Result<()> {
+        (**self).check_import(importer, path)
+    }
+}
+
+/// Serves the sources of modules from memory, keyed by their paths.
+impl ModuleLoader for HashMap<String, String> {
+    fn load(&self, path: &str) -> io::Result<String> {
+        self.get(path)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such module"))
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ModuleError {
+    #[error("Cannot load module {0}: {1}")]
+    Load(String, io::Error),
+    #[error("Module {0} cannot import {1}: {2}")]
+    ForbiddenImport(String, String, io::Error),
+    #[error("In module {0}: {1}")]
+    Parse(String, ParseError),
+    #[error("Modules import each other in a cycle: {}", .0.join(" -> "))]
+    ImportCycle(Vec<String>),
+    #[error("`{1}` names more than one import in {0}")]
+    DuplicateAlias(String, String),
+    #[error("No module is imported as `{1}` in {0}")]
+    UnknownModule(String, String),
+    #[error("Module {0} has no definition named `{1}`")]
+    UnknownMember(String, String),
+    #[error("`{1}` is defined more than once in {0}")]
+    DuplicateDefinition(String, String),
+    #[error("Module {0} has statements at top level, which only the main program can have")]
+    StatementInModule(String),
+}
+
+/// Loads the modules `program` imports, directly or not, through `loader`, and gives back the
+/// definitions of all of them as one program. A module imported more than once is loaded once, and
+/// its definitions come before those of every module which imports it.
+pub fn link(program: ast::Program, loader: &dyn ModuleLoader) -> Result<ast::Program, ModuleError> {
+    let mut linker = Linker {
+        loader,
+        modules: HashMap::new(),
+        loading: vec![],
+        definitions: vec![],
+    };
+    linker.add(MAIN, "", program)?;
+
+    Ok(ast::Program {
+        definitions: linker.definitions,
+    })
+}
+
+/// Loads the module at `path` through `loader` as the program which imports the others, and links
+/// it as `link` does. Its own definitions keep their names.
+pub fn load(path: &str, loader: &dyn ModuleLoader) -> Result<ast::Program, ModuleError> {
+    let mut linker = Linker {
+        loader,
+        modules: HashMap::new(),
+        loading: vec![],
+        definitions: vec![],
+    };
+    let program = linker.parse(path)?;
+    linker.add(path, "", program)?;
+
+    Ok(ast::Program {
+        definitions: linker.definitions,
+    })
+}
+
+/// The names a module defines at the top level.
+struct Namespace {
+    path: String,
+    /// Put before each of `names`. Empty for the main program, whose names are kept as they are.
+    prefix: String,
+    names: HashSet<String>,
+}
+
+impl Namespace {
+    fn qualify(&self, name: &str) -> String {
+        if self.prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}::{}", self.prefix, name)
+        }
+    }
+}
+
+struct Linker<'l> {
+    loader: &'l dyn ModuleLoader,
+    /// The modules linked so far, keyed by path.
+    modules: HashMap<String, Rc<Namespace>>,
+    /// The modules being loaded, each imported by the one before it.
+    loading: Vec<String>,
+    definitions: Vec<ast::TopLevel>,
+}
+
+impl Linker<'_> {
+    /// Links the module at `path`, unless it is already.
+    fn import(&mut self, path: &str) -> Result<Rc<Namespace>, ModuleError> {
+        if let Some(namespace) = self.modules.get(path) {
+            return Ok(Rc::clone(namespace));
+        }
+        if let Some(start) = self.loading.iter().position(|loading| loading == path) {
+            let mut cycle = self.loading[start..].to_vec();
+            cycle.push(path.to_string());
+            return Err(ModuleError::ImportCycle(cycle));
+        }
+
+        let program = self.parse(path)?;
+        let prefix = path.strip_suffix(".toys").unwrap_or(path);
+
+        self.add(path, prefix, program)
+    }
+
+    fn parse(&self, path: &str) -> Result<ast::Program, ModuleError> {
+        let source = self
+            .loader
+            .load(path)
+            .map_err(|error| ModuleError::Load(path.to_string(), error))?;
+
+        parser::parse(&source).map_err(|error| ModuleError::Parse(path.to_string(), error))
+    }
+
+    /// Links the module at `path`, whose source is parsed into `program`, after the modules it
+    /// imports. What it defines is renamed to `prefix::name`.
+    fn add(
+        &mut self,
+        path: &str,
+        prefix: &str,
+        program: ast::Program,
+    ) -> Result<Rc<Namespace>, ModuleError> {
+        self.loading.push(path.to_string());
+        let mut imports = HashMap::new();
+        let mut definitions = vec![];
+        for top_level in program.definitions {
+            match top_level {
+                ast::TopLevel::Import {
+                    path: imported,
+                    alias,
+                } => {
+                    let imported = resolve(path, &imported);
+                    self.loader.check_import(path, &imported).map_err(|error| {
+                        ModuleError::ForbiddenImport(path.to_string(), imported.clone(), error)
+                    })?;
+                    let namespace = self.import(&imported)?;
+                    if imports.insert(alias.clone(), namespace).is_some() {
+                        return Err(ModuleError::DuplicateAlias(path.to_string(), alias));
+                    }
+                }
+                // 文はプログラムの開始時に実行されるので、取り込まれるモジュールには書けない
+                ast::TopLevel::Statement(_) if !prefix.is_empty() => {
+                    return Err(ModuleError::StatementInModule(path.to_string()));
+                }
+                top_level => definitions.push(top_level),
+            }
+        }
+        self.loading.pop();
+
+        let mut names = HashSet::new();
+        for name in definitions.iter().flat_map(defined_names) {
+            if !names.insert(name.clone()) {
+                return Err(ModuleError::DuplicateDefinition(path.to_string(), name));
+            }
+        }
+        let namespace = Rc::new(Namespace {
+            path: path.to_string(),
+            prefix: prefix.to_string(),
+            names,
+        });
+
+        let renamer = Renamer {
+            namespace: &namespace,
+            imports: &imports,
+        };
+        for top_level in &mut definitions {
+            renamer.rename_top_level(top_level)?;
+        }
+        self.definitions.extend(definitions);
+        self.modules.insert(path.to_string(), Rc::clone(&namespace));
+
+        Ok(namespace)
+    }
+}
+
+/// The names `top_level` defines.
+fn defined_names(top_level: &ast::TopLevel) -> Vec<String> {
+    match top_level {
+        ast::TopLevel::FunctionDefinition(function) => vec![function.name.clone()],
+        ast::TopLevel::EnumDefinition(definition) => definition
+            .variants
+            .iter()
+            .map(|variant| variant.name.clone())
+            .collect(),
+        ast::TopLevel::GlobalVariableDefinition { name, .. }
+        | ast::TopLevel::ConstantDefinition { name, .. } => vec![name.clone()],
+        ast::TopLevel::MacroDefinition(definition) => vec![definition.name.clone()],
+        ast::TopLevel::Import { .. } | ast::TopLevel::Statement(_) => vec![],
+    }
+}
+
+/// The path of the module imported as `import` by the module at `importer`.
+fn resolve(importer: &str, import: &str) -> String {
+    let mut components: Vec<_> = importer.split('/').collect();
+    components.pop();
+    if import.starts_with('@') {
+        components.clear();
+    }
+
+    for component in import.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => match components.last() {
+                Some(&last) if last != ".." => {
+                    components.pop();
+                }
+                _ => components.push(".."),
+            },
+            component => components.push(component),
+        }
+    }
+
+    components.join("/")
+}
+
+/// Renames the names in one module to the ones they have in the linked program.
+struct Renamer<'r> {
+    namespace: &'r Namespace,
+    /// The modules imported, keyed by alias.
+    imports: &'r HashMap<String, Rc<Namespace>>,
+}
+
+impl Renamer<'_> {
+    /// The module which defines the name `name` refers to, and its name there.
+    fn resolve<'n>(&self, name: &'n str) -> Result<(&Namespace, &'n str), ModuleError> {
+        // 結合済みのプログラムを結合し直しても、名前は変わらない
+        let (alias, member) = match name.split_once("::") {
+            Some(qualified) if !self.namespace.names.contains(name) => qualified,
+            _ => return Ok((self.namespace, name)),
+        };
+
+        let namespace = self.imports.get(alias).ok_or_else(|| {
+            ModuleError::UnknownModule(self.namespace.path.clone(), alias.to_string())
+        })?;
+        if !namespace.names.contains(member) {
+            return Err(ModuleError::UnknownMember(
+                namespace.path.clone(),
+                member.to_string(),
+            ));
+        }
+
+        Ok((namespace, member))
+    }
+
+    fn rename(&self, name: &str) -> Result<String, ModuleError> {
+        let (namespace, name) = self.resolve(name)?;
+
+        // 定義されていない名前は、組み込み関数や局所変数のものとしてそのままにする
+        Ok(if namespace.names.contains(name) {
+            namespace.qualify(name)
+        } else {
+            name.to_string()
+        })
+    }
+
+    fn rename_top_level(&self, top_level: &mut ast::TopLevel) -> Result<(), ModuleError> {
+        match top_level {
+            ast::TopLevel::FunctionDefinition(function) => self.rename_function(function),
+            ast::TopLevel::EnumDefinition(definition) => {
+                for variant in &mut definition.variants {
+                    variant.name = self.rename(&variant.name)?;
+                }
+
+                Ok(())
+            }
+            ast::TopLevel::GlobalVariableDefinition { name, expression }
+            | ast::TopLevel::ConstantDefinition { name, expression } => {
+                *name = self.rename(name)?;
+                self.rename_expression(expression)
+            }
+            ast::TopLevel::MacroDefinition(definition) => {
+                definition.name = self.rename(&definition.name)?;
+                for param in &mut definition.params {
+                    *param = self.rename(param)?;
+                }
+                self.rename_expression(&mut definition.body)
+            }
+            ast::TopLevel::Import { .. } => Ok(()),
+            ast::TopLevel::Statement(statement) => self.rename_expression(statement),
+        }
+    }
+
+    fn rename_function(&self, function: &mut ast::Function) -> Result<(), ModuleError> {
+        function.name = self.rename(&function.name)?;
+        for arg in &mut function.args {
+            arg.name = self.rename(&arg.name)?;
+        }
+        for child in function.children_mut() {
+            self.rename_expression(child)?;
+        }
+
+        Ok(())
+    }
+
+    fn rename_expression(&self, expression: &mut ast::Expression) -> Result<(), ModuleError> {
+        match expression {
+            ast::Expression::FunctionDefinition { function } => {
+                return self.rename_function(Rc::make_mut(function))
+            }
+            ast::Expression::Identifier { name }
+            | ast::Expression::Assignment { name, .. }
+            | ast::Expression::Let { name, .. }
+            | ast::Expression::CompoundAssignment {
+                target: ast::AssignmentTarget::Variable { name },
+                ..
+            }
+            | ast::Expression::For { variable: name, .. }
+            | ast::Expression::ForEach { variable: name, .. } => *name = self.rename(name)?,
+            ast::Expression::Destructuring { pattern, .. } => self.rename_pattern(pattern)?,
+            ast::Expression::Match { arms, .. } => {
+                for arm in arms {
+                    self.rename_pattern(&mut arm.pattern)?;
+                }
+            }
+            ast::Expression::Try {
+                catch_clause: Some(catch_clause),
+                ..
+            } => catch_clause.variable = self.rename(&catch_clause.variable)?,
+            ast::Expression::FunctionCall {
+                name, named_args, ..
+            } => {
+                // 名前付き引数は呼び出される関数の仮引数と同じように名前を変える
+                let (callee, _) = self.resolve(name)?;
+                for (arg_name, _) in named_args {
+                    if callee.names.contains(arg_name.as_str()) {
+                        *arg_name = callee.qualify(arg_name);
+                    }
+                }
+                *name = self.rename(name)?;
+            }
+            _ => {}
+        }
+
+        for child in expression.children_mut() {
+            self.rename_expression(child)?;
+        }
+
+        Ok(())
+    }
+
+    fn rename_pattern(&self, pattern: &mut ast::Pattern) -> Result<(), ModuleError> {
+        match pattern {
+            ast::Pattern::Binding { name } => *name = self.rename(name)?,
+            ast::Pattern::Variant { name, fields } => {
+                *name = self.rename(name)?;
+                for field in fields {
+                    self.rename_pattern(field)?;
+                }
+            }
+            ast::Pattern::Tuple { elements } => {
+                for element in elements {
+                    self.rename_pattern(element)?;
+                }
+            }
+            ast::Pattern::Wildcard | ast::Pattern::Literal { .. } | ast::Pattern::Range { .. } => {}
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, InterpreterError};
+
+    fn run(main: &str, modules: &[(&str, &str)]) -> Result<String, InterpreterError> {
+        let loader: HashMap<_, _> = modules
+            .iter()
+            .map(|(path, source)| (path.to_string(), source.to_string()))
+            .collect();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_module_loader(loader);
+
+        let value = interpreter.call_main(parser::parse(main).unwrap())?;
+
+        Ok(value.to_string())
+    }
+
+    #[test]
+    fn qualified_call() {
+        let main = r#"
+            import "lib/math.toys" as math;
+            import "lib/shape.toys" as shape;
+
+            define scale() = 10;
+
+            define main() {
+                s = shape::Square(math::gcd(12, 18));
+                (shape::area(s), match (s) { shape::Square(side) => side * scale(), _ => 0 });
+            }
+        "#;
+        let math = r#"
+            define gcd(a, b) {
+                while (b != 0) {
+                    (a, b) = (b, a % b);
+                }
+                a;
+            }
+        "#;
+        let shape = r#"
+            import "math.toys" as math;
+
+            enum Shape {
+                Square(side),
+                Dot,
+            }
+
+            define scale() = 1;
+
+            define area(shape) = match (shape) {
+                Square(side) => side * side * scale() / math::gcd(side, 3),
+                Dot => 0,
+            };
+        "#;
+
+        let value = run(main, &[("lib/math.toys", math), ("lib/shape.toys", shape)]);
+
+        assert_eq!(value.unwrap(), "(12, 60)");
+    }
+
+    #[test]
+    fn import_cycle() {
+        let main = r#"
+            import "a.toys" as a;
+
+            define main() = a::f();
+        "#;
+        let a = r#"
+            import "dir/b.toys" as b;
+
+            define f() = b::g();
+        "#;
+        let b = r#"
+            import "../a.toys" as a;
+
+            define g() = 1;
+        "#;
+
+        match run(main, &[("a.toys", a), ("dir/b.toys", b)]).unwrap_err() {
+            InterpreterError::Module(error) => assert_eq!(
+                error.to_string(),
+                "Modules import each other in a cycle: a.toys -> dir/b.toys -> a.toys"
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn unknown_module() {
+        let main = r#"
+            import "a.toys" as a;
+
+            define main() = b::f();
+        "#;
+
+        match run(main, &[("a.toys", "define f() = 1;")]).unwrap_err() {
+            InterpreterError::Module(ModuleError::UnknownModule(_, alias)) => {
+                assert_eq!(alias, "b")
+            }
+            _ => unreachable!(),
+        }
+
+        match run(main, &[]).unwrap_err() {
+            InterpreterError::Module(ModuleError::Load(path, _)) => assert_eq!(path, "a.toys"),
+            _ => unreachable!(),
+        }
+    }
+}
@@ -1,17 +1,58 @@
 use crate::ast;
+use crate::value::IntegerType;
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    character::complete::{multispace0, multispace1},
-    combinator::opt,
+    character::complete::{anychar, digit1, multispace0, multispace1},
+    combinator::{map, map_opt, map_res, not, opt, peek, value, verify},
+    error::ErrorKind,
     multi::{fold_many0, many0, separated_list0},
-    sequence::{delimited, pair, preceded, terminated},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     IResult,
 };
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use thiserror::Error;
 
 mod helper_combinators;
 mod raw_res;
 
+#[derive(Error, Debug)]
+pub enum ParseError {
+    #[error("Cannot parse line {0}: {1}")]
+    UnexpectedInput(usize, String),
+    #[error("Integer literal {1}{2} on line {0} does not fit in {2}")]
+    IntegerLiteralOutOfRange(usize, BigInt, IntegerType),
+}
+
+/// Parses the whole of `input` as a program.
+pub fn parse(input: &str) -> Result<ast::Program, ParseError> {
+    // `#!` で始まる最初の行は読み飛ばす。行番号は変えない
+    let input = match input.strip_prefix("#!") {
+        Some(rest) => &rest[rest.find('\n').unwrap_or(rest.len())..],
+        None => input,
+    };
+    // 読めなかった箇所の行を示す
+    let line = |rest: &str| input[..input.len() - rest.len()].matches('\n').count() + 1;
+    let rest = match program(input) {
+        Ok(("", program)) => return Ok(program),
+        Ok((rest, _)) => rest,
+        Err(nom::Err::Failure(error)) if error.code == ErrorKind::TooLarge => {
+            let (_, (value, ty)) = pair(raw_res::integer, raw_res::integer_type)(error.input)
+                .expect("`integer` fails with TooLarge only at a typed literal");
+            return Err(ParseError::IntegerLiteralOutOfRange(
+                line(error.input),
+                value,
+                ty,
+            ));
+        }
+        Err(_) => input,
+    };
+    let text = rest.lines().next().unwrap_or_default().to_string();
+
+    Err(ParseError::UnexpectedInput(line(rest), text))
+}
+
 /// program <- top_level_definition*;
 pub fn program(input: &str) -> IResult<&str, ast::Program> {
     let (input, definitions) = many0(helper_combinators::ws(top_level_definition))(input)?;
@@ -21,29 +62,150 @@ pub fn program(input: &str) -> IResult<&str, ast::Program> {
 
 /// top_level_definition <-
 ///     function_definition \
-///     global_variable_definition;
+///     enum_definition \
+///     global_variable_definition \
+///     constant_definition \
+///     macro_definition \
+///     import_definition \
+///     top_level_statement;
 fn top_level_definition(input: &str) -> IResult<&str, ast::TopLevel> {
-    alt((function_definition, global_variable_definition))(input)
+    alt((
+        function_definition,
+        enum_definition,
+        global_variable_definition,
+        constant_definition,
+        macro_definition,
+        import_definition,
+        top_level_statement,
+    ))(input)
 }
 
-/// function_definition <-
-///     "define" identifier
-///     "(" (identifier ("," identifier)*)? ")"
-///     block_expression
+/// top_level_statement <- line;
+fn top_level_statement(input: &str) -> IResult<&str, ast::TopLevel> {
+    map(line, ast::top_level_statement)(input)
+}
+
+/// function_definition <- function;
 fn function_definition(input: &str) -> IResult<&str, ast::TopLevel> {
+    let (input, (name, args, contract, body)) = function(input)?;
+
+    Ok((
+        input,
+        ast::define_function_with_contract(name, &args, contract, body),
+    ))
+}
+
+/// function <-
+///     "define" identifier
+///     "(" (parameter ("," parameter)*)? ")"
+///     contract_clause*
+///     (block_expression / "=" expression ";");
+fn function(
+    input: &str,
+) -> IResult<&str, (&str, Vec<ast::Parameter>, ast::Contract, ast::Expression)> {
     let (input, _) = tag("define")(input)?;
     let (input, _) = multispace1(input)?;
 
     let (input, name) = raw_res::identifier(input)?;
 
-    let (input, args) = helper_combinators::ws(helper_combinators::parentheses(separated_list0(
-        delimited(multispace0, tag(","), multispace0),
-        raw_res::identifier,
+    // 可変長引数は最後の仮引数にしか置けない
+    let (input, args) = helper_combinators::ws(helper_combinators::parentheses(verify(
+        separated_list0(delimited(multispace0, tag(","), multispace0), parameter),
+        |args: &[ast::Parameter]| {
+            args.iter()
+                .rev()
+                .skip(1)
+                .all(|arg| !matches!(arg.kind, ast::ParameterKind::Variadic))
+        },
     )))(input)?;
 
-    let (input, body) = block_expression(input)?;
+    let (input, clauses) = many0(helper_combinators::ws(contract_clause))(input)?;
+    let mut requires = Vec::new();
+    let mut ensures = Vec::new();
+    for (keyword, condition) in clauses {
+        match keyword {
+            "requires" => requires.push(condition),
+            "ensures" => ensures.push(condition),
+            _ => unreachable!(),
+        }
+    }
+
+    let (input, body) = alt((
+        block_expression,
+        delimited(helper_combinators::ws(tag("=")), expression, tag(";")),
+    ))(input)?;
+
+    Ok((input, (name, args, ast::contract(requires, ensures), body)))
+}
+
+/// contract_clause <- ("requires" / "ensures") "(" expression ")";
+fn contract_clause(input: &str) -> IResult<&str, (&str, ast::Expression)> {
+    pair(
+        terminated(
+            alt((
+                helper_combinators::keyword("requires"),
+                helper_combinators::keyword("ensures"),
+            )),
+            multispace0,
+        ),
+        helper_combinators::parentheses(expression),
+    )(input)
+}
+
+/// parameter <- "..." identifier / identifier ("=" expression)?;
+fn parameter(input: &str) -> IResult<&str, ast::Parameter> {
+    alt((
+        map(
+            preceded(tag("..."), raw_res::identifier),
+            ast::variadic_parameter,
+        ),
+        map(
+            pair(
+                raw_res::identifier,
+                opt(preceded(helper_combinators::ws(tag("=")), expression)),
+            ),
+            |(name, default)| match default {
+                Some(default) => ast::default_parameter(name, default),
+                None => ast::parameter(name),
+            },
+        ),
+    ))(input)
+}
+
+/// enum_definition <-
+///     "enum" identifier
+///     "{" (variant ("," variant)* ","?)? "}";
+fn enum_definition(input: &str) -> IResult<&str, ast::TopLevel> {
+    let (input, _) = terminated(helper_combinators::keyword("enum"), multispace1)(input)?;
+    let (input, name) = raw_res::identifier(input)?;
+    let (input, variants) = preceded(
+        multispace0,
+        helper_combinators::curly_brackets(terminated(
+            separated_list0(helper_combinators::ws(tag(",")), variant),
+            opt(helper_combinators::ws(tag(","))),
+        )),
+    )(input)?;
+
+    let variants: Vec<(&str, &[&str])> = variants
+        .iter()
+        .map(|(name, fields)| (*name, &fields[..]))
+        .collect();
+
+    Ok((input, ast::define_enum(name, &variants)))
+}
 
-    Ok((input, ast::define_function(name, &args, body)))
+/// variant <- identifier ("(" (identifier ("," identifier)*)? ")")?;
+fn variant(input: &str) -> IResult<&str, (&str, Vec<&str>)> {
+    let (input, name) = raw_res::identifier(input)?;
+    let (input, fields) = opt(preceded(
+        multispace0,
+        helper_combinators::parentheses(separated_list0(
+            helper_combinators::ws(tag(",")),
+            raw_res::identifier,
+        )),
+    ))(input)?;
+
+    Ok((input, (name, fields.unwrap_or_default())))
 }
 
 /// global_variable_definition <-
@@ -58,35 +220,105 @@ fn global_variable_definition(input: &str) -> IResult<&str, ast::TopLevel> {
     Ok((input, ast::difine_global_variable(name, ast_expression)))
 }
 
+/// constant_definition <-
+///     "const" identifier "=" expression ";";
+fn constant_definition(input: &str) -> IResult<&str, ast::TopLevel> {
+    let (input, _) = terminated(helper_combinators::keyword("const"), multispace1)(input)?;
+    let (input, name) = raw_res::identifier(input)?;
+    let (input, _) = helper_combinators::ws(tag("="))(input)?;
+    let (input, ast_expression) = terminated(expression, tag(";"))(input)?;
+
+    Ok((input, ast::define_constant(name, ast_expression)))
+}
+
+/// macro_definition <-
+///     "macro" identifier "(" (identifier ("," identifier)*)? ")" block_expression;
+fn macro_definition(input: &str) -> IResult<&str, ast::TopLevel> {
+    let (input, _) = terminated(helper_combinators::keyword("macro"), multispace1)(input)?;
+    let (input, name) = raw_res::identifier(input)?;
+    let (input, params) = helper_combinators::ws(helper_combinators::parentheses(
+        separated_list0(helper_combinators::ws(tag(",")), raw_res::identifier),
+    ))(input)?;
+    let (input, body) = block_expression(input)?;
+
+    Ok((input, ast::define_macro(name, &params, body)))
+}
+
+/// import_definition <- "import" string "as" identifier ";";
+fn import_definition(input: &str) -> IResult<&str, ast::TopLevel> {
+    let (input, _) = terminated(helper_combinators::keyword("import"), multispace0)(input)?;
+    let (input, path) = raw_res::string(input)?;
+    let (input, _) = helper_combinators::ws(helper_combinators::keyword("as"))(input)?;
+    let (input, alias) = terminated(raw_res::identifier, helper_combinators::ws(tag(";")))(input)?;
+
+    Ok((input, ast::import(&path, alias)))
+}
+
 /// line <-
-///     println \
+///     print \
 ///     if_expression \
 ///     while_expression \
-///     block_expression
+///     for_expression \
+///     match_expression \
+///     return_expression \
+///     throw_expression \
+///     yield_expression \
+///     try_expression \
+///     break_expression \
+///     continue_expression \
+///     block_expression \
+///     nested_function_definition \
+///     compound_assignment \
+///     increment \
+///     let_binding \
 ///     assignment \
+///     destructuring_assignment \
 ///     expression_line;
 fn line(input: &str) -> IResult<&str, ast::Expression> {
     // *terminated multispace0* is important!
     terminated(
         alt((
-            println,
+            print,
             if_expression,
             while_expression,
+            for_expression,
+            match_expression,
+            return_expression,
+            throw_expression,
+            yield_expression,
+            try_expression,
+            break_expression,
+            continue_expression,
             block_expression,
+            nested_function_definition,
+            compound_assignment,
+            increment,
+            let_binding,
             assignment,
+            destructuring_assignment,
             expression_line,
         )),
         multispace0,
     )(input)
 }
 
-/// println <- "println" "(" expression ")" ";";
-fn println(input: &str) -> IResult<&str, ast::Expression> {
-    let (input, _) = terminated(tag("println"), multispace0)(input)?;
-    let (input, ast_expression) =
-        terminated(helper_combinators::parentheses(expression), tag(";"))(input)?;
+/// print <- ("println" / "print") "(" (expression ("," expression)*)? ")" ";";
+fn print(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, keyword) = terminated(alt((tag("println"), tag("print"))), multispace0)(input)?;
+    let (input, args) = terminated(
+        helper_combinators::parentheses(separated_list0(
+            helper_combinators::ws(tag(",")),
+            expression,
+        )),
+        tag(";"),
+    )(input)?;
+
+    let print = match keyword {
+        "println" => ast::ast_println,
+        _ => ast::ast_print,
+    };
 
-    Ok((input, ast::ast_println(ast_expression)))
+    Ok((input, print(args)))
 }
 
 /// if_expression <-
@@ -112,6 +344,185 @@ fn while_expression(input: &str) -> IResult<&str, ast::Expression> {
     Ok((input, ast::ast_while(condition, body)))
 }
 
+/// for_expression <-
+///     "for" "(" identifier "in" expression
+///     ((".." / "..=") expression ("step" expression)?)? ")" line;
+fn for_expression(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, _) = terminated(helper_combinators::keyword("for"), multispace0)(input)?;
+    let (input, (variable, start, range)) = helper_combinators::parentheses(tuple((
+        raw_res::identifier,
+        preceded(
+            helper_combinators::ws(helper_combinators::keyword("in")),
+            expression,
+        ),
+        opt(tuple((
+            helper_combinators::ws(alt((tag("..="), tag("..")))),
+            expression,
+            opt(preceded(
+                helper_combinators::ws(helper_combinators::keyword("step")),
+                expression,
+            )),
+        ))),
+    )))(input)?;
+    let (input, body) = preceded(multispace0, line)(input)?;
+
+    // 範囲がなければタプルやジェネレータの要素を順に取り出す
+    let ast_expression = match range {
+        Some((range, end, step)) => {
+            let inclusive = range == "..=";
+            ast::ast_for(variable, start, end, inclusive, step, body)
+        }
+        None => ast::ast_for_each(variable, start, body),
+    };
+
+    Ok((input, ast_expression))
+}
+
+/// match_expression <-
+///     "match" "(" expression ")" "{" match_arm* "}";
+fn match_expression(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, _) = terminated(helper_combinators::keyword("match"), multispace0)(input)?;
+    let (input, scrutinee) = helper_combinators::parentheses(expression)(input)?;
+    let (input, arms) = preceded(
+        multispace0,
+        helper_combinators::curly_brackets(many0(helper_combinators::ws(match_arm))),
+    )(input)?;
+
+    Ok((input, ast::ast_match(scrutinee, arms)))
+}
+
+/// match_arm <-
+///     pattern ("if" expression)? "=>"
+///     (block_expression ","? / expression ("," / &"}"));
+fn match_arm(input: &str) -> IResult<&str, ast::MatchArm> {
+    let (input, pattern) = pattern(input)?;
+    let (input, guard) = opt(preceded(
+        helper_combinators::ws(helper_combinators::keyword("if")),
+        expression,
+    ))(input)?;
+    let (input, _) = helper_combinators::ws(tag("=>"))(input)?;
+    let (input, body) = alt((
+        terminated(block_expression, opt(preceded(multispace0, tag(",")))),
+        terminated(
+            expression,
+            preceded(multispace0, alt((tag(","), peek(tag("}"))))),
+        ),
+    ))(input)?;
+
+    Ok((input, ast::match_arm(pattern, guard, body)))
+}
+
+/// pattern <-
+///     "_" \
+///     integer ("..=" / "..") integer \
+///     integer \
+///     qualified_identifier "(" (pattern ("," pattern)*)? ")" \
+///     qualified_identifier \
+///     "(" pattern "," (pattern ("," pattern)*)? ","? ")";
+fn pattern(input: &str) -> IResult<&str, ast::Pattern> {
+    alt((
+        map(helper_combinators::keyword("_"), |_| {
+            ast::wildcard_pattern()
+        }),
+        map(
+            tuple((
+                pattern_integer,
+                alt((tag("..="), tag(".."))),
+                pattern_integer,
+            )),
+            |(start, range, end)| ast::range_pattern(start, end, range == "..="),
+        ),
+        map(pattern_integer, ast::literal_pattern),
+        map(
+            pair(
+                terminated(raw_res::qualified_identifier, multispace0),
+                helper_combinators::parentheses(separated_list0(
+                    helper_combinators::ws(tag(",")),
+                    pattern,
+                )),
+            ),
+            |(name, fields)| ast::variant_pattern(name, fields),
+        ),
+        map(raw_res::qualified_identifier, ast::binding_pattern),
+        map(helper_combinators::tuple_of(pattern), ast::tuple_pattern),
+    ))(input)
+}
+
+/// pattern_integer <- integer, within the range of i64;
+fn pattern_integer(input: &str) -> IResult<&str, i64> {
+    map_opt(raw_res::integer, |value| value.to_i64())(input)
+}
+
+/// return_expression <- "return" expression ";";
+fn return_expression(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, _) = terminated(helper_combinators::keyword("return"), multispace0)(input)?;
+    let (input, ast_expression) = terminated(expression, tag(";"))(input)?;
+
+    Ok((input, ast::ast_return(ast_expression)))
+}
+
+/// throw_expression <- "throw" expression ";";
+fn throw_expression(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, _) = terminated(helper_combinators::keyword("throw"), multispace0)(input)?;
+    let (input, ast_expression) = terminated(expression, tag(";"))(input)?;
+
+    Ok((input, ast::ast_throw(ast_expression)))
+}
+
+/// yield_expression <- "yield" expression ";";
+fn yield_expression(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, _) = terminated(helper_combinators::keyword("yield"), multispace0)(input)?;
+    let (input, ast_expression) = terminated(expression, tag(";"))(input)?;
+
+    Ok((input, ast::ast_yield(ast_expression)))
+}
+
+/// try_expression <-
+///     "try" block_expression
+///     ("catch" "(" identifier ")" block_expression)?
+///     ("finally" block_expression)?;
+fn try_expression(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, _) = terminated(helper_combinators::keyword("try"), multispace0)(input)?;
+    let (input, body) = block_expression(input)?;
+    let (input, catch_clause) = opt(preceded(
+        helper_combinators::ws(helper_combinators::keyword("catch")),
+        pair(
+            helper_combinators::parentheses(helper_combinators::ws(raw_res::identifier)),
+            preceded(multispace0, block_expression),
+        ),
+    ))(input)?;
+    let (input, finally_clause) = opt(preceded(
+        helper_combinators::ws(helper_combinators::keyword("finally")),
+        block_expression,
+    ))(input)?;
+
+    // `try` だけでは意味がないので、少なくともどちらかの節を要求する
+    if catch_clause.is_none() && finally_clause.is_none() {
+        return Err(nom::Err::Error(nom::error::Error::new(
+            input,
+            nom::error::ErrorKind::Verify,
+        )));
+    }
+
+    Ok((input, ast::ast_try(body, catch_clause, finally_clause)))
+}
+
+/// break_expression <- "break" ";";
+fn break_expression(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, _) = terminated(helper_combinators::keyword("break"), multispace0)(input)?;
+    let (input, _) = tag(";")(input)?;
+
+    Ok((input, ast::ast_break()))
+}
+
+/// continue_expression <- "continue" ";";
+fn continue_expression(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, _) = terminated(helper_combinators::keyword("continue"), multispace0)(input)?;
+    let (input, _) = tag(";")(input)?;
+
+    Ok((input, ast::ast_continue()))
+}
+
 /// block_expression <- "{" line* "}";
 fn block_expression(input: &str) -> IResult<&str, ast::Expression> {
     let (input, elements) = helper_combinators::curly_brackets(many0(line))(input)?;
@@ -128,6 +539,102 @@ fn assignment(input: &str) -> IResult<&str, ast::Expression> {
     Ok((input, ast::assignment(name, ast_expression)))
 }
 
+/// nested_function_definition <- function;
+fn nested_function_definition(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, (name, args, contract, body)) = function(input)?;
+
+    Ok((
+        input,
+        ast::nested_function_with_contract(name, &args, contract, body),
+    ))
+}
+
+/// let_binding <- "let" identifier "=" expression ";";
+fn let_binding(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, _) = terminated(helper_combinators::keyword("let"), multispace1)(input)?;
+    let (input, name) = raw_res::identifier(input)?;
+    let (input, _) = helper_combinators::ws(tag("="))(input)?;
+    let (input, ast_expression) = terminated(expression, tag(";"))(input)?;
+
+    Ok((input, ast::ast_let(name, ast_expression)))
+}
+
+/// destructuring_assignment <- destructuring_pattern "=" expression ";";
+fn destructuring_assignment(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, pattern) = destructuring_pattern(input)?;
+    let (input, _) = helper_combinators::ws(tag("="))(input)?;
+    let (input, ast_expression) = terminated(expression, tag(";"))(input)?;
+
+    Ok((input, ast::destructuring(pattern, ast_expression)))
+}
+
+/// destructuring_pattern <-
+///     "(" destructuring_element "," (destructuring_element ("," destructuring_element)*)? ","? ")";
+fn destructuring_pattern(input: &str) -> IResult<&str, ast::Pattern> {
+    map(
+        helper_combinators::tuple_of(destructuring_element),
+        ast::tuple_pattern,
+    )(input)
+}
+
+/// destructuring_element <- "_" / identifier / destructuring_pattern;
+fn destructuring_element(input: &str) -> IResult<&str, ast::Pattern> {
+    alt((
+        map(helper_combinators::keyword("_"), |_| {
+            ast::wildcard_pattern()
+        }),
+        map(raw_res::identifier, ast::binding_pattern),
+        destructuring_pattern,
+    ))(input)
+}
+
+/// compound_assignment <-
+///     assignment_target ("+=" / "-=" / "*=" / "/=" / "%=") expression ";";
+fn compound_assignment(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, target) = assignment_target(input)?;
+    let (input, operator) =
+        helper_combinators::ws(alt((tag("+="), tag("-="), tag("*="), tag("/="), tag("%="))))(
+            input,
+        )?;
+    let (input, ast_expression) = terminated(expression, tag(";"))(input)?;
+
+    let operator = match operator {
+        "+=" => ast::Operator::Add,
+        "-=" => ast::Operator::Subtract,
+        "*=" => ast::Operator::Multiply,
+        "/=" => ast::Operator::Divide,
+        "%=" => ast::Operator::Remainder,
+        _ => unreachable!(),
+    };
+
+    Ok((
+        input,
+        ast::compound_assignment(operator, target, ast_expression),
+    ))
+}
+
+/// increment <- assignment_target ("++" / "--") ";";
+fn increment(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, target) = assignment_target(input)?;
+    let (input, operator) = helper_combinators::ws(alt((tag("++"), tag("--"))))(input)?;
+    let (input, _) = tag(";")(input)?;
+
+    let ast_expression = match operator {
+        "++" => ast::increment(target),
+        "--" => ast::decrement(target),
+        _ => unreachable!(),
+    };
+
+    Ok((input, ast_expression))
+}
+
+/// assignment_target <- identifier;
+fn assignment_target(input: &str) -> IResult<&str, ast::AssignmentTarget> {
+    let (input, name) = raw_res::identifier(input)?;
+
+    Ok((input, ast::variable_target(name)))
+}
+
 /// expression_line <- expression ";";
 fn expression_line(input: &str) -> IResult<&str, ast::Expression> {
     terminated(expression, tag(";"))(input)
@@ -190,18 +697,22 @@ fn additive(input: &str) -> IResult<&str, ast::Expression> {
     result
 }
 
-/// multitive <- primary (
-///     ("*" / "/") primary
+/// multitive <- postfix (
+///     ("*" / "/" / "%") postfix
 /// )*;
 fn multitive(input: &str) -> IResult<&str, ast::Expression> {
-    let (input, left_operand) = primary(input)?;
+    let (input, left_operand) = postfix(input)?;
 
     let result = fold_many0(
-        pair(helper_combinators::ws(alt((tag("*"), tag("/")))), primary),
+        pair(
+            helper_combinators::ws(alt((tag("*"), tag("/"), tag("%")))),
+            postfix,
+        ),
         || left_operand.clone(),
         |acc, (operator, right_operand)| match operator {
             "*" => ast::multiply(acc, right_operand),
             "/" => ast::divide(acc, right_operand),
+            "%" => ast::remainder(acc, right_operand),
             _ => unreachable!(),
         },
     )(input);
@@ -209,45 +720,189 @@ fn multitive(input: &str) -> IResult<&str, ast::Expression> {
     result
 }
 
-/// primary <- "(" expression ")"
+/// postfix <- primary ("." [0-9]+)*;
+fn postfix(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, tuple) = primary(input)?;
+
+    fold_many0(
+        preceded(
+            pair(multispace0, tag(".")),
+            map_res(digit1, |index: &str| index.parse::<usize>()),
+        ),
+        move || tuple.clone(),
+        ast::tuple_index,
+    )(input)
+}
+
+/// primary <- tuple_literal
+///     / "(" expression ")"
 ///     / integer
+///     / string_literal
+///     / match_expression
 ///     / function_call
 ///     / identifier
 fn primary(input: &str) -> IResult<&str, ast::Expression> {
     alt((
+        tuple_literal,
         helper_combinators::parentheses(expression),
         integer,
+        string_literal,
+        match_expression,
         function_call,
         identifier,
     ))(input)
 }
 
-/// integer <- i64
+/// tuple_literal <- "(" expression "," (expression ("," expression)*)? ","? ")";
+fn tuple_literal(input: &str) -> IResult<&str, ast::Expression> {
+    map(helper_combinators::tuple_of(expression), ast::tuple)(input)
+}
+
+/// integer <- ("+" / "-")? [0-9]+ integer_type?;
 fn integer(input: &str) -> IResult<&str, ast::Expression> {
-    let (input, value) = raw_res::integer(input)?;
+    let (rest, value) = raw_res::integer(input)?;
+    let (rest, ty) = opt(raw_res::integer_type)(rest)?;
+
+    let literal = match (value.to_i64(), ty) {
+        (Some(value), Some(ty)) if ty.contains(value) => ast::sized_integer(value, ty),
+        // 型を外れた値は他の読み方もできないので、その場で失敗させる
+        (_, Some(_)) => {
+            return Err(nom::Err::Failure(nom::error::Error::new(
+                input,
+                ErrorKind::TooLarge,
+            )))
+        }
+        (Some(value), None) => ast::integer(value),
+        // i64 に収まらない値は、多倍長整数のリテラルにする
+        (None, None) => ast::big_integer(value),
+    };
+
+    Ok((rest, literal))
+}
 
-    Ok((input, ast::integer(value)))
+/// string_literal <- "\"" (interpolation / string_fragment)* "\"";
+fn string_literal(input: &str) -> IResult<&str, ast::Expression> {
+    let (input, parts) = delimited(
+        tag("\""),
+        many0(alt((
+            interpolation,
+            map(raw_res::string_fragment, ast::FormatPart::Literal),
+        ))),
+        tag("\""),
+    )(input)?;
+
+    // 補間がなければ、ただの文字列にする
+    if parts
+        .iter()
+        .all(|part| matches!(part, ast::FormatPart::Literal(_)))
+    {
+        let mut value = String::new();
+        for part in parts {
+            if let ast::FormatPart::Literal(fragment) = part {
+                value.push_str(&fragment);
+            }
+        }
+        return Ok((input, ast::string(&value)));
+    }
+
+    Ok((input, ast::format(parts)))
+}
+
+/// interpolation <- "${" expression (":" format_spec)? "}";
+fn interpolation(input: &str) -> IResult<&str, ast::FormatPart> {
+    let (input, expression) = preceded(tag("${"), helper_combinators::ws(expression))(input)?;
+    let (input, spec) = terminated(opt(preceded(tag(":"), format_spec)), tag("}"))(input)?;
+
+    Ok((
+        input,
+        ast::interpolation(expression, spec.unwrap_or_default()),
+    ))
+}
+
+/// format_spec <- (. align / align)? "0"? [0-9]* ("b" / "o" / "x" / "X")?;
+/// align <- "<" / "^" / ">";
+fn format_spec(input: &str) -> IResult<&str, ast::FormatSpec> {
+    fn align(input: &str) -> IResult<&str, ast::Alignment> {
+        alt((
+            value(ast::Alignment::Left, tag("<")),
+            value(ast::Alignment::Center, tag("^")),
+            value(ast::Alignment::Right, tag(">")),
+        ))(input)
+    }
+
+    let (input, fill_and_align) = opt(alt((
+        pair(map(anychar, Some), align),
+        map(align, |align| (None, align)),
+    )))(input)?;
+    let (input, zero) = opt(tag("0"))(input)?;
+    let (input, width) = opt(map_res(digit1, str::parse))(input)?;
+    let (input, radix) = opt(alt((
+        value(ast::Radix::Binary, tag("b")),
+        value(ast::Radix::Octal, tag("o")),
+        value(ast::Radix::LowerHex, tag("x")),
+        value(ast::Radix::UpperHex, tag("X")),
+    )))(input)?;
+
+    Ok((
+        input,
+        ast::FormatSpec {
+            fill: fill_and_align.and_then(|(fill, _)| fill),
+            align: fill_and_align.map(|(_, align)| align),
+            zero: zero.is_some(),
+            width: width.unwrap_or(0),
+            radix,
+        },
+    ))
 }
 
-/// function_call <- identifier "("
-///     (expression ("," expression)*)?
+/// function_call <- qualified_identifier "("
+///     (argument ("," argument)*)?
 /// ")"
 fn function_call(input: &str) -> IResult<&str, ast::Expression> {
-    let (input, name) = terminated(raw_res::identifier, multispace0)(input)?;
-
-    let (input, args) = helper_combinators::parentheses(separated_list0(
-        helper_combinators::ws(tag(",")),
-        expression,
+    let (input, name) = terminated(raw_res::qualified_identifier, multispace0)(input)?;
+
+    // 名前付き引数の後ろに位置引数は置けない
+    let (input, arguments) = helper_combinators::parentheses(verify(
+        separated_list0(helper_combinators::ws(tag(",")), argument),
+        |arguments: &[(Option<&str>, ast::Expression)]| {
+            arguments
+                .windows(2)
+                .all(|pair| pair[0].0.is_none() || pair[1].0.is_some())
+        },
     ))(input)?;
 
-    Ok((input, ast::call(name, args)))
+    let mut args = Vec::new();
+    let mut named_args = Vec::new();
+    for (name, argument) in arguments {
+        match name {
+            Some(name) => named_args.push((name, argument)),
+            None => args.push(argument),
+        }
+    }
+
+    Ok((input, ast::call_with_named(name, args, named_args)))
 }
 
-/// identifier <- (alpha / "_")+ (
-///     alphanumeric / "_"
-/// )*;
+/// argument <- identifier "=" !"=" expression / expression;
+fn argument(input: &str) -> IResult<&str, (Option<&str>, ast::Expression)> {
+    alt((
+        map(
+            pair(
+                terminated(
+                    raw_res::identifier,
+                    helper_combinators::ws(terminated(tag("="), not(tag("=")))),
+                ),
+                expression,
+            ),
+            |(name, argument)| (Some(name), argument),
+        ),
+        map(expression, |argument| (None, argument)),
+    ))(input)
+}
+
+/// identifier <- qualified_identifier;
 fn identifier(input: &str) -> IResult<&str, ast::Expression> {
-    let (input, name) = raw_res::identifier(input)?;
+    let (input, name) = raw_res::qualified_identifier(input)?;
 
     Ok((input, ast::identifier(name)))
 }
@@ -255,7 +910,10 @@ fn identifier(input: &str) -> IResult<&str, ast::Expression> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::interpreter::Interpreter;
+    use crate::checker::CheckError;
+    use crate::interpreter::{Interpreter, InterpreterError};
+    use crate::macros::{self, MacroError};
+    use crate::value::Value;
 
     #[test]
     fn interger_test() {
@@ -265,7 +923,7 @@ mod tests {
         let (_, expression) = integer(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
     }
 
     #[test]
@@ -276,7 +934,7 @@ mod tests {
         let (_, expression) = multitive(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
     }
 
     #[test]
@@ -287,7 +945,7 @@ mod tests {
         let (_, expression) = multitive(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
     }
 
     #[test]
@@ -298,7 +956,18 @@ mod tests {
         let (_, expression) = multitive(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 21);
+        assert_eq!(value, Value::Integer(21));
+    }
+
+    #[test]
+    fn multitive_remainder() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "47 % 5";
+        let (_, expression) = multitive(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+
+        assert_eq!(value, Value::Integer(2));
     }
 
     #[test]
@@ -309,7 +978,7 @@ mod tests {
         let (_, expression) = multitive(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 54);
+        assert_eq!(value, Value::Integer(54));
     }
 
     #[test]
@@ -320,7 +989,7 @@ mod tests {
         let (_, expression) = additive(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
     }
 
     #[test]
@@ -331,7 +1000,7 @@ mod tests {
         let (_, expression) = additive(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 4);
+        assert_eq!(value, Value::Integer(4));
     }
 
     #[test]
@@ -342,7 +1011,7 @@ mod tests {
         let (_, expression) = additive(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 40);
+        assert_eq!(value, Value::Integer(40));
     }
 
     #[test]
@@ -353,7 +1022,7 @@ mod tests {
         let (_, expression) = additive(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 4);
+        assert_eq!(value, Value::Integer(4));
     }
 
     #[test]
@@ -364,7 +1033,7 @@ mod tests {
         let (_, expression) = comparative(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
     }
 
     #[test]
@@ -375,7 +1044,7 @@ mod tests {
         let (_, expression) = comparative(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 1);
+        assert_eq!(value, Value::Integer(1));
     }
 
     #[test]
@@ -386,7 +1055,7 @@ mod tests {
         let (_, expression) = comparative(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 0);
+        assert_eq!(value, Value::Integer(0));
     }
 
     #[test]
@@ -397,7 +1066,7 @@ mod tests {
         let (_, expression) = comparative(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 1);
+        assert_eq!(value, Value::Integer(1));
     }
 
     #[test]
@@ -408,7 +1077,7 @@ mod tests {
         let (_, expression) = comparative(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 1);
+        assert_eq!(value, Value::Integer(1));
     }
 
     #[test]
@@ -419,7 +1088,7 @@ mod tests {
         let (_, expression) = comparative(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 1);
+        assert_eq!(value, Value::Integer(1));
     }
 
     #[test]
@@ -430,7 +1099,7 @@ mod tests {
         let (_, expression) = comparative(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 0);
+        assert_eq!(value, Value::Integer(0));
     }
 
     #[test]
@@ -441,7 +1110,7 @@ mod tests {
         let (_, expression) = comparative(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 1);
+        assert_eq!(value, Value::Integer(1));
     }
 
     #[test]
@@ -452,7 +1121,7 @@ mod tests {
         let (_, expression) = expression_line(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 4);
+        assert_eq!(value, Value::Integer(4));
     }
 
     #[test]
@@ -463,14 +1132,70 @@ mod tests {
         let (_, expression) = assignment(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
 
         let input = "answer";
 
         let (_, expression) = identifier(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
+    }
+
+    #[test]
+    fn compound_assignment_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "{
+            answer = 10;
+            answer += 5;
+            answer -= 1;
+            answer *= 6;
+            answer /= 4;
+            answer %= 8;
+            answer;
+        }";
+
+        let (_, expression) = block_expression(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+
+        assert_eq!(value, Value::Integer(5));
+    }
+
+    #[test]
+    fn compound_assignment_zero_division() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "{
+            answer = 10;
+            answer %= 0;
+        }";
+
+        let (_, expression) = block_expression(input).unwrap();
+
+        match interpreter.interpret(&expression).unwrap_err() {
+            InterpreterError::ZeroDivision => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn increment_and_decrement_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "{
+            answer = 40;
+            answer++;
+            answer ++;
+            answer++;
+            answer--;
+            answer;
+        }";
+
+        let (_, expression) = block_expression(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+
+        assert_eq!(value, Value::Integer(42));
     }
 
     #[test]
@@ -488,7 +1213,7 @@ mod tests {
         let (_, expression) = block_expression(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
     }
 
     #[test]
@@ -515,7 +1240,7 @@ mod tests {
         let (_, expression) = expression_line(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 15);
+        assert_eq!(value, Value::Integer(15));
     }
 
     #[test]
@@ -529,7 +1254,7 @@ mod tests {
         let (_, expression) = if_expression(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
     }
 
     #[test]
@@ -545,18 +1270,71 @@ mod tests {
         let (_, expression) = if_expression(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 21);
+        assert_eq!(value, Value::Integer(21));
     }
 
     #[test]
     fn println_test() {
         let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
 
         let input = "println(42);";
-        let (_, expression) = println(input).unwrap();
+        let (_, expression) = print(input).unwrap();
         let value = interpreter.interpret(&expression).unwrap();
 
-        assert_eq!(value, 1);
+        assert_eq!(value, Value::Integer(1));
+        assert_eq!(interpreter.take_output(), "42\n");
+    }
+
+    #[test]
+    fn print_test() {
+        let input = r#"
+            define main() {
+                print("a");
+                print("b", 1, (2, 3));
+                println();
+                println("c", "d");
+            }
+        "#;
+
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        interpreter.call_main(parse(input).unwrap()).unwrap();
+
+        assert_eq!(interpreter.take_output(), "ab 1 (2, 3)\nc d\n");
+        assert_eq!(interpreter.take_output(), "");
+    }
+
+    #[test]
+    fn format_test() {
+        let input = r#"
+            define main() {
+                x = 42;
+                name = "toys";
+                println("x = ${x}, x + 1 = ${ x + 1 }, name = ${name}");
+                println("[${x:5}] [${x:<5}] [${x:^6}] [${0 - x:05}] [${name:*>6}] [${name:-^8}]");
+                println("${255:x} ${255:X} ${-5:b} ${8:o} ${255:#>6x} ${"nested ${x}"}");
+                println("\${x} costs $5 and ${(x, "a")}");
+            }
+        "#;
+
+        let mut interpreter = Interpreter::new();
+        interpreter.capture_output();
+        interpreter.call_main(parse(input).unwrap()).unwrap();
+
+        assert_eq!(
+            interpreter.take_output(),
+            "x = 42, x + 1 = 43, name = toys\n\
+             [   42] [42   ] [  42  ] [-0042] [**toys] [--toys--]\n\
+             ff FF -101 10 ####ff nested 42\n\
+             ${x} costs $5 and (42, \"a\")\n"
+        );
+
+        let (_, program) = program(r#"define main() = "${"a":x}";"#).unwrap();
+        match Interpreter::new().call_main(program).unwrap_err() {
+            InterpreterError::NotAnInteger(_) => {}
+            _ => unreachable!(),
+        }
     }
 
     #[test]
@@ -574,7 +1352,7 @@ mod tests {
         let (_, ast_program) = program(input).unwrap();
         let value = interpreter.call_main(ast_program).unwrap();
 
-        assert_eq!(value, 42);
+        assert_eq!(value, Value::Integer(42));
     }
 
     #[test]
@@ -598,6 +1376,1796 @@ mod tests {
         let (_, ast_program) = program(input).unwrap();
         let value = interpreter.call_main(ast_program).unwrap();
 
-        assert_eq!(value, 120);
+        assert_eq!(value, Value::Integer(120));
+    }
+
+    #[test]
+    fn return_expression_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define find_first_multiple(n, limit) {
+                i = 1;
+                while (i <= limit) {
+                    if (i / n * n == i) {
+                        return i;
+                    } else {
+                        0;
+                    }
+                    i = i + 1;
+                }
+                0;
+            }
+
+            define main() {
+                find_first_multiple(7, 100) + find_first_multiple(200, 100);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value, Value::Integer(7));
+    }
+
+    #[test]
+    fn break_and_continue_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define main() {
+                answer = 0;
+                i = 0;
+                while (1) {
+                    i = i + 1;
+                    if (i > 10) {
+                        break;
+                    } else {
+                        0;
+                    }
+                    if (i / 2 * 2 == i) {
+                        continue;
+                    } else {
+                        0;
+                    }
+                    answer = answer + i;
+                }
+                answer;
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value, Value::Integer(25));
+    }
+
+    #[test]
+    fn break_outside_loop_is_rejected() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define main() {
+                break;
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::Check(CheckError::BreakOutsideLoop) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn for_expression_exclusive_range() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "answer = 0;";
+        let (_, expression) = assignment(input).unwrap();
+        let _ = interpreter.interpret(&expression).unwrap();
+
+        let input = "for (i in 1..5) {
+            answer = answer + i;
+        }";
+
+        let (_, expression) = for_expression(input).unwrap();
+        let _ = interpreter.interpret(&expression).unwrap();
+
+        let input = "answer;";
+        let (_, expression) = expression_line(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+
+        assert_eq!(value, Value::Integer(10));
+    }
+
+    #[test]
+    fn for_expression_inclusive_range_with_step() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "answer = 0;";
+        let (_, expression) = assignment(input).unwrap();
+        let _ = interpreter.interpret(&expression).unwrap();
+
+        let input = "for (i in 0..=10 step 5) answer = answer * 10 + i;";
+
+        let (_, expression) = for_expression(input).unwrap();
+        let _ = interpreter.interpret(&expression).unwrap();
+
+        let input = "answer;";
+        let (_, expression) = expression_line(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+
+        assert_eq!(value, Value::Integer(60));
+    }
+
+    #[test]
+    fn for_expression_negative_step() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "answer = 0;";
+        let (_, expression) = assignment(input).unwrap();
+        let _ = interpreter.interpret(&expression).unwrap();
+
+        let input = "for (i in 3..0 step -1) {
+            answer = answer * 10 + i;
+        }";
+
+        let (_, expression) = for_expression(input).unwrap();
+        let _ = interpreter.interpret(&expression).unwrap();
+
+        let input = "answer;";
+        let (_, expression) = expression_line(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+
+        assert_eq!(value, Value::Integer(321));
+    }
+
+    #[test]
+    fn for_expression_variable_is_scoped_to_loop() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "for (i in 0..3) {
+            i;
+        }";
+
+        let (_, expression) = for_expression(input).unwrap();
+        let _ = interpreter.interpret(&expression).unwrap();
+
+        let input = "i";
+        let (_, expression) = identifier(input).unwrap();
+
+        match interpreter.interpret(&expression).unwrap_err() {
+            InterpreterError::VariableNotPresent(name) => assert_eq!(name, "i"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn match_expression_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define classify(n) {
+                match (n) {
+                    0 => 100,
+                    1..10 => {
+                        200 + n;
+                    }
+                    n if n < 0 => 0 - n,
+                    _ => 300,
+                }
+            }
+
+            define main() {
+                classify(0) + classify(5) + classify(-7) + classify(10);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value, Value::Integer(100 + 205 + 7 + 300));
+    }
+
+    #[test]
+    fn match_expression_as_operand() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "answer = match (3) { 1..=3 => 40, _ => 0 } + 2;";
+        let (_, expression) = assignment(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+
+        assert_eq!(value, Value::Integer(42));
+    }
+
+    #[test]
+    fn non_exhaustive_match_is_rejected() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define main() {
+                match (1) {
+                    0 => 1,
+                    1..10 => 2,
+                }
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::Check(CheckError::NonExhaustiveMatch(witness)) => {
+                assert_eq!(witness, i64::MIN.to_string())
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn enum_definition_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            enum Shape {
+                Circle(r),
+                Rect(w, h),
+                Empty,
+            }
+
+            define area(shape) {
+                match (shape) {
+                    Circle(r) => 3 * r * r,
+                    Rect(w, h) => w * h,
+                    Empty => 0,
+                }
+            }
+
+            define main() {
+                area(Circle(2)) + area(Rect(3, 4)) + area(Empty);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value, Value::Integer(24));
+    }
+
+    #[test]
+    fn enum_value_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            enum Tree {
+                Leaf,
+                Node(left, value, right),
+            }
+
+            define main() {
+                Node(Leaf, 3, Node(Leaf, 4, Leaf));
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value.to_string(), "Node(Leaf, 3, Node(Leaf, 4, Leaf))");
+    }
+
+    #[test]
+    fn non_exhaustive_enum_match_is_rejected() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            enum Shape { Circle(r), Rect(w, h) }
+
+            define main() {
+                match (Circle(1)) {
+                    Circle(_) => 1,
+                    Rect(0, h) => h,
+                }
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::Check(CheckError::NonExhaustiveMatch(witness)) => {
+                assert_eq!(witness, "Rect(-9223372036854775808, _)")
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn tuple_literal_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "(1, (2 + 3) * 4, (5,))";
+        let (_, expression) = expression(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+
+        assert_eq!(value.to_string(), "(1, 20, (5,))");
+    }
+
+    #[test]
+    fn tuple_index_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "((1, 2), (3, 4)).1.0 * 10 + (5, 6).1";
+        let (_, expression) = expression(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+
+        assert_eq!(value, Value::Integer(36));
+    }
+
+    #[test]
+    fn destructuring_assignment_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define divmod(n, d) {
+                (n / d, n % d);
+            }
+
+            define main() {
+                (q, r) = divmod(47, 5);
+                ((a, _), b) = ((1, 2), 3);
+                q * 1000 + r * 100 + a * 10 + b;
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value, Value::Integer(9213));
+    }
+
+    #[test]
+    fn destructuring_arity_mismatch() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "(q, r) = (1, 2, 3);";
+        let (_, expression) = destructuring_assignment(input).unwrap();
+
+        match interpreter.interpret(&expression).unwrap_err() {
+            InterpreterError::DestructuringMismatch(value, expected) => {
+                assert_eq!((value.to_string().as_str(), expected), ("(1, 2, 3)", 2))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn tuple_pattern_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define compare(a, b) {
+                match ((a < b, a == b)) {
+                    (1, _) => 0 - 1,
+                    (_, 1) => 0,
+                    (_, _) => 1,
+                }
+            }
+
+            define main() {
+                compare(1, 2) * 100 + compare(2, 2) * 10 + compare(3, 2);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value, Value::Integer(-99));
+    }
+
+    #[test]
+    fn constant_and_let_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            const WIDTH = 4 * 2;
+            const AREA = WIDTH * WIDTH;
+
+            define main() {
+                let half = AREA / 2;
+                for (i in 0..2) {
+                    let half = i;
+                }
+                half + WIDTH;
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value, Value::Integer(40));
+    }
+
+    #[test]
+    fn assignment_to_constant_is_rejected() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            const LIMIT = 10;
+
+            define main() {
+                LIMIT += 1;
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::Check(CheckError::AssignmentToImmutable(name, declaration)) => {
+                assert_eq!(
+                    (name.as_str(), declaration.as_str()),
+                    ("LIMIT", "`const LIMIT` at top level")
+                )
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn call_frame_test() {
+        let input = "
+            global calls = 0;
+
+            define count(n) {
+                calls += 1;
+                x = n;
+                if (n > 0) count(n - 1); else 0;
+                x;
+            }
+
+            define main() {
+                x = 100;
+                (count(3), x, calls);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = Interpreter::new().call_main(ast_program).unwrap();
+
+        // 呼び出し先の変数は呼び出し元と別だが、大域変数への代入は残る
+        assert_eq!(value.to_string(), "(3, 100, 4)");
+
+        let input = "
+            define main() {
+                x = 1;
+                peek();
+            }
+
+            define peek() = x;
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        match Interpreter::new().call_main(ast_program).unwrap_err() {
+            InterpreterError::VariableNotPresent(name) => assert_eq!(name, "x"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn nested_function_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define main() {
+                base = 100;
+
+                define is_even(n) {
+                    if (n == 0) 1; else is_odd(n - 1);
+                }
+                define is_odd(n) {
+                    if (n == 0) 0; else is_even(n - 1);
+                }
+                define sum(n) {
+                    if (n == 0) base; else n + sum(n - 1);
+                }
+                define outer(n) {
+                    define inner() = base + n;
+                    inner();
+                }
+
+                is_even(10) * 1000 + is_odd(10) * 10000 + sum(4) + outer(1) * 100000;
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value, Value::Integer(10101110));
+    }
+
+    #[test]
+    fn nested_function_is_scoped_to_block() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define helper_user() {
+                helper();
+            }
+
+            define main() {
+                define helper() {
+                    1;
+                }
+                helper_user();
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::FunctionNotFound(name) => assert_eq!(name, "helper"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn default_and_named_arguments_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define linear(x, a = 2, b = a * 10) = a * x + b;
+
+            define main() {
+                linear(1) * 10000 + linear(1, 3) * 100 + linear(b = 4, x = 5);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value, Value::Integer(223314));
+    }
+
+    #[test]
+    fn variadic_parameter_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define pack(first, ...rest) = (first, rest);
+
+            define main() {
+                (pack(1), pack(1, 2, 3));
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value.to_string(), "((1, ()), (1, (2, 3)))");
+    }
+
+    #[test]
+    fn variadic_parameter_must_be_last() {
+        let input = "define f(...rest, x) = x;";
+
+        let (rest, ast_program) = program(input).unwrap();
+
+        assert_eq!((rest, ast_program.definitions.len()), (input, 0));
+    }
+
+    #[test]
+    fn arguments_are_evaluated_by_caller() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define f(a, b) = (a, b);
+
+            define main() {
+                a = 10;
+                f(1, a);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value.to_string(), "(1, 10)");
+    }
+
+    #[test]
+    fn argument_count_errors() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "define main() = pair(1, 2, 3); define pair(a, b = 0) = (a, b);";
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::Check(CheckError::TooManyArguments(function, expected, given)) => {
+                assert_eq!((function.as_str(), expected, given), ("pair", 2, 3))
+            }
+            _ => unreachable!(),
+        }
+
+        // 検査を経ずに評価される式でも、呼び出しのたびに確かめられる
+        let input = "{ define pair(a, b = 0) = (a, b); pair(); }";
+        let (_, expression) = block_expression(input).unwrap();
+
+        match interpreter.interpret(&expression).unwrap_err() {
+            InterpreterError::NotEnoughArguments(function, expected, given) => {
+                assert_eq!((function.as_str(), expected, given), ("pair", 1, 0))
+            }
+            _ => unreachable!(),
+        }
+
+        let input = "{ define pair(a, b = 0) = (a, b); pair(1, 2, 3); }";
+        let (_, expression) = block_expression(input).unwrap();
+
+        match interpreter.interpret(&expression).unwrap_err() {
+            InterpreterError::TooManyArguments(function, expected, given) => {
+                assert_eq!((function.as_str(), expected, given), ("pair", 2, 3))
+            }
+            _ => unreachable!(),
+        }
+
+        // 省略できる仮引数の後ろの必須の仮引数は、数が足りていても名前で報告される
+        let input = "define main() = f(1, 2); define f(a, b = 0, c) = (a, b, c);";
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::Check(CheckError::MissingArgument(function, parameter)) => {
+                assert_eq!((function.as_str(), parameter.as_str()), ("f", "c"))
+            }
+            _ => unreachable!(),
+        }
+
+        let input = "{ define f(a, b = 0, c) = (a, b, c); f(1, 2); }";
+        let (_, expression) = block_expression(input).unwrap();
+
+        match interpreter.interpret(&expression).unwrap_err() {
+            InterpreterError::MissingArgument(function, parameter) => {
+                assert_eq!((function.as_str(), parameter.as_str()), ("f", "c"))
+            }
+            _ => unreachable!(),
+        }
+
+        let input = "{ define f(a, b = 0, c) = (a, b, c); f(1, c = 3); }";
+        let (_, expression) = block_expression(input).unwrap();
+        let value = interpreter.interpret(&expression).unwrap();
+        assert_eq!(value.to_string(), "(1, 0, 3)");
+    }
+
+    #[test]
+    fn unknown_named_argument() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define sq(x) = x * x;
+
+            define main() {
+                sq(y = 2);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::UnknownNamedArgument(function, name) => {
+                assert_eq!((function.as_str(), name.as_str()), ("sq", "y"))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn duplicate_named_argument() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define sub(x, y) = x - y;
+
+            define main() {
+                sub(1, x = 2);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::DuplicateArgument(function, name) => {
+                assert_eq!((function.as_str(), name.as_str()), ("sub", "x"))
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn max_call_depth_test() {
+        let input = "
+            define ping(n) { pong(n + 1); }
+            define pong(n) { ping(n + 1); }
+            define count(n) { if (n < 1) 0; else 1 + count(n - 1); }
+        ";
+        let mut interpreter = Interpreter::new();
+        interpreter.set_max_call_depth(10);
+        interpreter
+            .call_main(parse(&format!("{input} 0;")).unwrap())
+            .unwrap();
+
+        match interpreter.interpret(&block_expression("{ ping(0); }").unwrap().1) {
+            Err(InterpreterError::StackOverflow(depth, frames)) => {
+                assert_eq!(depth, 10);
+                assert_eq!(
+                    frames,
+                    ["pong", "ping", "pong", "ping", "pong", "ping", "pong", "ping"]
+                );
+            }
+            _ => unreachable!(),
+        }
+
+        let value = interpreter
+            .interpret(&block_expression("{ count(9); }").unwrap().1)
+            .unwrap();
+        assert_eq!(value.to_string(), "9");
+
+        let input = "
+            define count(n) { if (n < 1) 0; else 1 + count(n - 1); }
+            define main() {
+                try {
+                    count(11);
+                } catch (e) {
+                    (error_kind(e), error_message(e), count(3));
+                }
+            }
+        ";
+        let value = interpreter.call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(
+            value.to_string(),
+            r#"("StackOverflow", "Calls are nested deeper than 10, most recently in count <- count <- count <- count <- count <- count <- count <- count", 3)"#
+        );
+    }
+
+    #[test]
+    fn default_call_depth_test() {
+        // 既定の上限まで入れ子にしても、ネイティブのスタックは溢れない
+        let inputs = [
+            "define down(n) { down(n + 1); } define main() = down(0);",
+            "
+                define down(n) requires (n >= 0) { for (i in 0..1) { down(n + 1); } }
+                define main() = down(0);
+            ",
+            "
+                define down(n) { for (x in down(n + 1)) { yield x; } }
+                define main() { for (x in down(0)) { x; } }
+            ",
+            "
+                define down(n) { yield next(down(n + 1)); }
+                define main() = next(down(0));
+            ",
+            "
+                define down(n, m = down(n + 1)) { m; }
+                define main() = down(0);
+            ",
+        ];
+        for input in inputs {
+            match Interpreter::new().call_main(parse(input).unwrap()) {
+                Err(InterpreterError::StackOverflow(depth, frames)) => {
+                    assert_eq!(depth, 100_000);
+                    assert_eq!(frames, ["down"; 8]);
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn deep_recursion_test() {
+        // 呼び出しはネイティブのスタックを使わないので、テストのスレッドでも深く再帰できる
+        let input = "
+            define sum(n) { if (n < 1) 0; else n + sum(n - 1); }
+            define main() = sum(1000000);
+        ";
+        let mut interpreter = Interpreter::new();
+        interpreter.set_max_call_depth(1_000_002);
+        let value = interpreter.call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(value.to_string(), "500000500000");
+
+        // 既定値や契約の中の呼び出しも、呼び出しと同じマシンで実行される
+        let input = "
+            define check(n) { if (n < 1) 1; else valid(n - 1); }
+            define valid(n, depth = n) requires (check(depth) == 1) ensures (result == 1) { 1; }
+            define main() = valid(10000);
+        ";
+        let value = Interpreter::new().call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(value.to_string(), "1");
+    }
+
+    #[test]
+    fn control_flow_through_finally_test() {
+        let input = "
+            define find(limit, items) {
+                for (x in items) {
+                    try {
+                        if (x > limit) return x; else 0;
+                    } finally {
+                        0;
+                    }
+                }
+                0;
+            }
+
+            define main() {
+                log = 0;
+                for (i in 0..5) {
+                    try {
+                        if (i == 1) continue; else 0;
+                        if (i == 3) break; else 0;
+                        log = log * 10 + i;
+                    } finally {
+                        log = log * 10 + 9;
+                    }
+                }
+                (log, find(3, (1, 5, 2, 7)));
+            }
+        ";
+        let value = Interpreter::new().call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(value.to_string(), "(99299, 5)");
+    }
+
+    #[test]
+    fn arithmetic_mode_test() {
+        use crate::interpreter::ArithmeticMode;
+
+        let input = r#"
+            define main() {
+                big = 9223372036854775807;
+                try {
+                    big + 1;
+                } catch (e) {
+                    (error_kind(e), error_message(e));
+                }
+            }
+        "#;
+        let value = Interpreter::new().call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(
+            value.to_string(),
+            r#"("IntegerOverflow", "9223372036854775807 + 1 overflows")"#
+        );
+
+        let input = "
+            define main() {
+                big = 9223372036854775807;
+                (big + 1, 0 - big - 2, big * 2, (0 - big - 1) / (0 - 1));
+            }
+        ";
+        for (mode, expected) in [
+            (
+                ArithmeticMode::Wrapping,
+                "(-9223372036854775808, 9223372036854775807, -2, -9223372036854775808)",
+            ),
+            (
+                ArithmeticMode::Saturating,
+                "(9223372036854775807, -9223372036854775808, 9223372036854775807, 9223372036854775807)",
+            ),
+        ] {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_arithmetic_mode(mode);
+            let value = interpreter.call_main(parse(input).unwrap()).unwrap();
+            assert_eq!(value.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn big_integer_test() {
+        use crate::interpreter::ArithmeticMode;
+
+        let input = r#"
+            define factorial(n) {
+                result = 1;
+                while (n > 1) {
+                    result *= n;
+                    n -= 1;
+                }
+                result;
+            }
+
+            define main() {
+                big = 123456789012345678901234567890;
+                println(factorial(25));
+                println("${big * 8 + 7:o} ${0 - big:>32}");
+                (big / 10 % 1000, big > 9223372036854775807, factorial(21) / factorial(19));
+            }
+        "#;
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_arithmetic_mode(ArithmeticMode::BigInteger);
+        interpreter.capture_output();
+        let value = interpreter.call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(
+            interpreter.take_output(),
+            concat!(
+                "15511210043330985984000000\n",
+                "1435644177554156370167116176053227  -123456789012345678901234567890\n",
+            )
+        );
+        assert_eq!(value.to_string(), "(789, 1, 420)");
+
+        // 他のモードでは i64 の範囲を超えられない
+        match Interpreter::new()
+            .call_main(parse(input).unwrap())
+            .unwrap_err()
+        {
+            InterpreterError::IntegerOutOfRange(value) => {
+                assert_eq!(value.to_string(), "123456789012345678901234567890")
+            }
+            _ => unreachable!(),
+        }
+        let input = "define main() = 3037000500 * 3037000500;";
+        assert!(matches!(
+            Interpreter::new().call_main(parse(input).unwrap()),
+            Err(InterpreterError::IntegerOverflow(..))
+        ));
+    }
+
+    #[test]
+    fn sized_integer_test() {
+        use crate::interpreter::ArithmeticMode;
+
+        let input = r#"
+            define main() {
+                reg = 250u8;
+                reg += 5u8;
+                (
+                    reg,
+                    truncate_u8(4660),
+                    truncate_i32(4294967295),
+                    i64(reg) + 1,
+                    u32(7) * 3u32,
+                    7i32 / -2i32,
+                    "${200u8:08b}"
+                );
+            }
+        "#;
+        let value = Interpreter::new().call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(
+            value.to_string(),
+            r#"(255, 52, -1, 256, 21, -3, "11001000")"#
+        );
+
+        let input = "define main() = 255u8 + 1u8;";
+        assert!(matches!(
+            Interpreter::new().call_main(parse(input).unwrap()),
+            Err(InterpreterError::IntegerOverflow(..))
+        ));
+        for (mode, expected) in [
+            (ArithmeticMode::Wrapping, "0"),
+            (ArithmeticMode::Saturating, "255"),
+        ] {
+            let mut interpreter = Interpreter::new();
+            interpreter.set_arithmetic_mode(mode);
+            let value = interpreter.call_main(parse(input).unwrap()).unwrap();
+            assert_eq!(value.to_string(), expected);
+        }
+
+        let input = "define main() = 1u8 + 1;";
+        match Interpreter::new()
+            .call_main(parse(input).unwrap())
+            .unwrap_err()
+        {
+            InterpreterError::MixedIntegerTypes(operator, lhs, rhs) => {
+                assert_eq!(
+                    (operator.as_str(), lhs.as_str(), rhs.as_str()),
+                    ("+", "u8", "i64")
+                )
+            }
+            _ => unreachable!(),
+        }
+        let input = "define main() = u8(256);";
+        match Interpreter::new()
+            .call_main(parse(input).unwrap())
+            .unwrap_err()
+        {
+            error @ InterpreterError::ConversionOutOfRange(..) => {
+                assert_eq!(error.to_string(), "256 does not fit in u8")
+            }
+            _ => unreachable!(),
+        }
+        match parse("define main() =\n    256u8;") {
+            Err(error @ ParseError::IntegerLiteralOutOfRange(2, _, IntegerType::U8)) => {
+                assert_eq!(
+                    error.to_string(),
+                    "Integer literal 256u8 on line 2 does not fit in u8"
+                )
+            }
+            _ => unreachable!(),
+        }
+        // 型の接尾辞は語の途中では終わらない
+        assert!(matches!(
+            parse("define main() = 1u80;"),
+            Err(ParseError::UnexpectedInput(1, _))
+        ));
+    }
+
+    #[test]
+    fn catch_builtin_error() {
+        let mut interpreter = Interpreter::new();
+
+        let input = r#"
+            define main() {
+                try {
+                    1 / 0;
+                } catch (e) {
+                    (error_kind(e), error_message(e), error_kind(e) == "ZeroDivision");
+                }
+            }
+        "#;
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(
+            value.to_string(),
+            r#"("ZeroDivision", "Zero is an invalid denominator", 1)"#
+        );
+    }
+
+    #[test]
+    fn throw_and_finally_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define checked_half(n) {
+                if (n % 2 == 1) {
+                    throw (n, 1);
+                } else {
+                    n / 2;
+                }
+            }
+
+            define finally_on_return() {
+                try {
+                    return 1;
+                } finally {
+                    throw 2;
+                }
+            }
+
+            define main() {
+                total = 0;
+                cleanups = 0;
+                for (n in 7..=8) {
+                    try {
+                        total += checked_half(n);
+                    } catch (e) {
+                        total += e.0 * 100;
+                    } finally {
+                        cleanups += 1;
+                    }
+                }
+                try {
+                    finally_on_return();
+                } catch (e) {
+                    total += e * 1000;
+                }
+                (total, cleanups);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value.to_string(), "(2704, 2)");
+    }
+
+    #[test]
+    fn uncaught_exception() {
+        let mut interpreter = Interpreter::new();
+
+        let input = r#"
+            define main() {
+                try {
+                    throw error("Custom", "went wrong");
+                } finally {
+                    0;
+                }
+            }
+        "#;
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::Uncaught(value) => {
+                assert_eq!(value.to_string(), "Custom: went wrong")
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn generator_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define primes() {
+                n = 2;
+                while (1) {
+                    is_prime = 1;
+                    for (d in 2..n) {
+                        if (n % d == 0) {
+                            is_prime = 0;
+                            break;
+                        } else {
+                            0;
+                        }
+                    }
+                    if (is_prime) {
+                        yield n;
+                    } else {
+                        0;
+                    }
+                    n += 1;
+                }
+            }
+
+            define take(g, count) {
+                for (i in 0..count) {
+                    yield next(g);
+                }
+            }
+
+            define main() {
+                g = primes();
+                first = next(g);
+                sum = 0;
+                for (p in take(g, 4)) {
+                    sum = sum * 100 + p;
+                }
+                (first, sum, next(g));
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value.to_string(), "(2, 3050711, 13)");
+    }
+
+    #[test]
+    fn exhausted_generator() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            define pair(a, b) {
+                yield a;
+                yield b;
+            }
+
+            define main() {
+                sum = 0;
+                for (x in (1, 2, 3)) {
+                    sum += x;
+                }
+                g = pair(sum, sum * 2);
+                next(g) + next(g) + next(g);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::GeneratorExhausted(name) => assert_eq!(name, "pair"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn generator_in_block_test() {
+        // ブロックで定義されたジェネレータは、定義した呼び出しが続く間その変数を見る
+        let input = "
+            define take(g) = next(g);
+            define main() {
+                define g() {
+                    yield base;
+                    yield base;
+                }
+                base = 3;
+                gen = g();
+                first = next(gen);
+                base = 4;
+                (first, take(gen));
+            }
+        ";
+        let value = Interpreter::new().call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(value.to_string(), "(3, 4)");
+
+        let input = "
+            define make() {
+                base = 3;
+                define g() { yield base; }
+                g();
+            }
+            define main() = next(make());
+        ";
+        match Interpreter::new().call_main(parse(input).unwrap()) {
+            Err(InterpreterError::VariableNotPresent(name)) => assert_eq!(name, "base"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn nested_generators_test() {
+        // 入れ子のジェネレータはネイティブのスタックを使わないので、テストのスレッドでも深くできる
+        let input = "
+            define gen(n) {
+                if (n > 0) {
+                    for (x in gen(n - 1)) {
+                        yield x + 1;
+                    }
+                } else {
+                    yield 0;
+                }
+            }
+            define main() {
+                sum = 0;
+                for (x in gen(10000)) {
+                    sum += x;
+                }
+                sum;
+            }
+        ";
+        let value = Interpreter::new().call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(value.to_string(), "10000");
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_max_call_depth(1000);
+        match interpreter.call_main(parse(input).unwrap()) {
+            Err(InterpreterError::StackOverflow(depth, frames)) => {
+                assert_eq!(depth, 1000);
+                assert_eq!(frames, ["gen"; 8]);
+            }
+            _ => unreachable!(),
+        }
+
+        // 本体で定義された関数は、どこで再開されても本体の変数を見る
+        let input = "
+            define counter(start) {
+                n = start;
+                define step() { n += 1; }
+                while (n < start + 3) {
+                    yield step();
+                }
+            }
+            define deep(g, depth) {
+                if (depth > 0) deep(g, depth - 1); else next(g);
+            }
+            define main() {
+                g = counter(10);
+                first = next(g);
+                (first, deep(g, 5), deep(g, 2));
+            }
+        ";
+        let value = Interpreter::new().call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(value.to_string(), "(11, 12, 13)");
+    }
+
+    #[test]
+    fn yield_in_try_and_match_test() {
+        // match のアームや try の各節の中で中断しても、再開すればその続きから実行される
+        let input = r#"
+            define steps(items) {
+                for (item in items) {
+                    match (item) {
+                        0 => {
+                            yield "zero";
+                        }
+                        n => {
+                            try {
+                                yield 12 / n;
+                                1 / (n - 1);
+                            } catch (e) {
+                                yield error_kind(e);
+                            } finally {
+                                yield "end";
+                            }
+                        }
+                    }
+                }
+            }
+
+            define main() {
+                out = "";
+                for (step in steps((0, 1, 3))) {
+                    out = "${out} ${step}";
+                }
+                out;
+            }
+        "#;
+        let value = Interpreter::new().call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(value.to_string(), " zero 12 ZeroDivision end 4 end");
+
+        // 中断している間の例外は、ジェネレータの catch 節には捕まらない
+        let input = r#"
+            define guarded() {
+                try {
+                    yield 1;
+                    yield 2;
+                } catch (e) {
+                    yield "caught by the generator";
+                }
+            }
+
+            define main() {
+                g = guarded();
+                try {
+                    next(g);
+                    1 / 0;
+                } catch (e) {
+                    (error_kind(e), next(g));
+                }
+            }
+        "#;
+        let value = Interpreter::new().call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(value.to_string(), r#"("ZeroDivision", 2)"#);
+    }
+
+    #[test]
+    fn yield_outside_generator_is_rejected() {
+        let input = "
+            define squares(n) {
+                yield n * n;
+            }
+
+            yield 1;
+        ";
+
+        match Interpreter::new()
+            .call_main(parse(input).unwrap())
+            .unwrap_err()
+        {
+            InterpreterError::Check(CheckError::MisplacedYield) => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn macro_test() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            macro swap(a, b) {
+                tmp = a;
+                a = b;
+                b = tmp;
+            }
+
+            macro square(x) {
+                x * x;
+            }
+
+            macro sum_up(variable, count, total) {
+                for (variable in 0..count) total += variable * variable;
+            }
+
+            define main() {
+                tmp = 1;
+                other = 2;
+                swap(tmp, other);
+                sum = 0;
+                sum_up(i, 3, sum);
+                (tmp, other, square(1 + 2), sum);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value.to_string(), "(2, 1, 9, 5)");
+    }
+
+    #[test]
+    fn macro_does_not_capture_variables() {
+        let mut interpreter = Interpreter::new();
+
+        let input = "
+            macro answer() {
+                x;
+            }
+
+            define main() {
+                x = 42;
+                answer();
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match interpreter.call_main(ast_program).unwrap_err() {
+            InterpreterError::VariableNotPresent(name) => assert_eq!(name, "x#1"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn macro_errors() {
+        let input = "
+            macro twice(x) {
+                x + x;
+            }
+
+            define main() {
+                twice(1, 2);
+            }
+        ";
+        let (_, ast_program) = program(input).unwrap();
+
+        match Interpreter::new().call_main(ast_program).unwrap_err() {
+            InterpreterError::Macro(MacroError::MacroArity(name, 1, 2)) => {
+                assert_eq!(name, "twice")
+            }
+            _ => unreachable!(),
+        }
+
+        let input = "
+            macro forever(x) {
+                forever(x);
+            }
+
+            define main() {
+                forever(1);
+            }
+        ";
+        let (_, ast_program) = program(input).unwrap();
+
+        match Interpreter::new().call_main(ast_program).unwrap_err() {
+            InterpreterError::Macro(MacroError::RecursionLimit(name, _)) => {
+                assert_eq!(name, "forever")
+            }
+            _ => unreachable!(),
+        }
+
+        let input = r#"
+            macro twice(x) {
+                x;
+                x;
+            }
+
+            twice(println("hi"));
+        "#;
+
+        match Interpreter::new()
+            .call_main(parse(input).unwrap())
+            .unwrap_err()
+        {
+            InterpreterError::Macro(error @ MacroError::StatementArgument(..)) => assert_eq!(
+                error.to_string(),
+                "`println` cannot be given to macro twice, since it is a statement rather than a function"
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn printed_program_parses_back() {
+        let input = r#"
+            enum Shape {
+                Circle(r),
+                Empty,
+            }
+
+            const LIMIT = 10;
+
+            define area(shape, scale = 1, ...rest) {
+                match (shape) {
+                    Circle(r) if r > 0 => 3 * r * r * scale,
+                    _ => 0,
+                }
+            }
+
+            define main() {
+                let t = (1, "a\"b\n", (2,));
+                x = (1 - (2 - 3)) * (4 + 5) % 6;
+                (a, _) = (t.0, t.2.0);
+                for (i in 0..=LIMIT step 2) {
+                    if (i < 4) continue; else x -= i;
+                }
+                try {
+                    throw error("Kind", "message");
+                } catch (e) {
+                    println(error_kind(e), "${x:>4} \${y}");
+                    print();
+                } finally {
+                    define helper(y) = y + 1;
+                    helper(x);
+                }
+                area(Circle(2), scale = 3);
+            }
+        "#;
+
+        let printed = parse(input).unwrap().to_string();
+        let reprinted = parse(&printed).unwrap().to_string();
+
+        assert_eq!(printed, reprinted);
+        assert!(printed.contains("x = (1 - (2 - 3)) * (4 + 5) % 6;"));
+    }
+
+    #[test]
+    fn expanded_program_parses_back() {
+        let input = "
+            macro swap(a, b) {
+                tmp = a;
+                a = b;
+                b = tmp;
+            }
+
+            define main() {
+                tmp_1 = 1;
+                other = 2;
+                swap(tmp_1, other);
+                (tmp_1, other);
+            }
+        ";
+
+        let mut expanded = macros::expand(parse(input).unwrap()).unwrap();
+        macros::respell(&mut expanded);
+        let printed = expanded.to_string();
+        assert!(printed.contains("tmp_1_ = tmp_1;"));
+
+        let value = Interpreter::new().call_main(parse(&printed).unwrap());
+        assert_eq!(value.unwrap().to_string(), "(2, 1)");
+    }
+
+    #[test]
+    fn contract_test() {
+        let input = "
+            define gcd(a, b)
+                requires (a > 0)
+                requires (b > 0)
+                ensures (a % result == 0)
+                ensures (b % result == 0)
+            {
+                while (b != 0) {
+                    (a, b) = (b, a % b);
+                }
+                a;
+            }
+
+            define main() {
+                (gcd(12, 18), gcd(0, 5));
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match Interpreter::new().call_main(ast_program).unwrap_err() {
+            InterpreterError::ContractViolation(name, clause, arguments) => {
+                assert_eq!(name, "gcd");
+                assert_eq!(clause, "requires (a > 0)");
+                assert_eq!(
+                    arguments,
+                    vec![
+                        ("a".to_string(), Value::Integer(0)),
+                        ("b".to_string(), Value::Integer(5))
+                    ]
+                );
+            }
+            _ => unreachable!(),
+        }
+
+        let (_, ast_program) = program(input).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_contract_checking(false);
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value.to_string(), "(6, 5)");
+    }
+
+    #[test]
+    fn ensures_is_checked_against_result() {
+        let input = "
+            define abs(n) ensures (result >= 0) = n;
+
+            define main() {
+                abs(3) + abs(-4);
+            }
+        ";
+
+        let (_, ast_program) = program(input).unwrap();
+
+        match Interpreter::new().call_main(ast_program).unwrap_err() {
+            error @ InterpreterError::ContractViolation(..) => {
+                assert_eq!(
+                    error.to_string(),
+                    "abs(n = -4) violates `ensures (result >= 0)`"
+                )
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn script_test() {
+        let input = "#!/usr/bin/env -S toys run
+            define twice(n) = n * 2;
+
+            let count = argc();
+            total = 0;
+            for (i in 0..count) {
+                total = total + twice(i);
+            }
+            (count, total, args());
+        ";
+
+        let ast_program = parse(input).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_args(["a", "b", "c"].map(String::from));
+        let value = interpreter.call_main(ast_program).unwrap();
+
+        assert_eq!(value.to_string(), "(3, 6, (\"a\", \"b\", \"c\"))");
+
+        match parse("#!toys\nx = 1;\ndefine main( = 1;") {
+            Err(ParseError::UnexpectedInput(line, _)) => assert_eq!(line, 3),
+            _ => unreachable!(),
+        }
+        match Interpreter::new()
+            .call_main(parse("").unwrap())
+            .unwrap_err()
+        {
+            InterpreterError::MainNotPresent => {}
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn script_globals_test() {
+        // 最上位の文が代入した変数は、関数からも main からも見える
+        let input = "
+            x = 3;
+            define f() { x; }
+            define bump() { x = x + 1; }
+            for (i in 0..2) {
+                bump();
+            }
+            result = (f(), x);
+            define main() = (result, f());
+        ";
+
+        let mut interpreter = Interpreter::new();
+        let value = interpreter.call_main(parse(input).unwrap()).unwrap();
+        assert_eq!(value.to_string(), "((5, 5), 5)");
+
+        // ループの変数はループの中だけのもの
+        let input = "
+            define inner() { i; }
+            for (i in 0..2) {
+                inner();
+            }
+        ";
+        match Interpreter::new()
+            .call_main(parse(input).unwrap())
+            .unwrap_err()
+        {
+            InterpreterError::VariableNotPresent(name) => assert_eq!(name, "i"),
+            _ => unreachable!(),
+        }
+
+        let input = "
+            let limit = 10;
+            define raise() { limit = 20; }
+            raise();
+        ";
+        match Interpreter::new()
+            .call_main(parse(input).unwrap())
+            .unwrap_err()
+        {
+            InterpreterError::Check(CheckError::AssignmentToImmutable(name, declaration)) => {
+                assert_eq!(
+                    (name.as_str(), declaration.as_str()),
+                    ("limit", "`let limit` at top level")
+                )
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn exit_test() {
+        let input = "
+            try {
+                exit(3);
+            } catch (e) {
+                exit(1);
+            } finally {
+                cleaned = 1;
+            }
+            exit(2);
+        ";
+
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.run(parse(input).unwrap()).unwrap(), 3);
+
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.run(parse("1;").unwrap()).unwrap(), 0);
+
+        // 0..=255 に収まらない終了コードは、下位 8 ビットが 0 でも失敗として報告される
+        for code in ["256", "-1", "4294967296"] {
+            let input = format!("exit({});", code);
+            let mut interpreter = Interpreter::new();
+            assert_eq!(interpreter.run(parse(&input).unwrap()).unwrap(), 1);
+        }
+    }
+
+    #[test]
+    fn state_after_error_test() {
+        let mut interpreter = Interpreter::new();
+        let evaluate = |interpreter: &mut Interpreter, input: &str| {
+            let (_, expression) = block_expression(input.trim()).unwrap();
+            interpreter.interpret(&expression)
+        };
+
+        evaluate(&mut interpreter, "{ x = 1; }").unwrap();
+        let input = "
+            {
+                define f(n) {
+                    for (i in 0..3) {
+                        y = i;
+                        if (i == n) 1 / 0; else 0;
+                    }
+                }
+                f(2);
+            }
+        ";
+        assert!(matches!(
+            evaluate(&mut interpreter, input),
+            Err(InterpreterError::ZeroDivision)
+        ));
+
+        assert_eq!(
+            evaluate(&mut interpreter, "{ x; }").unwrap(),
+            Value::Integer(1)
+        );
+        for name in ["n", "i", "y"] {
+            match evaluate(&mut interpreter, &format!("{{ {}; }}", name)).unwrap_err() {
+                InterpreterError::VariableNotPresent(variable) => assert_eq!(variable, name),
+                _ => unreachable!(),
+            }
+        }
+        assert!(matches!(
+            evaluate(&mut interpreter, "{ f(0); }"),
+            Err(InterpreterError::FunctionNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn transaction_test() {
+        let mut interpreter = Interpreter::new();
+        let evaluate = |interpreter: &mut Interpreter, input: &str| {
+            let (_, expression) = block_expression(input.trim()).unwrap();
+            interpreter.interpret(&expression)
+        };
+
+        let input = "global total = 1; define main() = 0;";
+        interpreter.call_main(parse(input).unwrap()).unwrap();
+
+        let result = interpreter.transaction(|interpreter| {
+            evaluate(interpreter, "{ total = 2; x = 3; }")?;
+            interpreter.call_main(parse("define helper() = 4; 1 / 0;").unwrap())
+        });
+        assert!(matches!(result, Err(InterpreterError::ZeroDivision)));
+        assert_eq!(
+            evaluate(&mut interpreter, "{ total; }").unwrap(),
+            Value::Integer(1)
+        );
+        assert!(evaluate(&mut interpreter, "{ x; }").is_err());
+        assert!(evaluate(&mut interpreter, "{ helper(); }").is_err());
+
+        // 成功すれば変更はそのまま残る
+        let value = interpreter
+            .transaction(|interpreter| evaluate(interpreter, "{ total = 5; x = 6; total + x; }"))
+            .unwrap();
+        assert_eq!(value, Value::Integer(11));
+        assert_eq!(
+            evaluate(&mut interpreter, "{ (total, x); }")
+                .unwrap()
+                .to_string(),
+            "(5, 6)"
+        );
     }
 }
@@ -1,41 +1,77 @@
 //! Useful helper combinators.
 //! Ref. https://docs.rs/nom/7.1.0/nom/recipes/index.html
 
-use nom::{bytes::complete::tag, character::complete::multispace0, sequence::delimited, IResult};
+use nom::{
+    bytes::complete::tag,
+    character::complete::{multispace0, satisfy},
+    combinator::{map, not, opt},
+    multi::separated_list0,
+    sequence::{delimited, pair, terminated},
+    IResult,
+};
 
 /// A combinator that takes a parser `inner` and produces a parser that also consumes both leading and
 /// trailing whitespace, returning the output of `inner`.
-pub fn ws<'a, F: 'a, O, E: nom::error::ParseError<&'a str>>(
+pub fn ws<'a, F, O, E: nom::error::ParseError<&'a str>>(
     inner: F,
 ) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
 where
-    F: FnMut(&'a str) -> IResult<&'a str, O, E>,
+    F: 'a + FnMut(&'a str) -> IResult<&'a str, O, E>,
 {
     delimited(multispace0, inner, multispace0)
 }
 
 /// A combinator that takes a parser `inner` and produces a parser that also consumes both leading "(" and
 /// trailing ")", returning the output of `inner`.
-pub fn parentheses<'a, F: 'a, O, E: nom::error::ParseError<&'a str>>(
+pub fn parentheses<'a, F, O, E: nom::error::ParseError<&'a str>>(
     inner: F,
 ) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
 where
-    F: FnMut(&'a str) -> IResult<&'a str, O, E>,
+    F: 'a + FnMut(&'a str) -> IResult<&'a str, O, E>,
 {
     delimited(tag("("), ws(inner), tag(")"))
 }
 
 /// A combinator that takes a parser `inner` and produces a parser that also consumes both leading "{" and
 /// trailing "}", returning the output of `inner`.
-pub fn curly_brackets<'a, F: 'a, O, E: nom::error::ParseError<&'a str>>(
+pub fn curly_brackets<'a, F, O, E: nom::error::ParseError<&'a str>>(
     inner: F,
 ) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
 where
-    F: FnMut(&'a str) -> IResult<&'a str, O, E>,
+    F: 'a + FnMut(&'a str) -> IResult<&'a str, O, E>,
 {
     delimited(tag("{"), ws(inner), tag("}"))
 }
 
+/// A combinator that takes a parser `inner` and produces a parser for a parenthesized, comma separated
+/// list of `inner`, returning their outputs. The list needs at least one comma, as in `(a,)` or `(a, b)`,
+/// so that it can be told apart from `inner` merely wrapped in parentheses.
+pub fn tuple_of<'a, F, O: 'a, E: 'a + nom::error::ParseError<&'a str>>(
+    inner: F,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Vec<O>, E>
+where
+    F: 'a + Copy + FnMut(&'a str) -> IResult<&'a str, O, E>,
+{
+    map(
+        parentheses(pair(
+            terminated(inner, ws(tag(","))),
+            terminated(separated_list0(ws(tag(",")), inner), opt(ws(tag(",")))),
+        )),
+        |(first, rest)| std::iter::once(first).chain(rest).collect(),
+    )
+}
+
+/// A combinator that recognizes the reserved word `word` only when it is not immediately followed by
+/// an identifier character, so that e.g. `return` does not match the head of `returned`.
+pub fn keyword<'a, E: nom::error::ParseError<&'a str>>(
+    word: &'static str,
+) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, E> {
+    terminated(
+        tag(word),
+        not(satisfy(|c: char| c.is_alphanumeric() || c == '_')),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -98,4 +134,31 @@ mod tests {
 
         assert_eq!(curly_brackets(hello)(input), Ok(("", "hello")));
     }
+
+    #[test]
+    fn tuple_of_test() {
+        fn hello(input: &str) -> IResult<&str, &str> {
+            tag("hello")(input)
+        }
+
+        let mut hellos = tuple_of(hello);
+
+        assert_eq!(hellos("(hello,)"), Ok(("", vec!["hello"])));
+        assert_eq!(hellos("(hello, hello)"), Ok(("", vec!["hello", "hello"])));
+        assert_eq!(
+            hellos("( hello ,hello, )"),
+            Ok(("", vec!["hello", "hello"]))
+        );
+        assert!(hellos("(hello)").is_err());
+    }
+
+    #[test]
+    fn keyword_test() {
+        let mut keyword_return = keyword::<nom::error::Error<&str>>("return");
+
+        assert_eq!(keyword_return("return 42;"), Ok((" 42;", "return")));
+        assert_eq!(keyword_return("return(42);"), Ok(("(42);", "return")));
+        assert!(keyword_return("returned;").is_err());
+        assert!(keyword_return("return_value;").is_err());
+    }
 }
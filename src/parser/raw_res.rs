@@ -1,16 +1,30 @@
+use super::helper_combinators::keyword;
+use crate::value::IntegerType;
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{alpha1, alphanumeric1, i64},
-    combinator::recognize,
-    multi::many0,
-    sequence::pair,
+    bytes::complete::{escaped_transform, tag},
+    character::complete::{alpha1, alphanumeric1, char, digit1, none_of, one_of},
+    combinator::{map, map_res, not, opt, recognize, value},
+    multi::{fold_many1, many0},
+    sequence::{delimited, pair, preceded, terminated},
     IResult,
 };
+use num_bigint::BigInt;
 
-/// integer <- i64
-pub fn integer(input: &str) -> IResult<&str, i64> {
-    i64(input)
+/// integer <- ("+" / "-")? [0-9]+;
+/// Any number of digits is accepted, whether or not the value fits in `i64`.
+pub fn integer(input: &str) -> IResult<&str, BigInt> {
+    map_res(recognize(pair(opt(one_of("+-")), digit1)), str::parse)(input)
+}
+
+/// integer_type <- ("u8" / "u16" / "u32" / "i32") !(alphanumeric / "_");
+pub fn integer_type(input: &str) -> IResult<&str, IntegerType> {
+    alt((
+        value(IntegerType::U8, keyword("u8")),
+        value(IntegerType::U16, keyword("u16")),
+        value(IntegerType::U32, keyword("u32")),
+        value(IntegerType::I32, keyword("i32")),
+    ))(input)
 }
 
 /// identifier <- (alpha / "_")+ (
@@ -23,6 +37,62 @@ pub fn identifier(input: &str) -> IResult<&str, &str> {
     ))(input)
 }
 
+/// qualified_identifier <- identifier ("::" identifier)*;
+pub fn qualified_identifier(input: &str) -> IResult<&str, &str> {
+    recognize(pair(identifier, many0(pair(tag("::"), identifier))))(input)
+}
+
+/// string <- "\"" (("\\" ("\\" / "\"" / "n" / "t" / "$")) / !("\"" / "\\") .)* "\"";
+pub fn string(input: &str) -> IResult<&str, String> {
+    delimited(
+        char('"'),
+        map(
+            opt(escaped_transform(
+                none_of("\"\\"),
+                '\\',
+                alt((
+                    value("\\", tag("\\")),
+                    value("\"", tag("\"")),
+                    value("\n", tag("n")),
+                    value("\t", tag("t")),
+                    value("$", tag("$")),
+                )),
+            )),
+            Option::unwrap_or_default,
+        ),
+        char('"'),
+    )(input)
+}
+
+/// string_fragment <- (
+///     "\\" ("\\" / "\"" / "n" / "t" / "$") /
+///     "$" !"{" /
+///     !("\"" / "\\" / "$") .
+/// )+;
+pub fn string_fragment(input: &str) -> IResult<&str, String> {
+    fold_many1(
+        alt((
+            preceded(
+                char('\\'),
+                alt((
+                    value('\\', char('\\')),
+                    value('"', char('"')),
+                    value('\n', char('n')),
+                    value('\t', char('t')),
+                    value('$', char('$')),
+                )),
+            ),
+            terminated(char('$'), not(char('{'))),
+            none_of("\"\\$"),
+        )),
+        String::new,
+        |mut fragment, c| {
+            fragment.push(c);
+            fragment
+        },
+    )(input)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -42,7 +112,21 @@ mod test {
     }
 
     #[test]
-    fn i64_test() {
-        assert_eq!(integer("42"), Ok(("", 42)));
+    fn integer_test() {
+        assert_eq!(integer("42"), Ok(("", BigInt::from(42))));
+        assert_eq!(integer("-7;"), Ok((";", BigInt::from(-7))));
+        assert_eq!(
+            integer("18446744073709551616").map(|(_, value)| value.to_string()),
+            Ok("18446744073709551616".to_string())
+        );
+    }
+
+    #[test]
+    fn string_test() {
+        assert_eq!(string(r#""""#), Ok(("", "".to_string())));
+        assert_eq!(
+            string(r#""say \"hi\"\n" rest"#),
+            Ok((" rest", "say \"hi\"\n".to_string()))
+        );
     }
 }
@@ -0,0 +1,534 @@
+//! Projects, which are directories with a `toys.toml` manifest such as:
+//!
+//! ```toml
+//! [package]
+//! name = "app"
+//! entry = "main.toys"
+//! roots = ["src", "lib"]
+//!
+//! [dependencies]
+//! geometry = { path = "../geometry" }
+//! ```
+//!
+//! `entry` and `roots` default to `main.toys` and `["."]`. A module is looked up in every source
+//! root of its package, and those of the package `geometry` are imported as
+//! `@geometry/shapes.toys`. A package can only import its own modules and those of the packages
+//! in its `[dependencies]`.
+//!
+//! What a module defines is renamed to `@geometry/shapes::name` after its path, so that a name
+//! defined by two packages refers to two different definitions. Definitions conflict across
+//! packages when one file is loaded as two modules, since everything it defines would then be
+//! defined twice, with global variables of its own each time. That is reported, as are a package
+//! depended on under two names, two packages by the same name and a module found in more than
+//! one root.
+
+mod manifest;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::modules::ModuleLoader;
+use manifest::Value;
+use thiserror::Error;
+
+/// The name of the manifest in the directory of each package.
+pub const MANIFEST: &str = "toys.toml";
+
+#[derive(Error, Debug)]
+pub enum ProjectError {
+    #[error("Cannot read {0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("{0}:{1}: {2}")]
+    Manifest(PathBuf, usize, String),
+    #[error("{0} does not name the package")]
+    MissingName(PathBuf),
+    #[error("Package {0} is both in {1} and in {2}")]
+    DependencyConflict(String, PathBuf, PathBuf),
+    #[error("Package in {0} is depended on both as {1} and as {2}")]
+    DuplicatePackage(PathBuf, String, String),
+    #[error("{0} is loaded both as {1} and as {2}, which would define what it defines twice")]
+    DuplicateModule(PathBuf, String, String),
+    #[error("Module {0} is both in {1} and in {2}")]
+    ConflictingModule(String, PathBuf, PathBuf),
+    #[error("Package {0} does not declare {1} in its [dependencies]")]
+    UndeclaredDependency(String, String),
+}
+
+/// A package and every package it depends on, directly or not.
+pub struct Project {
+    root: Package,
+    /// The packages by name, including the root one.
+    packages: HashMap<String, Package>,
+    /// The path of the module each file has been loaded as.
+    loaded: RefCell<HashMap<PathBuf, String>>,
+}
+
+#[derive(Clone)]
+struct Package {
+    name: String,
+    dir: PathBuf,
+    entry: String,
+    roots: Vec<PathBuf>,
+    dependencies: Vec<(String, PathBuf)>,
+}
+
+impl Project {
+    /// Reads the manifest in `dir` and those of the packages it depends on.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, ProjectError> {
+        let root = Package::read(dir.as_ref())?;
+        let mut packages = HashMap::new();
+        let mut names = HashMap::new();
+        let mut pending = vec![root.clone()];
+        while let Some(package) = pending.pop() {
+            match packages.get(&package.name) {
+                Some(Package { dir, .. }) if *dir == package.dir => continue,
+                Some(Package { dir, .. }) => {
+                    return Err(ProjectError::DependencyConflict(
+                        package.name,
+                        dir.clone(),
+                        package.dir,
+                    ))
+                }
+                None => {}
+            }
+            // 同じパッケージを別名で読み込むと、その定義がすべて二重になる
+            if let Some(name) = names.insert(package.dir.clone(), package.name.clone()) {
+                return Err(ProjectError::DuplicatePackage(
+                    package.dir,
+                    name,
+                    package.name,
+                ));
+            }
+
+            for (name, dir) in &package.dependencies {
+                let mut dependency = Package::read(dir)?;
+                // 依存先は、依存元が付けた名前で参照される
+                dependency.name = name.clone();
+                pending.push(dependency);
+            }
+            packages.insert(package.name.clone(), package);
+        }
+
+        Ok(Project {
+            root,
+            packages,
+            loaded: RefCell::new(HashMap::new()),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.root.name
+    }
+
+    /// The path of the module whose `main` runs the project.
+    pub fn entry(&self) -> &str {
+        &self.root.entry
+    }
+
+    /// The package the module at `path` belongs to, and its path within the package.
+    fn locate<'p>(&self, path: &'p str) -> io::Result<(&Package, &'p str)> {
+        match path.strip_prefix('@').and_then(|path| path.split_once('/')) {
+            Some((name, path)) => match self.packages.get(name) {
+                Some(package) => Ok((package, path)),
+                None => Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("the project does not depend on a package named {}", name),
+                )),
+            },
+            None => Ok((&self.root, path)),
+        }
+    }
+}
+
+impl ModuleLoader for Project {
+    fn load(&self, path: &str) -> io::Result<String> {
+        let (package, within) = self.locate(path)?;
+        let file = fs::canonicalize(package.find(within)?)?;
+        // `../` で他のパッケージのモジュールを指すと、同じファイルが二つのモジュールになる
+        let mut loaded = self.loaded.borrow_mut();
+        match loaded.get(&file) {
+            Some(other) if other != path => {
+                return Err(io::Error::other(ProjectError::DuplicateModule(
+                    file,
+                    other.clone(),
+                    path.to_string(),
+                )))
+            }
+            Some(_) => {}
+            None => {
+                loaded.insert(file.clone(), path.to_string());
+            }
+        }
+
+        fs::read_to_string(file)
+    }
+
+    fn check_import(&self, importer: &str, path: &str) -> io::Result<()> {
+        let (importer, _) = self.locate(importer)?;
+        let (package, _) = self.locate(path)?;
+        let declared = importer
+            .dependencies
+            .iter()
+            .any(|(name, _)| *name == package.name);
+        if package.name == importer.name || declared {
+            return Ok(());
+        }
+
+        Err(io::Error::other(ProjectError::UndeclaredDependency(
+            importer.name.clone(),
+            package.name.clone(),
+        )))
+    }
+}
+
+impl Package {
+    fn read(dir: &Path) -> Result<Self, ProjectError> {
+        let dir =
+            fs::canonicalize(dir).map_err(|error| ProjectError::Io(dir.to_path_buf(), error))?;
+        let path = dir.join(MANIFEST);
+        let source =
+            fs::read_to_string(&path).map_err(|error| ProjectError::Io(path.clone(), error))?;
+        let entries = manifest::parse(&source)
+            .map_err(|error| ProjectError::Manifest(path.clone(), error.line, error.message))?;
+
+        let mut name = None;
+        let mut entry = "main.toys".to_string();
+        let mut roots = vec![dir.clone()];
+        let mut dependencies = vec![];
+        for manifest::Entry {
+            line,
+            section,
+            key,
+            value,
+        } in entries
+        {
+            let invalid = |message: &str| {
+                Err(ProjectError::Manifest(
+                    path.clone(),
+                    line,
+                    format!("`{}` {}", key, message),
+                ))
+            };
+            match (section.as_str(), key.as_str(), value) {
+                ("package", "name", Value::String(value)) => name = Some(value),
+                ("package", "entry", Value::String(value)) => entry = value,
+                ("package", "roots", Value::Array(values)) => {
+                    roots = values.iter().map(|root| dir.join(root)).collect()
+                }
+                ("package", "name" | "entry", _) => return invalid("must be a string"),
+                ("package", "roots", _) => return invalid("must be an array of strings"),
+                ("dependencies", _, Value::Table(fields)) => {
+                    match fields.into_iter().find(|(field, _)| field == "path") {
+                        Some((_, value)) => dependencies.push((key.clone(), dir.join(value))),
+                        None => return invalid("must have a `path`"),
+                    }
+                }
+                ("dependencies", _, _) => {
+                    return invalid("must be a table such as { path = \"..\" }")
+                }
+                _ => return invalid(&format!("is not a key of [{}]", section)),
+            }
+        }
+
+        Ok(Package {
+            name: name.ok_or_else(|| ProjectError::MissingName(path.clone()))?,
+            dir,
+            entry,
+            roots,
+            dependencies,
+        })
+    }
+
+    /// The file of the module at `path`, which must be in exactly one of the source roots.
+    fn find(&self, path: &str) -> io::Result<PathBuf> {
+        let mut found = self
+            .roots
+            .iter()
+            .map(|root| root.join(path))
+            .filter(|file| file.is_file());
+
+        match (found.next(), found.next()) {
+            (Some(file), None) => Ok(file),
+            (Some(first), Some(second)) => Err(io::Error::other(ProjectError::ConflictingModule(
+                path.to_string(),
+                first,
+                second,
+            ))),
+            (None, _) => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no source root of package {} has it", self.name),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::{Interpreter, InterpreterError};
+    use crate::modules::{self, ModuleError};
+    use std::env;
+    use std::process;
+
+    /// Writes `files` under a fresh directory named after `name`, and gives back its path.
+    fn create(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("toys-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for (path, contents) in files {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        dir
+    }
+
+    fn run(project: Project) -> Result<String, InterpreterError> {
+        let program = modules::load(project.entry(), &project)?;
+        let mut interpreter = Interpreter::new();
+        interpreter.set_module_loader(project);
+
+        Ok(interpreter.call_main(program)?.to_string())
+    }
+
+    #[test]
+    fn project_with_dependency() {
+        let dir = create(
+            "dependency",
+            &[
+                (
+                    "app/toys.toml",
+                    r#"
+                        [package]
+                        name = "app"
+                        entry = "app.toys"
+                        roots = ["src", "lib"]
+
+                        [dependencies]
+                        geometry = { path = "../geometry" }
+                    "#,
+                ),
+                (
+                    "app/src/app.toys",
+                    r#"
+                        import "util.toys" as util;
+                        import "@geometry/shapes.toys" as shapes;
+
+                        define area() = 100;
+
+                        define main() = (area(), util::area(), shapes::area(3));
+                    "#,
+                ),
+                (
+                    "app/lib/util.toys",
+                    "define area() = shapes::area(2);\nimport \"@geometry/shapes.toys\" as shapes;",
+                ),
+                ("geometry/toys.toml", "[package]\nname = \"geometry\"\n"),
+                ("geometry/shapes.toys", "define area(side) = side * side;"),
+            ],
+        );
+
+        let project = Project::open(dir.join("app")).unwrap();
+        assert_eq!(project.name(), "app");
+        assert_eq!(run(project).unwrap(), "(100, 4, 9)");
+    }
+
+    #[test]
+    fn conflicts() {
+        let dir = create(
+            "conflicts",
+            &[
+                (
+                    "app/toys.toml",
+                    "[package]\nname = \"app\"\nroots = [\"a\", \"b\"]\n",
+                ),
+                ("app/a/main.toys", "define main() = 1;"),
+                ("app/b/main.toys", "define main() = 2;"),
+                (
+                    "other/toys.toml",
+                    "[package]\nname = \"other\"\n[dependencies]\nother = { path = \"../app\" }\n",
+                ),
+            ],
+        );
+
+        match run(Project::open(dir.join("app")).unwrap()).unwrap_err() {
+            InterpreterError::Module(ModuleError::Load(path, error)) => {
+                assert_eq!(path, "main.toys");
+                assert!(matches!(
+                    error
+                        .into_inner()
+                        .unwrap()
+                        .downcast::<ProjectError>()
+                        .as_deref(),
+                    Ok(ProjectError::ConflictingModule(..))
+                ));
+            }
+            _ => unreachable!(),
+        }
+
+        match Project::open(dir.join("other")) {
+            Err(ProjectError::DependencyConflict(name, ..)) => assert_eq!(name, "other"),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn definition_conflicts() {
+        let dir = create(
+            "definition-conflicts",
+            &[
+                (
+                    "app/toys.toml",
+                    "[package]\nname = \"app\"\n[dependencies]\ngeometry = { path = \"../geometry\" }\n",
+                ),
+                (
+                    "app/main.toys",
+                    r#"
+                        import "@geometry/shapes.toys" as shapes;
+                        import "../geometry/shapes.toys" as copy;
+
+                        define main() = shapes::area(2) + copy::area(3);
+                    "#,
+                ),
+                ("geometry/toys.toml", "[package]\nname = \"geometry\"\n"),
+                ("geometry/shapes.toys", "define area(side) = side * side;"),
+                (
+                    "twice/toys.toml",
+                    r#"
+                        [package]
+                        name = "twice"
+
+                        [dependencies]
+                        geometry = { path = "../geometry" }
+                        units = { path = "../units" }
+                    "#,
+                ),
+                (
+                    "units/toys.toml",
+                    "[package]\nname = \"units\"\n[dependencies]\ngeo = { path = \"../geometry\" }\n",
+                ),
+            ],
+        );
+
+        match run(Project::open(dir.join("app")).unwrap()).unwrap_err() {
+            InterpreterError::Module(ModuleError::Load(path, error)) => {
+                assert_eq!(path, "../geometry/shapes.toys");
+                match error
+                    .into_inner()
+                    .unwrap()
+                    .downcast::<ProjectError>()
+                    .as_deref()
+                {
+                    Ok(ProjectError::DuplicateModule(_, first, second)) => assert_eq!(
+                        (first.as_str(), second.as_str()),
+                        ("@geometry/shapes.toys", "../geometry/shapes.toys")
+                    ),
+                    _ => unreachable!(),
+                }
+            }
+            _ => unreachable!(),
+        }
+
+        match Project::open(dir.join("twice")) {
+            Err(ProjectError::DuplicatePackage(path, first, second)) => {
+                assert_eq!(path, fs::canonicalize(dir.join("geometry")).unwrap());
+                let mut names = [first, second];
+                names.sort();
+                assert_eq!(names, ["geo", "geometry"]);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn undeclared_dependency() {
+        let dir = create(
+            "undeclared",
+            &[
+                (
+                    "app/toys.toml",
+                    r#"
+                        [package]
+                        name = "app"
+
+                        [dependencies]
+                        geometry = { path = "../geometry" }
+                        units = { path = "../units" }
+                    "#,
+                ),
+                (
+                    "app/main.toys",
+                    "import \"@geometry/shapes.toys\" as shapes;\ndefine main() = shapes::area();",
+                ),
+                ("app/config.toys", "define scale() = 2;"),
+                ("geometry/toys.toml", "[package]\nname = \"geometry\"\n"),
+                ("units/toys.toml", "[package]\nname = \"units\"\n"),
+                ("units/metric.toys", "define scale() = 3;"),
+            ],
+        );
+
+        // 依存先は、依存元も兄弟の依存先も宣言なしには取り込めない
+        for (import, package) in [("@app/config.toys", "app"), ("@units/metric.toys", "units")] {
+            let shapes = format!(
+                "import \"{}\" as other;\ndefine area() = other::scale();",
+                import
+            );
+            fs::write(dir.join("geometry/shapes.toys"), shapes).unwrap();
+
+            match run(Project::open(dir.join("app")).unwrap()).unwrap_err() {
+                InterpreterError::Module(ModuleError::ForbiddenImport(importer, path, error)) => {
+                    assert_eq!(
+                        (importer.as_str(), path.as_str()),
+                        ("@geometry/shapes.toys", import)
+                    );
+                    match error
+                        .into_inner()
+                        .unwrap()
+                        .downcast::<ProjectError>()
+                        .as_deref()
+                    {
+                        Ok(ProjectError::UndeclaredDependency(importer, name)) => {
+                            assert_eq!((importer.as_str(), name.as_str()), ("geometry", package))
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    #[test]
+    fn manifest_errors() {
+        let dir = create(
+            "manifest-errors",
+            &[
+                ("unnamed/toys.toml", "[package]\nentry = \"main.toys\"\n"),
+                (
+                    "typo/toys.toml",
+                    "[package]\nname = \"typo\"\nroot = [\"src\"]\n",
+                ),
+            ],
+        );
+
+        assert!(matches!(
+            Project::open(dir.join("unnamed")),
+            Err(ProjectError::MissingName(_))
+        ));
+        match Project::open(dir.join("typo")) {
+            Err(error @ ProjectError::Manifest(_, 3, _)) => {
+                assert!(error
+                    .to_string()
+                    .ends_with("`root` is not a key of [package]"))
+            }
+            _ => unreachable!(),
+        }
+        assert!(matches!(
+            Project::open(dir.join("missing")),
+            Err(ProjectError::Io(..))
+        ));
+    }
+}
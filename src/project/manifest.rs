@@ -0,0 +1,215 @@
+//! A reader for the subset of TOML manifests are written in: `[section]` headers, and
+//! `key = value` entries whose values are strings, arrays of strings or inline tables of strings.
+//! `#` starts a comment outside of strings.
+
+use nom::{
+    branch::alt,
+    bytes::complete::{escaped_transform, tag, take_while1},
+    character::complete::{char, none_of, not_line_ending, space0},
+    combinator::{all_consuming, map, opt, value},
+    multi::separated_list0,
+    sequence::{delimited, pair, preceded, separated_pair, terminated},
+    IResult,
+};
+use thiserror::Error;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Array(Vec<String>),
+    Table(Vec<(String, String)>),
+}
+
+/// A `key = value` entry, with the section it is in and the line it is on.
+#[derive(Debug)]
+pub struct Entry {
+    pub line: usize,
+    pub section: String,
+    pub key: String,
+    pub value: Value,
+}
+
+#[derive(Error, Debug)]
+#[error("line {line}: {message}")]
+pub struct SyntaxError {
+    pub line: usize,
+    pub message: String,
+}
+
+enum Line<'a> {
+    Header(&'a str),
+    Entry(&'a str, Value),
+    Blank,
+}
+
+pub fn parse(source: &str) -> Result<Vec<Entry>, SyntaxError> {
+    let mut entries = Vec::new();
+    let mut section = String::new();
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let (_, parsed) = all_consuming(terminated(
+            delimited(space0, alt((header, entry, blank)), space0),
+            opt(comment),
+        ))(text)
+        .map_err(|_| SyntaxError {
+            line,
+            message: format!("cannot read `{}`", text.trim()),
+        })?;
+
+        match parsed {
+            Line::Header(name) => section = name.to_string(),
+            Line::Entry(key, value) => entries.push(Entry {
+                line,
+                section: section.clone(),
+                key: key.to_string(),
+                value,
+            }),
+            Line::Blank => {}
+        }
+    }
+
+    Ok(entries)
+}
+
+/// comment <- "#" .*;
+fn comment(input: &str) -> IResult<&str, &str> {
+    preceded(char('#'), not_line_ending)(input)
+}
+
+/// blank <- "";
+fn blank(input: &str) -> IResult<&str, Line<'_>> {
+    Ok((input, Line::Blank))
+}
+
+/// header <- "[" key "]";
+fn header(input: &str) -> IResult<&str, Line<'_>> {
+    map(
+        delimited(pair(char('['), space0), key, pair(space0, char(']'))),
+        Line::Header,
+    )(input)
+}
+
+/// entry <- key "=" value;
+fn entry(input: &str) -> IResult<&str, Line<'_>> {
+    map(
+        separated_pair(key, delimited(space0, char('='), space0), value_of_entry),
+        |(key, value)| Line::Entry(key, value),
+    )(input)
+}
+
+/// key <- [A-Za-z0-9_-]+;
+fn key(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_' || c == '-')(input)
+}
+
+/// value <- string / "[" (string ("," string)*)? ","? "]" / "{" (key "=" string ("," key "=" string)*)? "}";
+fn value_of_entry(input: &str) -> IResult<&str, Value> {
+    alt((
+        map(string, Value::String),
+        map(
+            delimited(
+                pair(char('['), space0),
+                terminated(
+                    separated_list0(delimited(space0, char(','), space0), string),
+                    opt(pair(space0, char(','))),
+                ),
+                pair(space0, char(']')),
+            ),
+            Value::Array,
+        ),
+        map(
+            delimited(
+                pair(char('{'), space0),
+                separated_list0(
+                    delimited(space0, char(','), space0),
+                    separated_pair(
+                        map(key, str::to_string),
+                        delimited(space0, char('='), space0),
+                        string,
+                    ),
+                ),
+                pair(space0, char('}')),
+            ),
+            Value::Table,
+        ),
+    ))(input)
+}
+
+/// string <- "\"" (("\\" ("\\" / "\"" / "n" / "t")) / !("\"" / "\\") .)* "\"";
+fn string(input: &str) -> IResult<&str, String> {
+    delimited(
+        char('"'),
+        map(
+            opt(escaped_transform(
+                none_of("\"\\"),
+                '\\',
+                alt((
+                    value("\\", tag("\\")),
+                    value("\"", tag("\"")),
+                    value("\n", tag("n")),
+                    value("\t", tag("t")),
+                )),
+            )),
+            Option::unwrap_or_default,
+        ),
+        char('"'),
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let source = r#"
+            # the application
+            [package]
+            name = "app" # trailing comment
+            roots = ["src", "lib",]
+
+            [dependencies]
+            geometry = { path = "../geometry" }
+        "#;
+
+        let entries = parse(source).unwrap();
+        let entries: Vec<_> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.line,
+                    entry.section.as_str(),
+                    entry.key.as_str(),
+                    &entry.value,
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            entries,
+            vec![
+                (4, "package", "name", &Value::String("app".to_string())),
+                (
+                    5,
+                    "package",
+                    "roots",
+                    &Value::Array(vec!["src".to_string(), "lib".to_string()])
+                ),
+                (
+                    8,
+                    "dependencies",
+                    "geometry",
+                    &Value::Table(vec![("path".to_string(), "../geometry".to_string())])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn syntax_error() {
+        let error = parse("[package]\nname = app\n").unwrap_err();
+
+        assert_eq!(error.to_string(), "line 2: cannot read `name = app`");
+    }
+}
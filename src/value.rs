@@ -0,0 +1,228 @@
+//! Runtime values of Toys programs.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::interpreter::GeneratorState;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    /// An integer beyond the range of `i64`, made by the big integer arithmetic mode. The
+    /// integers within the range are always `Integer`s, so that each integer has one form.
+    BigInteger(BigInt),
+    /// An integer of a fixed width, made by a literal such as `255u8` or by a conversion. The
+    /// value is always within the range of its type.
+    SizedInteger(IntegerType, i64),
+    /// A value built by a constructor of a user-defined `enum`.
+    Variant {
+        name: String,
+        fields: Vec<Value>,
+    },
+    Tuple(Vec<Value>),
+    String(String),
+    /// An error raised by the interpreter or built by `error(kind, message)`, as seen by `catch`.
+    Error {
+        kind: String,
+        message: String,
+    },
+    Generator(Generator),
+}
+
+/// The types of the integers narrower than the default `i64`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntegerType {
+    U8,
+    U16,
+    U32,
+    I32,
+}
+
+impl IntegerType {
+    /// The smallest and the largest integers of the type.
+    pub fn bounds(self) -> (i64, i64) {
+        match self {
+            IntegerType::U8 => (0, u8::MAX.into()),
+            IntegerType::U16 => (0, u16::MAX.into()),
+            IntegerType::U32 => (0, u32::MAX.into()),
+            IntegerType::I32 => (i32::MIN.into(), i32::MAX.into()),
+        }
+    }
+
+    pub fn contains(self, value: i64) -> bool {
+        let (min, max) = self.bounds();
+        (min..=max).contains(&value)
+    }
+
+    /// Keeps as many low bits of `value` as the type has, in two's complement.
+    pub fn truncate(self, value: i128) -> i64 {
+        match self {
+            IntegerType::U8 => (value as u8).into(),
+            IntegerType::U16 => (value as u16).into(),
+            IntegerType::U32 => (value as u32).into(),
+            IntegerType::I32 => (value as i32).into(),
+        }
+    }
+}
+
+impl fmt::Display for IntegerType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            IntegerType::U8 => "u8",
+            IntegerType::U16 => "u16",
+            IntegerType::U32 => "u32",
+            IntegerType::I32 => "i32",
+        };
+
+        write!(f, "{}", name)
+    }
+}
+
+/// The suspended body of a call to a generator function. Copies of a generator share its state,
+/// so values taken through one copy are not seen through the others.
+#[derive(Clone)]
+pub struct Generator {
+    pub(crate) name: String,
+    pub(crate) state: Rc<RefCell<GeneratorState>>,
+}
+
+impl PartialEq for Generator {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Generator({})", self.name)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<BigInt> for Value {
+    fn from(value: BigInt) -> Self {
+        match value.to_i64() {
+            Some(value) => Value::Integer(value),
+            None => Value::BigInteger(value),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Integer(value) => write!(f, "{}", value),
+            Value::BigInteger(value) => write!(f, "{}", value),
+            Value::SizedInteger(_, value) => write!(f, "{}", value),
+            Value::Variant { name, fields } => {
+                write!(f, "{}", name)?;
+                if !fields.is_empty() {
+                    write_comma_separated(f, fields)?;
+                }
+
+                Ok(())
+            }
+            // 要素が一つのタプルは括弧付きの式と区別するためにカンマを付ける
+            Value::Tuple(elements) if elements.len() == 1 => {
+                write!(f, "(")?;
+                write_element(f, &elements[0])?;
+                write!(f, ",)")
+            }
+            Value::Tuple(elements) => write_comma_separated(f, elements),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Error { kind, message } => write!(f, "{}: {}", kind, message),
+            Value::Generator(generator) => write!(f, "<generator {}>", generator.name),
+        }
+    }
+}
+
+/// Writes `values` as `(v1, v2, ...)`.
+fn write_comma_separated(f: &mut fmt::Formatter, values: &[Value]) -> fmt::Result {
+    write!(f, "(")?;
+    for (index, value) in values.iter().enumerate() {
+        if index > 0 {
+            write!(f, ", ")?;
+        }
+        write_element(f, value)?;
+    }
+    write!(f, ")")
+}
+
+/// Writes a value nested in another one, quoting strings.
+fn write_element(f: &mut fmt::Formatter, value: &Value) -> fmt::Result {
+    match value {
+        Value::String(value) => write!(f, "{:?}", value),
+        _ => write!(f, "{}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_integer() {
+        assert_eq!(Value::Integer(-42).to_string(), "-42");
+    }
+
+    #[test]
+    fn big_integer_has_one_form() {
+        let big = BigInt::from(i64::MAX) * 1000u32;
+        assert_eq!(Value::from(big.clone()), Value::BigInteger(big));
+        assert_eq!(
+            Value::from(BigInt::from(i64::MIN)),
+            Value::Integer(i64::MIN)
+        );
+        assert_eq!(
+            Value::from(BigInt::from(i64::MIN) - 1).to_string(),
+            "-9223372036854775809"
+        );
+    }
+
+    #[test]
+    fn display_variant() {
+        let circle = Value::Variant {
+            name: "Circle".to_string(),
+            fields: vec![Value::Integer(3)],
+        };
+        assert_eq!(circle.to_string(), "Circle(3)");
+
+        let rect = Value::Variant {
+            name: "Rect".to_string(),
+            fields: vec![Value::Integer(2), circle],
+        };
+        assert_eq!(rect.to_string(), "Rect(2, Circle(3))");
+
+        let empty = Value::Variant {
+            name: "Empty".to_string(),
+            fields: vec![],
+        };
+        assert_eq!(empty.to_string(), "Empty");
+    }
+
+    #[test]
+    fn display_tuple() {
+        let pair = Value::Tuple(vec![Value::Integer(1), Value::Integer(2)]);
+        assert_eq!(pair.to_string(), "(1, 2)");
+
+        let single = Value::Tuple(vec![Value::Integer(1)]);
+        assert_eq!(single.to_string(), "(1,)");
+    }
+
+    #[test]
+    fn display_string() {
+        let greeting = Value::String("hello, \"world\"".to_string());
+        assert_eq!(greeting.to_string(), "hello, \"world\"");
+
+        let pair = Value::Tuple(vec![greeting, Value::Integer(1)]);
+        assert_eq!(pair.to_string(), "(\"hello, \\\"world\\\"\", 1)");
+    }
+}